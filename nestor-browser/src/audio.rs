@@ -0,0 +1,60 @@
+use wasm_bindgen::JsValue;
+use web_sys::AudioContext;
+
+// Small head start given to the first buffer, so the next batch of
+// samples has time to arrive before the current one finishes playing
+const START_LATENCY: f64 = 0.05;
+const MAX_LATENCY: f64 = 0.2;
+
+pub struct AudioPlayer {
+    ctx: AudioContext,
+    next_start_time: f64,
+}
+
+impl AudioPlayer {
+    pub fn new() -> Result<Self, JsValue> {
+        Ok(AudioPlayer {
+            ctx: AudioContext::new()?,
+            next_start_time: 0.0,
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.ctx.sample_rate() as u32
+    }
+
+    // Browsers keep the context suspended until there is some user interaction
+    pub fn resume(&self) {
+        let _ = self.ctx.resume();
+    }
+
+    // Schedules the samples right after the previously queued ones
+    pub fn play(&mut self, samples: &[f32]) -> Result<(), JsValue> {
+        if samples.is_empty() {
+            return Ok(());
+        }
+
+        let now = self.ctx.current_time();
+
+        if self.next_start_time < now {
+            self.next_start_time = now + START_LATENCY;
+        } else if self.next_start_time > now + MAX_LATENCY {
+            // Running ahead of the sound card, drop this batch
+            return Ok(());
+        }
+
+        let buffer = self
+            .ctx
+            .create_buffer(1, samples.len() as u32, self.ctx.sample_rate())?;
+        buffer.copy_to_channel(samples, 0)?;
+
+        let source = self.ctx.create_buffer_source()?;
+        source.set_buffer(Some(&buffer));
+        source.connect_with_audio_node(&self.ctx.destination())?;
+        source.start_with_when(self.next_start_time)?;
+
+        self.next_start_time += buffer.duration();
+
+        Ok(())
+    }
+}
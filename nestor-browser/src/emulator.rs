@@ -1,5 +1,5 @@
 use gloo::events::EventListener;
-use nestor::JoypadButton;
+use nestor::{JoypadButton, NtscFilter, Overscan, PixelAspect};
 use std::borrow::Cow;
 use wasm_bindgen::{Clamped, JsCast};
 use web_sys::{HtmlCanvasElement, ImageData};
@@ -9,17 +9,31 @@ use yew::{
     Properties,
 };
 
-const WIDTH: u32 = 256;
-const HEIGHT: u32 = 224;
-
 #[derive(Properties, PartialEq, Clone)]
 pub struct EmulatorProps {
+    // RGBA, see `NES::display_frame`
     pub frame: Vec<u8>,
+    pub overscan: Overscan,
+    // Whether the backend runs the NTSC filter
+    pub filtered: bool,
+    pub pixel_aspect: PixelAspect,
     pub fps: Option<usize>,
     pub key_pressed: Callback<JoypadButton>,
     pub key_released: Callback<JoypadButton>,
 }
 
+// Both settings are chosen here, so the backend only sends the pixels
+fn frame_size(overscan: Overscan, filtered: bool) -> (u32, u32) {
+    let (width, height) = overscan.visible_size();
+    let width = if filtered {
+        NtscFilter::output_width(width)
+    } else {
+        width
+    };
+
+    (width as u32, height as u32)
+}
+
 fn joypad_from_key(key: &str) -> Option<JoypadButton> {
     match key {
         "ArrowUp" => Some(JoypadButton::UP),
@@ -76,6 +90,7 @@ where
 pub fn emulator(props: &EmulatorProps) -> Html {
     let canvas_ref = use_node_ref();
     let ctx_ref = use_mut_ref(|| None);
+    let (width, height) = frame_size(props.overscan, props.filtered);
 
     {
         let canvas_ref = canvas_ref.clone();
@@ -97,7 +112,7 @@ pub fn emulator(props: &EmulatorProps) -> Html {
                 ctx.set_image_smoothing_enabled(false);
 
                 ctx.set_fill_style_str("#000000");
-                ctx.fill_rect(0_f64, 0_f64, WIDTH as f64, HEIGHT as f64);
+                ctx.fill_rect(0_f64, 0_f64, width as f64, height as f64);
 
                 *ctx_ref.borrow_mut() = Some(ctx);
             }
@@ -109,9 +124,9 @@ pub fn emulator(props: &EmulatorProps) -> Html {
 
         use_effect_with(frame, move |frame| {
             if let Some(ctx) = ctx_ref.borrow().as_ref() {
-                if !frame.is_empty() {
+                if frame.len() == (width * height * 4) as usize {
                     let img_data =
-                        ImageData::new_with_u8_clamped_array(Clamped(frame.as_slice()), WIDTH)
+                        ImageData::new_with_u8_clamped_array(Clamped(frame.as_slice()), width)
                             .unwrap();
 
                     ctx.put_image_data(&img_data, 0.0, 0.0).unwrap();
@@ -134,9 +149,12 @@ pub fn emulator(props: &EmulatorProps) -> Html {
             key_released.emit(key);
         });
     }
+    let (display_width, display_height) = props.overscan.display_size(props.pixel_aspect);
+    let style = format!("aspect-ratio: {} / {}", display_width, display_height);
+
     html! {
     <div>
-        <canvas class="full-canvas-container" width="256" height="240" ref={canvas_ref}></canvas>
+        <canvas class="full-canvas-container" width={width.to_string()} height={height.to_string()} style={style} ref={canvas_ref}></canvas>
         if let Some(fps) = props.fps {
             <div class="fps-counter">{fps}</div>
         }
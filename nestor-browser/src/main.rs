@@ -1,4 +1,5 @@
 mod app;
+mod audio;
 mod emulator;
 mod nametables;
 mod ppu;
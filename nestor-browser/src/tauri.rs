@@ -1,14 +1,18 @@
+use crate::audio::AudioPlayer;
 use crate::emulator::Emulator;
 use crate::nametables::Nametables;
 use crate::ppu::PPU;
 
 use fps_counter::FPSCounter;
-use nestor::JoypadButton;
+use gloo::file::{callbacks::read_as_bytes, File};
+use nestor::{BuiltinPalette, JoypadButton, NtscSignal, Overscan, PixelAspect};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 use web_sys::js_sys::Uint8Array;
+use web_sys::{HtmlInputElement, HtmlSelectElement};
 use yew::{
-    function_component, html, platform::spawn_local, use_mut_ref, use_state_eq, Callback, Html,
+    function_component, html, platform::spawn_local, use_effect_with, use_mut_ref, use_state,
+    use_state_eq, Callback, Event, Html, TargetCast,
 };
 use yew_hooks::{use_async, use_interval};
 
@@ -24,6 +28,18 @@ pub struct PPUData {
     pub palettes: Vec<u8>,
 }
 
+// The Tauri backend isn't part of this repository. It has to provide:
+// - request_frame: the RGBA bytes of `NES::display_frame`, filtered and
+//   cropped with the settings below
+// - request_nametables, request_ppu: `NametablesData` and `PPUData`
+// - key_pressed, key_released { key: JoypadButton }
+// - request_audio_samples: `NES::drain_audio_samples`
+// - set_audio_sample_rate { sampleRate: u32 }
+// - set_palette { name }: one of `BuiltinPalette::name`
+// - load_palette { data }: .pal file bytes, see `Palette::from_bytes`
+// - set_video_filter { name }: one of `NtscSignal::name`, or none to turn
+//   the filter off
+// - set_overscan { overscan: Overscan }
 #[wasm_bindgen]
 extern "C" {
     #[wasm_bindgen(js_namespace = ["window", "__TAURI__", "core"], js_name = invoke)]
@@ -46,17 +62,53 @@ async fn request_frame() -> Result<Vec<u8>, ()> {
     Ok(arr.to_vec())
 }
 
+async fn request_audio_samples() -> Result<Vec<f32>, ()> {
+    request_data::<Vec<f32>>("request_audio_samples").await
+}
+
 #[function_component(EmulatorTauriWrapper)]
 pub fn emulator_tauri_wrapper() -> Html {
     let fps_counter = use_mut_ref(FPSCounter::new);
     let fps = use_state_eq(|| Option::<usize>::None);
+    let audio = use_mut_ref(|| AudioPlayer::new().ok());
+
+    {
+        let audio = audio.clone();
+
+        use_effect_with((), move |_| {
+            if let Some(audio) = audio.borrow().as_ref() {
+                #[derive(Serialize)]
+                #[serde(rename_all = "camelCase")]
+                struct Args {
+                    sample_rate: u32,
+                }
+
+                let args = Args {
+                    sample_rate: audio.sample_rate(),
+                };
+
+                spawn_local(async move {
+                    let args = serde_wasm_bindgen::to_value(&args).unwrap();
+                    invoke("set_audio_sample_rate", args).await;
+                });
+            }
+        });
+    }
 
     let state = {
         let fps = fps.clone();
+        let audio = audio.clone();
 
         use_async(async move {
             let result = request_frame().await;
             fps.set(Some(fps_counter.clone().borrow_mut().tick()));
+
+            if let Ok(samples) = request_audio_samples().await {
+                if let Some(audio) = audio.borrow_mut().as_mut() {
+                    let _ = audio.play(&samples);
+                }
+            }
+
             result
         })
     };
@@ -73,7 +125,11 @@ pub fn emulator_tauri_wrapper() -> Html {
         )
     }
 
-    let key_pressed = Callback::from(|key| {
+    let key_pressed = Callback::from(move |key| {
+        if let Some(audio) = audio.borrow().as_ref() {
+            audio.resume();
+        }
+
         spawn_local(async move {
             #[derive(Serialize)]
             struct Args {
@@ -101,11 +157,156 @@ pub fn emulator_tauri_wrapper() -> Html {
         })
     });
 
+    let palette_selected = Callback::from(|e: Event| {
+        let Some(select) = e.target_dyn_into::<HtmlSelectElement>() else {
+            return;
+        };
+
+        spawn_local(async move {
+            #[derive(Serialize)]
+            struct Args {
+                name: String,
+            }
+
+            let args = Args {
+                name: select.value(),
+            };
+
+            let args = serde_wasm_bindgen::to_value(&args).unwrap();
+            invoke("set_palette", args).await;
+        })
+    });
+
+    let filter = use_state(|| None::<NtscSignal>);
+
+    // An empty name turns the filter off
+    let filter_selected = {
+        let filter = filter.clone();
+
+        Callback::from(move |e: Event| {
+            let Some(select) = e.target_dyn_into::<HtmlSelectElement>() else {
+                return;
+            };
+
+            let signal = NtscSignal::from_name(&select.value());
+            filter.set(signal);
+
+            spawn_local(async move {
+                #[derive(Serialize)]
+                struct Args {
+                    name: Option<&'static str>,
+                }
+
+                let args = Args {
+                    name: signal.map(|signal| signal.name()),
+                };
+
+                let args = serde_wasm_bindgen::to_value(&args).unwrap();
+                invoke("set_video_filter", args).await;
+            })
+        })
+    };
+
+    let overscan = use_state(Overscan::default);
+    let pixel_aspect = use_state(PixelAspect::default);
+
+    let overscan_selected = {
+        let overscan = overscan.clone();
+
+        Callback::from(move |e: Event| {
+            let Some(select) = e.target_dyn_into::<HtmlSelectElement>() else {
+                return;
+            };
+
+            let value = match select.value().as_str() {
+                "show" => Overscan::NONE,
+                _ => Overscan::NTSC,
+            };
+            overscan.set(value);
+
+            spawn_local(async move {
+                #[derive(Serialize)]
+                struct Args {
+                    overscan: Overscan,
+                }
+
+                let args = serde_wasm_bindgen::to_value(&Args { overscan: value }).unwrap();
+                invoke("set_overscan", args).await;
+            })
+        })
+    };
+
+    let pixel_aspect_selected = {
+        let pixel_aspect = pixel_aspect.clone();
+
+        Callback::from(move |e: Event| {
+            if let Some(select) = e.target_dyn_into::<HtmlSelectElement>() {
+                pixel_aspect.set(match select.value().as_str() {
+                    "ntsc" => PixelAspect::Ntsc,
+                    _ => PixelAspect::Square,
+                });
+            }
+        })
+    };
+
+    // The pending read has to be kept alive until it completes
+    let palette_reader = use_mut_ref(|| None);
+
+    let palette_file_selected = Callback::from(move |e: Event| {
+        let Some(file) = e
+            .target_dyn_into::<HtmlInputElement>()
+            .and_then(|input| input.files())
+            .and_then(|files| files.get(0))
+        else {
+            return;
+        };
+
+        let reader = read_as_bytes(&File::from(file), |result| {
+            let Ok(data) = result else {
+                return;
+            };
+
+            spawn_local(async move {
+                #[derive(Serialize)]
+                struct Args {
+                    data: Vec<u8>,
+                }
+
+                let args = serde_wasm_bindgen::to_value(&Args { data }).unwrap();
+                invoke("load_palette", args).await;
+            })
+        });
+
+        *palette_reader.borrow_mut() = Some(reader);
+    });
+
     html! {
         <div>
             if let Some(frame) = &state.data {
-                <Emulator frame={(frame).clone()} fps={*fps} key_pressed={key_pressed} key_released={key_released}/>
+                <Emulator frame={(frame).clone()} overscan={*overscan} filtered={filter.is_some()} pixel_aspect={*pixel_aspect} fps={*fps} key_pressed={key_pressed} key_released={key_released}/>
             }
+            <div class="video-settings">
+                <select onchange={palette_selected}>
+                    { for BuiltinPalette::ALL.iter().map(|palette| html! {
+                        <option value={palette.name()}>{palette.name()}</option>
+                    }) }
+                </select>
+                <input type="file" accept=".pal" onchange={palette_file_selected}/>
+                <select onchange={filter_selected}>
+                    <option value="">{"No Filter"}</option>
+                    { for NtscSignal::ALL.iter().map(|signal| html! {
+                        <option value={signal.name()}>{signal.name()}</option>
+                    }) }
+                </select>
+                <select onchange={overscan_selected}>
+                    <option value="crop">{"Crop Overscan"}</option>
+                    <option value="show">{"Show Overscan"}</option>
+                </select>
+                <select onchange={pixel_aspect_selected}>
+                    <option value="square">{"Square Pixels"}</option>
+                    <option value="ntsc">{"8:7 Pixels"}</option>
+                </select>
+            </div>
         </div>
     }
 }
@@ -0,0 +1,105 @@
+use nestor::{JoypadButton, PlayerJoypad};
+
+// A scripted input file holds one change per line:
+//
+//     <frame> <player> <buttons...>
+//
+// From <frame> on, <player> (1 or 2) holds exactly the listed buttons
+// (A, B, SELECT, START, UP, DOWN, LEFT, RIGHT), or nothing when "-" is given.
+// Empty lines and lines starting with '#' are ignored.
+#[derive(Debug)]
+pub struct InputEvent {
+    pub frame: usize,
+    pub player: PlayerJoypad,
+    pub buttons: JoypadButton,
+}
+
+pub fn parse_script(script: &str) -> Result<Vec<InputEvent>, String> {
+    let mut events = vec![];
+
+    for (index, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let error = |message: &str| format!("Line {}: {message}", index + 1);
+        let mut fields = line.split_whitespace();
+
+        let frame = fields
+            .next()
+            .and_then(|f| f.parse::<usize>().ok())
+            .ok_or_else(|| error("expected a frame number"))?;
+
+        let player = match fields.next() {
+            Some("1") => PlayerJoypad::One,
+            Some("2") => PlayerJoypad::Two,
+            _ => return Err(error("expected player 1 or 2")),
+        };
+
+        let mut buttons = JoypadButton::empty();
+        for name in fields {
+            buttons |= match name.to_uppercase().as_str() {
+                "-" => JoypadButton::empty(),
+                "A" => JoypadButton::BUTTON_A,
+                "B" => JoypadButton::BUTTON_B,
+                "SELECT" => JoypadButton::SELECT,
+                "START" => JoypadButton::START,
+                "UP" => JoypadButton::UP,
+                "DOWN" => JoypadButton::DOWN,
+                "LEFT" => JoypadButton::LEFT,
+                "RIGHT" => JoypadButton::RIGHT,
+                _ => return Err(error(&format!("unknown button {name}"))),
+            };
+        }
+
+        events.push(InputEvent {
+            frame,
+            player,
+            buttons,
+        });
+    }
+
+    events.sort_by_key(|event| event.frame);
+
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_script() {
+        let script = "
+            # Press start, then walk right
+            60 1 START
+            10 2 a b
+            65 1 -
+            90 1 RIGHT A
+        ";
+
+        let events = parse_script(script).unwrap();
+
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[0].frame, 10);
+        assert!(matches!(events[0].player, PlayerJoypad::Two));
+        assert_eq!(
+            events[0].buttons,
+            JoypadButton::BUTTON_A | JoypadButton::BUTTON_B
+        );
+        assert_eq!(events[1].buttons, JoypadButton::START);
+        assert_eq!(events[2].buttons, JoypadButton::empty());
+        assert_eq!(
+            events[3].buttons,
+            JoypadButton::RIGHT | JoypadButton::BUTTON_A
+        );
+    }
+
+    #[test]
+    fn test_parse_script_errors() {
+        assert!(parse_script("x 1 A").is_err());
+        assert!(parse_script("10 3 A").is_err());
+        assert!(parse_script("10 1 TURBO").is_err());
+    }
+}
@@ -0,0 +1,251 @@
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Instant;
+
+use clap::{Parser, ValueEnum};
+
+use nestor::frame::Frame;
+use nestor::{
+    BuiltinPalette, CodeDataLogger, GdbServer, JoypadButton, Palette, Region, TraceFormat,
+    TraceLogger, NES, ROM,
+};
+
+mod input;
+
+#[derive(Clone, Copy, ValueEnum)]
+enum TraceStyle {
+    /// Nintendulator's format, with the PPU position and CPU cycles
+    Nintendulator,
+    /// Registers only, like nestest_no_cycle.log
+    NoCycle,
+}
+
+impl From<TraceStyle> for TraceFormat {
+    fn from(style: TraceStyle) -> Self {
+        match style {
+            TraceStyle::Nintendulator => TraceFormat::NINTENDULATOR,
+            TraceStyle::NoCycle => TraceFormat::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum RegionArg {
+    Ntsc,
+    Pal,
+    Dendy,
+}
+
+impl From<RegionArg> for Region {
+    fn from(region: RegionArg) -> Self {
+        match region {
+            RegionArg::Ntsc => Region::Ntsc,
+            RegionArg::Pal => Region::Pal,
+            RegionArg::Dendy => Region::Dendy,
+        }
+    }
+}
+
+/// Runs a ROM headlessly, for regression tests and benchmarking
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// The iNES/NES 2.0 ROM to run
+    rom: PathBuf,
+
+    /// Number of frames to emulate
+    #[arg(short, long, default_value_t = 60)]
+    frames: usize,
+
+    /// Scripted input file, see `input.rs` for the format
+    #[arg(short, long)]
+    input: Option<PathBuf>,
+
+    /// Write the last frame to this PNG file
+    #[arg(short, long)]
+    screenshot: Option<PathBuf>,
+
+    /// Print a CRC32 of the last frame
+    #[arg(long)]
+    hash: bool,
+
+    /// Write a trace of every executed instruction to this file
+    #[arg(short, long)]
+    trace: Option<PathBuf>,
+
+    /// Format of the trace lines
+    #[arg(long, value_enum, default_value_t = TraceStyle::Nintendulator)]
+    trace_format: TraceStyle,
+
+    /// First frame to trace
+    #[arg(long, default_value_t = 0)]
+    trace_from: usize,
+
+    /// Frame to stop tracing at, traces until the end when omitted
+    #[arg(long)]
+    trace_to: Option<usize>,
+
+    /// Code/data log in FCEUX's .cdl format, updated when it already exists.
+    /// Trace lines also show what it knew about each instruction.
+    #[arg(long)]
+    cdl: Option<PathBuf>,
+
+    /// Console region, detected from the ROM header when omitted
+    #[arg(long, value_enum)]
+    region: Option<RegionArg>,
+
+    /// Palette for the screenshot: nestor, composite, rgb or a .pal file
+    #[arg(long)]
+    palette: Option<String>,
+
+    /// Wait for a GDB client on this localhost port before running,
+    /// the frames are emulated once it detaches
+    #[arg(long)]
+    gdb: Option<u16>,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &Args) -> Result<(), String> {
+    let rom = ROM::from_path(&args.rom)?;
+
+    let events = match &args.input {
+        Some(path) => {
+            let script = fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+            input::parse_script(&script)?
+        }
+        None => vec![],
+    };
+
+    let trace_format = TraceFormat {
+        code_data: args.cdl.is_some(),
+        ..args.trace_format.into()
+    };
+
+    let mut trace = match &args.trace {
+        Some(path) => Some(TraceLogger::to_file(path, trace_format)?),
+        None => None,
+    };
+
+    let mut nes = NES::new();
+
+    if let Some(path) = &args.cdl {
+        let logger = if path.exists() {
+            CodeDataLogger::load(path, &rom)?
+        } else {
+            CodeDataLogger::for_rom(&rom)
+        };
+        nes.start_code_data_log(logger);
+    }
+
+    nes.insert_cartridge(rom);
+
+    if let Some(region) = args.region {
+        nes.set_region(region.into());
+    }
+
+    if let Some(name) = &args.palette {
+        let palette = match BuiltinPalette::from_name(name) {
+            Some(palette) => Palette::builtin(palette),
+            None => Palette::load(name)?,
+        };
+        nes.set_palette(palette);
+    }
+
+    if let Some(port) = args.gdb {
+        let server = GdbServer::bind(port)?;
+        eprintln!("Waiting for GDB on {}", server.local_addr()?);
+
+        let mut session = server.accept()?;
+        nes.serve_gdb(&mut session)?;
+    }
+
+    let mut events = events.into_iter().peekable();
+    let mut last_frame = None;
+    let start = Instant::now();
+
+    for frame_number in 0..args.frames {
+        while let Some(event) = events.next_if(|event| event.frame <= frame_number) {
+            nes.button_pressed(event.player.clone(), JoypadButton::all(), false);
+            nes.button_pressed(event.player, event.buttons, true);
+        }
+
+        if frame_number == args.trace_from {
+            if let Some(logger) = trace.take() {
+                nes.start_trace(logger);
+            }
+        }
+
+        if Some(frame_number) == args.trace_to {
+            trace = nes.stop_trace();
+        }
+
+        loop {
+            if let Some(frame) = nes.emulate_frame() {
+                if frame_number + 1 == args.frames {
+                    last_frame = Some(frame.clone());
+                }
+                break;
+            }
+        }
+    }
+
+    let elapsed = start.elapsed();
+    eprintln!(
+        "Ran {} frames in {:.2?} ({:.1} fps)",
+        args.frames,
+        elapsed,
+        args.frames as f64 / elapsed.as_secs_f64()
+    );
+
+    if let Some(mut logger) = nes.stop_trace().or(trace) {
+        logger.flush()?;
+    }
+
+    if let (Some(path), Some(logger)) = (&args.cdl, nes.stop_code_data_log()) {
+        logger.save(path)?;
+    }
+
+    if let Some(frame) = last_frame {
+        if args.hash {
+            println!("{:08x}", crc32fast::hash(&frame.data));
+        }
+
+        if let Some(path) = &args.screenshot {
+            write_png(path, &frame)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn write_png(path: &Path, frame: &Frame) -> Result<(), String> {
+    let file =
+        File::create(path).map_err(|e| format!("Failed to create {}: {e}", path.display()))?;
+
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(file),
+        frame.width() as u32,
+        frame.height() as u32,
+    );
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer
+        .write_image_data(&frame.data)
+        .map_err(|e| e.to_string())
+}
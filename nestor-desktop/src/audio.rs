@@ -0,0 +1,91 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, SampleFormat, SizedSample};
+
+// Anything queued past this many seconds is dropped, so latency can't
+// build up when the emulator runs ahead of the sound card
+const MAX_QUEUED_SECONDS: f32 = 0.1;
+
+pub struct Audio {
+    _stream: cpal::Stream,
+    queue: Arc<Mutex<VecDeque<f32>>>,
+    sample_rate: u32,
+}
+
+impl Audio {
+    pub fn new() -> Result<Self, String> {
+        let host = cpal::default_host();
+        let device = host
+            .default_output_device()
+            .ok_or("No audio output device available")?;
+        let supported_config = device.default_output_config().map_err(|e| e.to_string())?;
+        let config = supported_config.config();
+        let queue = Arc::new(Mutex::new(VecDeque::new()));
+
+        let stream = match supported_config.sample_format() {
+            SampleFormat::F32 => build_stream::<f32>(&device, &config, queue.clone()),
+            SampleFormat::I16 => build_stream::<i16>(&device, &config, queue.clone()),
+            SampleFormat::U16 => build_stream::<u16>(&device, &config, queue.clone()),
+            format => Err(format!("Unsupported audio sample format: {format}")),
+        }?;
+
+        stream.play().map_err(|e| e.to_string())?;
+
+        Ok(Audio {
+            _stream: stream,
+            queue,
+            sample_rate: config.sample_rate.0,
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn queue(&self, samples: &[f32]) {
+        let mut queue = self.queue.lock().unwrap();
+        let max_len = (self.sample_rate as f32 * MAX_QUEUED_SECONDS) as usize;
+
+        queue.extend(samples);
+
+        if queue.len() > max_len {
+            let excess = queue.len() - max_len;
+            queue.drain(..excess);
+        }
+    }
+}
+
+// Sound cards don't all take f32, the samples are converted to the device's format
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    queue: Arc<Mutex<VecDeque<f32>>>,
+) -> Result<cpal::Stream, String>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let channels = config.channels as usize;
+    let mut last_sample = 0.0;
+
+    device
+        .build_output_stream(
+            config,
+            move |data: &mut [T], _| {
+                let mut queue = queue.lock().unwrap();
+
+                // The emulator outputs mono, so the same sample goes to every channel.
+                // Repeating the last sample on underrun avoids clicks.
+                for frame in data.chunks_mut(channels) {
+                    if let Some(sample) = queue.pop_front() {
+                        last_sample = sample;
+                    }
+                    frame.fill(T::from_sample(last_sample));
+                }
+            },
+            |error| eprintln!("Audio stream error: {error}"),
+            None,
+        )
+        .map_err(|e| e.to_string())
+}
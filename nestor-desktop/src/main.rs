@@ -1,7 +1,7 @@
 use iced::widget::horizontal_space;
 use iced::window;
 use iced::{Element, Subscription, Task, Theme};
-use windows::{emulator, nametables, ppu};
+use windows::{debugger, emulator, events, memory, nametables, ppu, sprites};
 
 use std::collections::BTreeMap;
 use std::fmt::Debug;
@@ -9,6 +9,8 @@ use std::sync::{Arc, RwLock};
 
 use nestor::NES;
 
+#[cfg(feature = "audio")]
+mod audio;
 mod menu;
 mod windows;
 
@@ -26,6 +28,10 @@ pub enum Message {
     EmulatorMessage(window::Id, emulator::Message),
     PPUMessage(window::Id, ppu::Message),
     NametablesMessage(window::Id, nametables::Message),
+    DebuggerMessage(window::Id, debugger::Message),
+    MemoryMessage(window::Id, memory::Message),
+    SpritesMessage(window::Id, sprites::Message),
+    EventsMessage(window::Id, events::Message),
     Dummy,
 }
 
@@ -33,6 +39,10 @@ pub enum Window {
     Emulator(emulator::Emulator),
     PPU(ppu::PPUWindow),
     Nametables(nametables::NametablesWindow),
+    Debugger(debugger::DebuggerWindow),
+    Memory(memory::MemoryWindow),
+    Sprites(sprites::SpritesWindow),
+    Events(events::EventsWindow),
 }
 
 struct App {
@@ -58,6 +68,10 @@ impl App {
                 Window::Emulator(window) => window.title(),
                 Window::PPU(window) => window.title(),
                 Window::Nametables(window) => window.title(),
+                Window::Debugger(window) => window.title(),
+                Window::Memory(window) => window.title(),
+                Window::Sprites(window) => window.title(),
+                Window::Events(window) => window.title(),
             };
 
             return format!("NEStor - {}", subtitle);
@@ -73,7 +87,8 @@ impl App {
     fn update(&mut self, message: Message) -> Task<Message> {
         match message {
             Message::WindowClosed(id) => {
-                if let Some(Window::Emulator(_)) = self.windows.get(&id) {
+                if let Some(Window::Emulator(emulator)) = self.windows.get(&id) {
+                    emulator.save_battery_ram();
                     return iced::exit();
                 }
 
@@ -96,6 +111,22 @@ impl App {
                                 let window = nametables::NametablesWindow::new(self.nes.clone());
                                 return self.open_window(Window::Nametables(window));
                             }
+                            emulator::Action::OpenDebuggerWindow => {
+                                let window = debugger::DebuggerWindow::new(self.nes.clone());
+                                return self.open_window(Window::Debugger(window));
+                            }
+                            emulator::Action::OpenMemoryWindow => {
+                                let window = memory::MemoryWindow::new(self.nes.clone());
+                                return self.open_window(Window::Memory(window));
+                            }
+                            emulator::Action::OpenSpritesWindow => {
+                                let window = sprites::SpritesWindow::new(self.nes.clone());
+                                return self.open_window(Window::Sprites(window));
+                            }
+                            emulator::Action::OpenEventsWindow => {
+                                let window = events::EventsWindow::new(self.nes.clone());
+                                return self.open_window(Window::Events(window));
+                            }
                         }
                     }
                 }
@@ -114,6 +145,30 @@ impl App {
                 }
                 Task::none()
             }
+            Message::DebuggerMessage(id, message) => {
+                if let Some(Window::Debugger(debugger)) = self.windows.get_mut(&id) {
+                    if let Some(_action) = debugger.update(message) {}
+                }
+                Task::none()
+            }
+            Message::MemoryMessage(id, message) => {
+                if let Some(Window::Memory(memory)) = self.windows.get_mut(&id) {
+                    if let Some(_action) = memory.update(message) {}
+                }
+                Task::none()
+            }
+            Message::SpritesMessage(id, message) => {
+                if let Some(Window::Sprites(sprites)) = self.windows.get_mut(&id) {
+                    if let Some(_action) = sprites.update(message) {}
+                }
+                Task::none()
+            }
+            Message::EventsMessage(id, message) => {
+                if let Some(Window::Events(events)) = self.windows.get_mut(&id) {
+                    if let Some(_action) = events.update(message) {}
+                }
+                Task::none()
+            }
             Message::Dummy => Task::none(),
         }
     }
@@ -144,6 +199,34 @@ impl App {
                         .with(id_cloned)
                         .map(move |(id, m)| Message::NametablesMessage(id, m))
                 }
+                Window::Debugger(window) => {
+                    let id_cloned = id.clone();
+                    window
+                        .subscription()
+                        .with(id_cloned)
+                        .map(move |(id, m)| Message::DebuggerMessage(id, m))
+                }
+                Window::Memory(window) => {
+                    let id_cloned = id.clone();
+                    window
+                        .subscription()
+                        .with(id_cloned)
+                        .map(move |(id, m)| Message::MemoryMessage(id, m))
+                }
+                Window::Sprites(window) => {
+                    let id_cloned = id.clone();
+                    window
+                        .subscription()
+                        .with(id_cloned)
+                        .map(move |(id, m)| Message::SpritesMessage(id, m))
+                }
+                Window::Events(window) => {
+                    let id_cloned = id.clone();
+                    window
+                        .subscription()
+                        .with(id_cloned)
+                        .map(move |(id, m)| Message::EventsMessage(id, m))
+                }
             })
             .collect();
 
@@ -165,6 +248,18 @@ impl App {
                 Window::Nametables(window) => window
                     .view()
                     .map(move |m| Message::NametablesMessage(window_id, m)),
+                Window::Debugger(window) => window
+                    .view()
+                    .map(move |m| Message::DebuggerMessage(window_id, m)),
+                Window::Memory(window) => window
+                    .view()
+                    .map(move |m| Message::MemoryMessage(window_id, m)),
+                Window::Sprites(window) => window
+                    .view()
+                    .map(move |m| Message::SpritesMessage(window_id, m)),
+                Window::Events(window) => window
+                    .view()
+                    .map(move |m| Message::EventsMessage(window_id, m)),
             }
         } else {
             horizontal_space().into()
@@ -176,6 +271,10 @@ impl App {
             Window::Emulator(e) => e.settings(),
             Window::PPU(p) => p.settings(),
             Window::Nametables(n) => n.settings(),
+            Window::Debugger(d) => d.settings(),
+            Window::Memory(m) => m.settings(),
+            Window::Sprites(s) => s.settings(),
+            Window::Events(e) => e.settings(),
         };
         let (id, task) = window::open(settings);
         self.windows.insert(id, window);
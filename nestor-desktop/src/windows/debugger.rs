@@ -0,0 +1,310 @@
+use iced::widget::{button, container, row, scrollable, text, Column};
+use iced::{futures, Color, Font, Length, Subscription};
+use iced::{Element, Theme};
+
+use std::cell::RefCell;
+use std::sync::{mpsc, Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use nestor::{StopReason, TraceFormat, TraceLogger, NES};
+
+const INSTRUCTIONS_BEFORE_PC: usize = 10;
+const INSTRUCTIONS_AFTER_PC: usize = 20;
+
+const TRACE_CAPACITY: usize = 10_000;
+const TRACE_LINES_SHOWN: usize = 12;
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    NewSnapshot(Snapshot),
+    Continue,
+    Pause,
+    StepInto,
+    StepOver,
+    StepOut,
+    RunToFrame,
+    ToggleBreakpoint(u16),
+    ToggleTrace,
+}
+
+pub enum Action {}
+
+#[derive(Debug, Clone)]
+pub struct Line {
+    address: u16,
+    label: Option<String>,
+    text: String,
+    breakpoint: bool,
+    current: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+    lines: Vec<Line>,
+    registers: String,
+    status: String,
+    // Most recent trace lines, None while not tracing
+    trace: Option<Vec<String>>,
+}
+
+impl Snapshot {
+    fn new(nes: &NES) -> Self {
+        let cpu = &nes.cpu;
+        let pc = cpu.program_counter;
+
+        let lines = nes
+            .disassemble_around(pc, INSTRUCTIONS_BEFORE_PC, INSTRUCTIONS_AFTER_PC)
+            .into_iter()
+            .map(|instruction| {
+                let operand_label = instruction
+                    .target
+                    .or(instruction.operand_address)
+                    .and_then(|address| nes.label(address));
+
+                let text = match operand_label {
+                    Some(label) => format!("{:32} ; {}", instruction.to_string(), label),
+                    None => instruction.to_string(),
+                };
+
+                Line {
+                    address: instruction.address,
+                    label: nes.label(instruction.address).map(str::to_string),
+                    breakpoint: nes.debugger.has_breakpoint(instruction.address),
+                    current: instruction.address == pc,
+                    text,
+                }
+            })
+            .collect();
+
+        let registers = format!(
+            "PC:{:04X}{}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}\nCYC:{}  PPU:{:3},{:3}",
+            pc,
+            label_suffix(nes, pc),
+            cpu.register_a,
+            cpu.register_x,
+            cpu.register_y,
+            cpu.processor_status,
+            cpu.stack_pointer,
+            cpu.cycles,
+            cpu.bus.ppu.scanline,
+            cpu.bus.ppu.cycle,
+        );
+
+        let status = if nes.is_running() {
+            "Running".to_string()
+        } else {
+            match nes.debugger.stop_reason() {
+                Some(StopReason::Breakpoint(address)) => {
+                    format!(
+                        "Breakpoint at ${:04X}{}",
+                        address,
+                        label_suffix(nes, address)
+                    )
+                }
+                Some(StopReason::Watchpoint(access)) => format!(
+                    "Watchpoint: {:?} ${:04X}{} = {:02X}",
+                    access.kind,
+                    access.address,
+                    label_suffix(nes, access.address),
+                    access.value
+                ),
+                Some(StopReason::Step) => "Step".to_string(),
+                Some(StopReason::Scanline(scanline)) => format!("Scanline {}", scanline),
+                Some(StopReason::Frame) => "Frame".to_string(),
+                Some(StopReason::Halted) => "CPU halted".to_string(),
+                None => "Paused".to_string(),
+            }
+        };
+
+        let trace = nes.trace_logger().map(|logger| {
+            let lines: Vec<&String> = logger.lines().collect();
+            let skip = lines.len().saturating_sub(TRACE_LINES_SHOWN);
+
+            lines.into_iter().skip(skip).cloned().collect()
+        });
+
+        Snapshot {
+            lines,
+            registers,
+            status,
+            trace,
+        }
+    }
+}
+
+// " (label)" when the address has one
+fn label_suffix(nes: &NES, address: u16) -> String {
+    nes.label(address)
+        .map(|label| format!(" ({})", label))
+        .unwrap_or_default()
+}
+
+pub struct DebuggerWindow {
+    nes: Arc<RwLock<NES>>,
+    receiver: RefCell<Option<mpsc::Receiver<Snapshot>>>,
+    snapshot: Snapshot,
+}
+
+impl DebuggerWindow {
+    pub fn new(nes: Arc<RwLock<NES>>) -> Self {
+        let (tx, rx) = mpsc::channel::<Snapshot>();
+
+        {
+            let nes = nes.clone();
+
+            thread::spawn(move || loop {
+                thread::sleep(Duration::from_millis(100));
+
+                let nes = nes.read().unwrap();
+
+                if nes.rom.is_some() {
+                    let _ = tx.send(Snapshot::new(&nes));
+                }
+            });
+        }
+
+        Self {
+            nes,
+            receiver: RefCell::new(Some(rx)),
+            snapshot: Snapshot::default(),
+        }
+    }
+}
+
+impl DebuggerWindow {
+    pub fn title(&self) -> String {
+        "Debugger".into()
+    }
+
+    pub fn settings(&self) -> iced::window::Settings {
+        iced::window::Settings {
+            size: iced::Size::new(720.0, 640.0),
+            ..Default::default()
+        }
+    }
+
+    pub fn view(&self) -> Element<Message> {
+        let controls = row![
+            button("Continue").on_press(Message::Continue),
+            button("Pause").on_press(Message::Pause),
+            button("Step Into").on_press(Message::StepInto),
+            button("Step Over").on_press(Message::StepOver),
+            button("Step Out").on_press(Message::StepOut),
+            button("Run to Frame").on_press(Message::RunToFrame),
+            button(if self.snapshot.trace.is_some() {
+                "Stop Trace"
+            } else {
+                "Trace"
+            })
+            .on_press(Message::ToggleTrace),
+        ]
+        .spacing(8);
+
+        let lines = self.snapshot.lines.iter().flat_map(|line| {
+            let marker = match (line.breakpoint, line.current) {
+                (true, true) => "B>",
+                (true, false) => "B ",
+                (false, true) => " >",
+                (false, false) => "  ",
+            };
+
+            let breakpoint = line.breakpoint;
+            let current = line.current;
+
+            let instruction =
+                button(text(format!("{} {}", marker, line.text)).font(Font::MONOSPACE))
+                    .width(Length::Fill)
+                    .padding([1, 4])
+                    .style(move |theme: &Theme, _status| {
+                        let palette = theme.extended_palette();
+
+                        button::Style {
+                            background: current.then(|| palette.primary.weak.color.into()),
+                            text_color: if breakpoint {
+                                Color::from_rgb8(230, 80, 80)
+                            } else {
+                                palette.background.base.text
+                            },
+                            ..Default::default()
+                        }
+                    })
+                    .on_press(Message::ToggleBreakpoint(line.address))
+                    .into();
+
+            let label = line.label.as_ref().map(|label| {
+                text(format!("{}:", label))
+                    .font(Font::MONOSPACE)
+                    .color(Color::from_rgb8(90, 170, 230))
+                    .into()
+            });
+
+            label.into_iter().chain(std::iter::once(instruction))
+        });
+
+        let disassembly = scrollable(Column::with_children(lines)).height(Length::Fill);
+
+        let mut columns = Column::new()
+            .spacing(10)
+            .padding(10)
+            .push(controls)
+            .push(text(&self.snapshot.registers).font(Font::MONOSPACE))
+            .push(text(&self.snapshot.status))
+            .push(disassembly);
+
+        if let Some(trace) = &self.snapshot.trace {
+            columns = columns.push(text(trace.join("\n")).font(Font::MONOSPACE).size(12));
+        }
+
+        container(columns)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .into()
+    }
+
+    pub fn update(&mut self, message: Message) -> Option<Action> {
+        match message {
+            Message::NewSnapshot(snapshot) => self.snapshot = snapshot,
+            Message::Continue => self.nes.write().unwrap().continue_emulation(),
+            Message::Pause => self.nes.write().unwrap().pause_emulation(),
+            Message::StepInto => self.nes.write().unwrap().step_into(),
+            Message::StepOver => self.nes.write().unwrap().step_over(),
+            Message::StepOut => self.nes.write().unwrap().step_out(),
+            Message::RunToFrame => self.nes.write().unwrap().run_to_frame(),
+            Message::ToggleBreakpoint(address) => {
+                let mut nes = self.nes.write().unwrap();
+                let debugger = &mut nes.debugger;
+
+                if debugger.has_breakpoint(address) {
+                    debugger.remove_breakpoint(address);
+                } else {
+                    debugger.add_breakpoint(address);
+                }
+            }
+            Message::ToggleTrace => {
+                let mut nes = self.nes.write().unwrap();
+
+                if nes.stop_trace().is_none() {
+                    nes.start_trace(TraceLogger::ring_buffer(
+                        TRACE_CAPACITY,
+                        TraceFormat::NINTENDULATOR,
+                    ));
+                }
+            }
+        }
+
+        None
+    }
+
+    pub fn subscription(&self) -> Subscription<Message> {
+        let snapshot_streaming =
+            futures::stream::unfold(self.receiver.take(), move |mut receiver| async {
+                let snapshot = receiver.as_mut().unwrap().recv().unwrap();
+                Some((Message::NewSnapshot(snapshot), receiver))
+            });
+
+        let snapshot_handler = Subscription::run_with_id("debugger", snapshot_streaming);
+
+        Subscription::batch([snapshot_handler])
+    }
+}
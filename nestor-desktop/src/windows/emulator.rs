@@ -1,34 +1,48 @@
 use iced::keyboard::{self, key, Key};
 use iced::widget::{container, row, text, Stack};
 use iced::widget::{image, Column};
-use iced::{futures, Alignment, Pixels, Size};
+use iced::{futures, window, Alignment, Pixels, Size};
 use iced::{Element, Length, Subscription, Task};
 
 use std::cell::RefCell;
+use std::fs;
 use std::path::PathBuf;
-use std::sync::{mpsc, Arc, RwLock};
-use std::{
-    thread,
-    time::{Duration, Instant},
-};
+use std::sync::{mpsc, Arc, Mutex, RwLock};
+use std::{thread, time::Instant};
 
 use fps_counter::FPSCounter;
 
-use nestor::{JoypadButton, PlayerJoypad, NES, ROM};
+use nestor::frame::Frame;
+use nestor::{
+    BuiltinPalette, DisplayFrame, JoypadButton, NtscFilter, NtscSettings, NtscSignal, Overscan,
+    Palette, PixelAspect, PlayerJoypad, Symbols, NES, ROM,
+};
 
 use crate::menu::{menu_bar, Menu};
 
-const NES_WIDTH: u32 = 256;
-const NES_HEIGHT: u32 = 240;
+// Window pixels per NES pixel
+const SCALE: f32 = 3.0;
 
 #[derive(Debug, Clone)]
 pub enum Message {
-    NewFrame(Vec<u8>),
+    NewFrame(DisplayFrame),
     OpenRom,
     RomOpened(Option<PathBuf>),
+    LoadSymbols,
+    SymbolsOpened(Option<PathBuf>),
+    SelectPalette(BuiltinPalette),
+    LoadPalette,
+    PaletteOpened(Option<PathBuf>),
+    SelectFilter(Option<NtscSignal>),
+    SetOverscan(Overscan),
+    SetPixelAspect(PixelAspect),
     ButtonPressed(PlayerJoypad, JoypadButton, bool),
     OpenPPU,
     OpenNametables,
+    OpenDebugger,
+    OpenMemory,
+    OpenSprites,
+    OpenEvents,
     Dummy,
 }
 
@@ -36,12 +50,21 @@ pub enum Action {
     Run(Task<Message>),
     OpenPPUWindow,
     OpenNametablesWindow,
+    OpenDebuggerWindow,
+    OpenMemoryWindow,
+    OpenSpritesWindow,
+    OpenEventsWindow,
 }
 
 pub struct Emulator {
     nes: Arc<RwLock<NES>>,
-    receiver: RefCell<Option<mpsc::Receiver<Vec<u8>>>>,
-    frame_buffer: Vec<u8>,
+    receiver: RefCell<Option<mpsc::Receiver<DisplayFrame>>>,
+    rom_path: Option<PathBuf>,
+    // Shared with the emulation thread, which runs it on every frame
+    filter: Arc<Mutex<Option<NtscFilter>>>,
+    pixel_aspect: PixelAspect,
+    // Filters make frames wider, the overscan crops them
+    frame: DisplayFrame,
     is_running: bool,
     fps_counter: FPSCounter,
     fps: usize,
@@ -49,31 +72,62 @@ pub struct Emulator {
 
 impl Emulator {
     pub fn new(nes: Arc<RwLock<NES>>) -> Self {
-        let (tx, rx) = mpsc::channel::<Vec<u8>>();
+        let (tx, rx) = mpsc::channel::<DisplayFrame>();
+        let filter = Arc::new(Mutex::new(None::<NtscFilter>));
 
         {
             let nes = nes.clone();
+            let filter = filter.clone();
 
             thread::spawn(move || {
-                let wait_time = Duration::from_micros(16667);
                 let mut start = Instant::now();
+                let mut filtered = Frame::default();
+                let mut display = DisplayFrame::default();
+
+                // The stream has to live on this thread, it isn't Send on every platform
+                #[cfg(feature = "audio")]
+                let audio = match crate::audio::Audio::new() {
+                    Ok(audio) => {
+                        nes.write()
+                            .unwrap()
+                            .set_audio_sample_rate(audio.sample_rate());
+                        Some(audio)
+                    }
+                    Err(error) => {
+                        eprintln!("Failed to open the audio output: {error}");
+                        None
+                    }
+                };
 
                 loop {
                     let mut nes = nes.write().unwrap();
 
-                    if nes.is_running() {
-                        let frame = nes.emulate_frame();
+                    if nes.is_running() && nes.emulate_frame().is_some() {
+                        let filtered = match filter.lock().unwrap().as_mut() {
+                            Some(filter) => {
+                                filter.filter(nes.frame(), &mut filtered);
+                                Some(&filtered)
+                            }
+                            None => None,
+                        };
 
-                        if let Some(frame) = frame {
-                            let _ = tx.send(frame.to_rgba());
-                            let runtime = start.elapsed();
+                        nes.display_frame(filtered, &mut display);
+                        let _ = tx.send(std::mem::take(&mut display));
 
-                            if let Some(remaining) = wait_time.checked_sub(runtime) {
-                                thread::sleep(remaining);
-                            }
+                        #[cfg(feature = "audio")]
+                        if let Some(ref audio) = audio {
+                            audio.queue(&nes.drain_audio_samples());
+                        }
 
-                            start = Instant::now()
+                        // 60 Hz for NTSC, 50 Hz for PAL and Dendy
+                        let wait_time = nes.region().frame_duration();
+                        let runtime = start.elapsed();
+
+                        if let Some(remaining) = wait_time.checked_sub(runtime) {
+                            thread::sleep(remaining);
                         }
+
+                        start = Instant::now()
                     }
                 }
             });
@@ -82,7 +136,10 @@ impl Emulator {
         Emulator {
             nes,
             receiver: RefCell::new(Some(rx)),
-            frame_buffer: Vec::new(),
+            rom_path: None,
+            filter,
+            pixel_aspect: PixelAspect::default(),
+            frame: DisplayFrame::default(),
             is_running: false,
             fps_counter: FPSCounter::new(),
             fps: 0,
@@ -103,9 +160,25 @@ impl Emulator {
             }
             Message::RomOpened(result) => {
                 if let Some(path) = result {
-                    match ROM::from_path(path) {
+                    match ROM::from_path(&path) {
                         Ok(rom) => {
-                            self.nes.write().unwrap().insert_cartridge(rom);
+                            self.save_battery_ram();
+
+                            let mut nes = self.nes.write().unwrap();
+                            nes.insert_cartridge(rom);
+
+                            if let Ok(data) = fs::read(path.with_extension("sav")) {
+                                if let Err(error) = nes.load_battery_ram(&data) {
+                                    eprintln!("Failed to load the save file: {error}");
+                                }
+                            }
+
+                            nes.symbols = Symbols::load_for_rom(&path).unwrap_or_else(|error| {
+                                eprintln!("Failed to load the symbols: {error}");
+                                Symbols::new()
+                            });
+
+                            self.rom_path = Some(path);
                             self.is_running = true;
                         }
                         Err(error) => panic!("Failed on loading the rom: {error}"),
@@ -116,6 +189,53 @@ impl Emulator {
 
                 None
             }
+            Message::LoadSymbols => Some(Action::Run(Task::perform(
+                open_symbols(),
+                Message::SymbolsOpened,
+            ))),
+            Message::SymbolsOpened(result) => {
+                if let Some(path) = result {
+                    if let Err(error) = self.nes.write().unwrap().symbols.load(&path) {
+                        eprintln!("Failed to load the symbols: {error}");
+                    }
+                }
+
+                None
+            }
+            Message::SelectPalette(palette) => {
+                self.nes
+                    .write()
+                    .unwrap()
+                    .set_palette(Palette::builtin(palette));
+                None
+            }
+            Message::LoadPalette => Some(Action::Run(Task::perform(
+                open_palette(),
+                Message::PaletteOpened,
+            ))),
+            Message::PaletteOpened(result) => {
+                if let Some(path) = result {
+                    match Palette::load(&path) {
+                        Ok(palette) => self.nes.write().unwrap().set_palette(palette),
+                        Err(error) => eprintln!("Failed to load the palette: {error}"),
+                    }
+                }
+
+                None
+            }
+            Message::SelectFilter(signal) => {
+                *self.filter.lock().unwrap() =
+                    signal.map(|signal| NtscFilter::new(NtscSettings::preset(signal)));
+                None
+            }
+            Message::SetOverscan(overscan) => {
+                self.nes.write().unwrap().set_overscan(overscan);
+                Some(self.resize_window())
+            }
+            Message::SetPixelAspect(aspect) => {
+                self.pixel_aspect = aspect;
+                Some(self.resize_window())
+            }
             Message::ButtonPressed(player, button, pressed) => {
                 self.nes
                     .write()
@@ -124,20 +244,55 @@ impl Emulator {
                 None
             }
             Message::NewFrame(frame) => {
-                self.frame_buffer = frame;
+                self.frame = frame;
                 self.fps = self.fps_counter.tick();
 
                 None
             }
             Message::OpenPPU => Some(Action::OpenPPUWindow),
             Message::OpenNametables => Some(Action::OpenNametablesWindow),
+            Message::OpenDebugger => Some(Action::OpenDebuggerWindow),
+            Message::OpenMemory => Some(Action::OpenMemoryWindow),
+            Message::OpenSprites => Some(Action::OpenSpritesWindow),
+            Message::OpenEvents => Some(Action::OpenEventsWindow),
             Message::Dummy => None,
         }
     }
 
+    // Writes the cartridge's battery-backed RAM to a .sav file next to the ROM
+    pub fn save_battery_ram(&self) {
+        let Some(path) = &self.rom_path else {
+            return;
+        };
+
+        if let Some(data) = self.nes.read().unwrap().battery_ram() {
+            if let Err(error) = fs::write(path.with_extension("sav"), data) {
+                eprintln!("Failed to write the save file: {error}");
+            }
+        }
+    }
+
+    // The visible picture, scaled and with the pixel aspect applied
+    fn window_size(&self) -> Size {
+        let (width, height) = self
+            .nes
+            .read()
+            .unwrap()
+            .overscan()
+            .display_size(self.pixel_aspect);
+
+        Size::new((width * SCALE).round(), (height * SCALE).round())
+    }
+
+    // The emulator is the first window opened
+    fn resize_window(&self) -> Action {
+        let size = self.window_size();
+        Action::Run(window::get_oldest().and_then(move |id| window::resize(id, size)))
+    }
+
     pub fn settings(&self) -> iced::window::Settings {
         iced::window::Settings {
-            size: Size::new((NES_WIDTH * 3) as f32, (NES_HEIGHT * 3) as f32),
+            size: self.window_size(),
             resizable: false,
             ..Default::default()
         }
@@ -202,19 +357,50 @@ impl Emulator {
     }
 
     pub fn view(&self) -> Element<Message> {
-        let file_menu = Menu::new("File").item("Open", Message::OpenRom).build();
+        let file_menu = Menu::new("File")
+            .item("Open", Message::OpenRom)
+            .item("Load Symbols", Message::LoadSymbols)
+            .build();
+        let palette_menu = BuiltinPalette::ALL
+            .iter()
+            .fold(Menu::new("Palette"), |menu, palette| {
+                menu.item(palette.name(), Message::SelectPalette(*palette))
+            })
+            .item("Load .pal", Message::LoadPalette)
+            .build();
+        let video_menu = NtscSignal::ALL
+            .iter()
+            .fold(
+                Menu::new("Video").item("No Filter", Message::SelectFilter(None)),
+                |menu, signal| menu.item(signal.name(), Message::SelectFilter(Some(*signal))),
+            )
+            .item("Show Overscan", Message::SetOverscan(Overscan::NONE))
+            .item("Crop Overscan", Message::SetOverscan(Overscan::NTSC))
+            .item(
+                "Square Pixels",
+                Message::SetPixelAspect(PixelAspect::Square),
+            )
+            .item("8:7 Pixels", Message::SetPixelAspect(PixelAspect::Ntsc))
+            .build();
         let debugger_menu = Menu::new("Debugger")
             .item("PPU", Message::OpenPPU)
             .item("Nametables", Message::OpenNametables)
+            .item("Sprites", Message::OpenSprites)
+            .item("PPU Events", Message::OpenEvents)
+            .item("Disassembler", Message::OpenDebugger)
+            .item("Memory", Message::OpenMemory)
             .build();
 
-        let mb = menu_bar(vec![file_menu, debugger_menu]);
+        let mb = menu_bar(vec![file_menu, palette_menu, video_menu, debugger_menu]);
 
         let mut cols = Column::new().push(mb);
 
         if self.is_running {
-            let img_handle =
-                image::Handle::from_rgba(NES_WIDTH, NES_HEIGHT, self.frame_buffer.to_vec());
+            let img_handle = image::Handle::from_rgba(
+                self.frame.width,
+                self.frame.height,
+                self.frame.rgba.to_vec(),
+            );
 
             let image: Element<Message> = image(img_handle)
                 .filter_method(image::FilterMethod::Nearest)
@@ -252,3 +438,28 @@ async fn open_rom() -> Option<PathBuf> {
 
     res.map(|file| file.path().to_path_buf())
 }
+
+// ca65 debug info or FCEUX name lists, added to the ones already loaded
+async fn open_symbols() -> Option<PathBuf> {
+    let path = std::env::current_dir().unwrap();
+
+    let res = rfd::AsyncFileDialog::new()
+        .add_filter("symbols", &["dbg", "nl"])
+        .set_directory(&path)
+        .pick_file()
+        .await;
+
+    res.map(|file| file.path().to_path_buf())
+}
+
+async fn open_palette() -> Option<PathBuf> {
+    let path = std::env::current_dir().unwrap();
+
+    let res = rfd::AsyncFileDialog::new()
+        .add_filter("palette", &["pal"])
+        .set_directory(&path)
+        .pick_file()
+        .await;
+
+    res.map(|file| file.path().to_path_buf())
+}
@@ -0,0 +1,399 @@
+use iced::widget::{button, container, image, mouse_area, row, scrollable, text, Column, Row};
+use iced::{futures, Color, Font, Length, Point, Subscription};
+use iced::{Element, Theme};
+
+use std::cell::RefCell;
+use std::sync::{mpsc, Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use nestor::{PpuEvent, PpuEventKind, PpuEventLogger, Region, NES};
+
+const DOTS: usize = 341;
+// Grid pixels per dot and scanline
+const SCALE: usize = 2;
+// How far from the cursor a click still picks an event, in dots
+const PICK_DISTANCE: usize = 4;
+
+const VISIBLE_COLOR: [u8; 3] = [48, 48, 48];
+const BLANK_COLOR: [u8; 3] = [24, 24, 24];
+const SELECTED_COLOR: [u8; 3] = [255, 255, 255];
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    NewSnapshot(Snapshot),
+    CursorMoved(Point),
+    Pick,
+    Select(usize),
+    ToggleRecording,
+}
+
+pub enum Action {}
+
+#[derive(Debug, Clone)]
+struct Event {
+    event: PpuEvent,
+    // Symbol of the instruction that was running
+    label: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    events: Vec<Event>,
+    recording: bool,
+    // 262, or 312 for PAL and Dendy
+    scanlines: usize,
+}
+
+impl Default for Snapshot {
+    fn default() -> Self {
+        Snapshot {
+            events: Vec::new(),
+            recording: false,
+            scanlines: Region::Ntsc.scanlines_per_frame(),
+        }
+    }
+}
+
+impl Snapshot {
+    fn new(nes: &NES) -> Self {
+        let events = nes
+            .ppu_event_logger()
+            .map(|logger| logger.events())
+            .unwrap_or_default()
+            .iter()
+            .map(|event| Event {
+                event: *event,
+                label: nes.label(event.program_counter).map(str::to_string),
+            })
+            .collect();
+
+        Snapshot {
+            events,
+            recording: nes.ppu_event_logger().is_some(),
+            scanlines: nes.region().scanlines_per_frame(),
+        }
+    }
+}
+
+fn event_name(kind: &PpuEventKind) -> String {
+    match kind {
+        PpuEventKind::RegisterWrite { address, value } => {
+            let register = match address {
+                0x2000 => "PPUCTRL",
+                0x2001 => "PPUMASK",
+                0x2002 => "PPUSTATUS",
+                0x2003 => "OAMADDR",
+                0x2004 => "OAMDATA",
+                0x2005 => "PPUSCROLL",
+                0x2006 => "PPUADDR",
+                0x2007 => "PPUDATA",
+                _ => "OAMDMA",
+            };
+            format!("${:04X} {} = ${:02X}", address, register, value)
+        }
+        PpuEventKind::Nmi => "NMI".to_string(),
+        PpuEventKind::SpriteZeroHit => "Sprite 0 hit".to_string(),
+        PpuEventKind::Irq(sources) => format!("IRQ {:?}", sources),
+    }
+}
+
+fn event_color(kind: &PpuEventKind) -> [u8; 3] {
+    match kind {
+        PpuEventKind::RegisterWrite { address, .. } => match address {
+            0x2000 => [230, 70, 70],
+            0x2001 => [240, 150, 50],
+            0x2003 | 0x2004 => [230, 220, 60],
+            0x2005 => [90, 210, 90],
+            0x2006 => [70, 200, 220],
+            0x2007 => [80, 120, 240],
+            0x4014 => [200, 90, 220],
+            _ => [160, 160, 160],
+        },
+        PpuEventKind::Nmi => [250, 250, 250],
+        PpuEventKind::SpriteZeroHit => [250, 140, 190],
+        PpuEventKind::Irq(_) => [150, 100, 250],
+    }
+}
+
+const LEGEND: [(&str, PpuEventKind); 10] = [
+    ("$2000", register(0x2000)),
+    ("$2001", register(0x2001)),
+    ("$2003/4", register(0x2003)),
+    ("$2005", register(0x2005)),
+    ("$2006", register(0x2006)),
+    ("$2007", register(0x2007)),
+    ("$4014", register(0x4014)),
+    ("NMI", PpuEventKind::Nmi),
+    ("Sprite 0", PpuEventKind::SpriteZeroHit),
+    ("IRQ", PpuEventKind::Irq(nestor::IrqSource::empty())),
+];
+
+const fn register(address: u16) -> PpuEventKind {
+    PpuEventKind::RegisterWrite { address, value: 0 }
+}
+
+pub struct EventsWindow {
+    nes: Arc<RwLock<NES>>,
+    receiver: RefCell<Option<mpsc::Receiver<Snapshot>>>,
+    snapshot: Snapshot,
+    grid: Vec<u8>,
+    // Dot and scanline under the cursor
+    cursor: Option<(usize, usize)>,
+    selected: Option<usize>,
+}
+
+impl EventsWindow {
+    pub fn new(nes: Arc<RwLock<NES>>) -> Self {
+        {
+            let mut nes = nes.write().unwrap();
+
+            if nes.ppu_event_logger().is_none() {
+                nes.start_ppu_event_log(PpuEventLogger::new());
+            }
+        }
+
+        let (tx, rx) = mpsc::channel::<Snapshot>();
+
+        {
+            let nes = nes.clone();
+
+            thread::spawn(move || loop {
+                thread::sleep(Duration::from_millis(250));
+
+                let nes = nes.read().unwrap();
+
+                if nes.rom.is_some() {
+                    let _ = tx.send(Snapshot::new(&nes));
+                }
+            });
+        }
+
+        let mut window = Self {
+            nes,
+            receiver: RefCell::new(Some(rx)),
+            snapshot: Snapshot::default(),
+            grid: Vec::new(),
+            cursor: None,
+            selected: None,
+        };
+        window.draw_grid();
+        window
+    }
+
+    fn draw_grid(&mut self) {
+        let scanlines = self.snapshot.scanlines;
+        let mut grid = vec![0; DOTS * scanlines * 4];
+
+        for scanline in 0..scanlines {
+            for dot in 0..DOTS {
+                let color = if scanline < 240 && (1..=256).contains(&dot) {
+                    VISIBLE_COLOR
+                } else {
+                    BLANK_COLOR
+                };
+                set_pixel(&mut grid, dot, scanline, color);
+            }
+        }
+
+        for (i, event) in self.snapshot.events.iter().enumerate() {
+            let color = if Some(i) == self.selected {
+                SELECTED_COLOR
+            } else {
+                event_color(&event.event.kind)
+            };
+
+            // A small cross, so single dots stay visible
+            let (dot, scanline) = (event.event.cycle, event.event.scanline);
+            set_pixel(&mut grid, dot, scanline, color);
+            set_pixel(&mut grid, dot.wrapping_sub(1), scanline, color);
+            set_pixel(&mut grid, dot + 1, scanline, color);
+            set_pixel(&mut grid, dot, scanline.wrapping_sub(1), color);
+            set_pixel(&mut grid, dot, scanline + 1, color);
+        }
+
+        self.grid = grid;
+    }
+
+    // The event closest to the cursor, if close enough
+    fn pick(&self) -> Option<usize> {
+        let (dot, scanline) = self.cursor?;
+
+        self.snapshot
+            .events
+            .iter()
+            .enumerate()
+            .map(|(i, event)| {
+                let distance =
+                    event.event.cycle.abs_diff(dot) + event.event.scanline.abs_diff(scanline);
+                (distance, i)
+            })
+            .filter(|(distance, _)| *distance <= PICK_DISTANCE)
+            .min()
+            .map(|(_, i)| i)
+    }
+}
+
+fn set_pixel(buffer: &mut [u8], dot: usize, scanline: usize, color: [u8; 3]) {
+    let scanlines = buffer.len() / (DOTS * 4);
+
+    if dot < DOTS && scanline < scanlines {
+        let base = (scanline * DOTS + dot) * 4;
+        buffer[base..base + 4].copy_from_slice(&[color[0], color[1], color[2], 255]);
+    }
+}
+
+impl EventsWindow {
+    pub fn title(&self) -> String {
+        "PPU Events".into()
+    }
+
+    pub fn settings(&self) -> iced::window::Settings {
+        iced::window::Settings {
+            size: iced::Size::new(1040.0, 740.0),
+            ..Default::default()
+        }
+    }
+
+    pub fn view(&self) -> Element<Message> {
+        let controls = row![
+            button(if self.snapshot.recording {
+                "Stop"
+            } else {
+                "Record"
+            })
+            .on_press(Message::ToggleRecording),
+            text(match self.cursor {
+                Some((dot, scanline)) => format!("Scanline {:3}, dot {:3}", scanline, dot),
+                None => String::new(),
+            })
+            .font(Font::MONOSPACE),
+        ]
+        .spacing(10);
+
+        let legend = Row::with_children(LEGEND.iter().map(|(name, kind)| {
+            let [r, g, b] = event_color(kind);
+            text(*name).color(Color::from_rgb8(r, g, b)).into()
+        }))
+        .spacing(12);
+
+        let grid = mouse_area(
+            image(image::Handle::from_rgba(
+                DOTS as u32,
+                self.snapshot.scanlines as u32,
+                self.grid.clone(),
+            ))
+            .filter_method(image::FilterMethod::Nearest)
+            .width((DOTS * SCALE) as f32)
+            .height((self.snapshot.scanlines * SCALE) as f32),
+        )
+        .on_move(Message::CursorMoved)
+        .on_press(Message::Pick);
+
+        let entries = self.snapshot.events.iter().enumerate().map(|(i, event)| {
+            let selected = self.selected == Some(i);
+
+            let line = format!(
+                "{:3},{:3} {:04X} {}",
+                event.event.scanline,
+                event.event.cycle,
+                event.event.program_counter,
+                event_name(&event.event.kind)
+            );
+
+            button(text(line).font(Font::MONOSPACE).size(12))
+                .width(Length::Fill)
+                .padding([1, 4])
+                .style(move |theme: &Theme, _status| {
+                    let palette = theme.extended_palette();
+
+                    button::Style {
+                        background: selected.then(|| palette.primary.weak.color.into()),
+                        text_color: palette.background.base.text,
+                        ..Default::default()
+                    }
+                })
+                .on_press(Message::Select(i))
+                .into()
+        });
+
+        let details = match self.selected.and_then(|i| self.snapshot.events.get(i)) {
+            Some(event) => format!(
+                "Scanline {}, dot {}: {}\nPC ${:04X}{}",
+                event.event.scanline,
+                event.event.cycle,
+                event_name(&event.event.kind),
+                event.event.program_counter,
+                event
+                    .label
+                    .as_ref()
+                    .map(|label| format!(" ({})", label))
+                    .unwrap_or_default()
+            ),
+            None => format!("{} events last frame", self.snapshot.events.len()),
+        };
+
+        let list = Column::new()
+            .spacing(10)
+            .push(text(details).font(Font::MONOSPACE))
+            .push(scrollable(Column::with_children(entries)).height(Length::Fill));
+
+        let columns = Column::new()
+            .spacing(10)
+            .padding(10)
+            .push(controls)
+            .push(legend)
+            .push(row![grid, list].spacing(10));
+
+        container(columns)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .into()
+    }
+
+    pub fn update(&mut self, message: Message) -> Option<Action> {
+        match message {
+            Message::NewSnapshot(snapshot) => {
+                if self.selected >= Some(snapshot.events.len()) {
+                    self.selected = None;
+                }
+                self.snapshot = snapshot;
+            }
+            Message::CursorMoved(point) => {
+                let dot = point.x as usize / SCALE;
+                let scanline = point.y as usize / SCALE;
+
+                self.cursor =
+                    (dot < DOTS && scanline < self.snapshot.scanlines).then_some((dot, scanline));
+                return None;
+            }
+            Message::Pick => self.selected = self.pick(),
+            Message::Select(i) => self.selected = Some(i),
+            Message::ToggleRecording => {
+                let mut nes = self.nes.write().unwrap();
+
+                if nes.stop_ppu_event_log().is_none() {
+                    nes.start_ppu_event_log(PpuEventLogger::new());
+                }
+
+                self.snapshot.recording = nes.ppu_event_logger().is_some();
+            }
+        }
+
+        self.draw_grid();
+
+        None
+    }
+
+    pub fn subscription(&self) -> Subscription<Message> {
+        let snapshot_streaming =
+            futures::stream::unfold(self.receiver.take(), move |mut receiver| async {
+                let snapshot = receiver.as_mut().unwrap().recv().unwrap();
+                Some((Message::NewSnapshot(snapshot), receiver))
+            });
+
+        let snapshot_handler = Subscription::run_with_id("ppu_events", snapshot_streaming);
+
+        Subscription::batch([snapshot_handler])
+    }
+}
@@ -0,0 +1,293 @@
+use iced::widget::{button, container, row, text, text_input, Column, Row};
+use iced::{futures, Color, Font, Length, Subscription};
+use iced::{Element, Theme};
+
+use std::cell::RefCell;
+use std::sync::{mpsc, Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use nestor::{MemoryRegion, NES};
+
+const BYTES_PER_ROW: usize = 16;
+const PAGE_SIZE: usize = BYTES_PER_ROW * 16;
+
+const REGIONS: [(&str, MemoryRegion); 4] = [
+    ("CPU", MemoryRegion::Cpu),
+    ("PPU", MemoryRegion::Ppu),
+    ("OAM", MemoryRegion::Oam),
+    ("PRG-RAM", MemoryRegion::PrgRam),
+];
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    Refresh,
+    SelectRegion(MemoryRegion),
+    PreviousPage,
+    NextPage,
+    AddressChanged(String),
+    GoToAddress,
+    SelectByte(usize),
+    ValueChanged(String),
+    WriteValue,
+}
+
+pub enum Action {}
+
+pub struct MemoryWindow {
+    nes: Arc<RwLock<NES>>,
+    receiver: RefCell<Option<mpsc::Receiver<()>>>,
+    region: MemoryRegion,
+    region_size: usize,
+    page_start: usize,
+    bytes: Vec<u8>,
+    // Bytes that changed since the previous refresh
+    changed: Vec<bool>,
+    selected: Option<usize>,
+    address_input: String,
+    value_input: String,
+    paused: bool,
+    // Symbol for the selected byte, CPU addresses only
+    selected_label: Option<String>,
+}
+
+impl MemoryWindow {
+    pub fn new(nes: Arc<RwLock<NES>>) -> Self {
+        let (tx, rx) = mpsc::channel::<()>();
+
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_millis(250));
+
+            if tx.send(()).is_err() {
+                break;
+            }
+        });
+
+        let mut window = Self {
+            nes,
+            receiver: RefCell::new(Some(rx)),
+            region: MemoryRegion::Cpu,
+            region_size: 0,
+            page_start: 0,
+            bytes: Vec::new(),
+            changed: Vec::new(),
+            selected: None,
+            address_input: String::new(),
+            value_input: String::new(),
+            paused: false,
+            selected_label: None,
+        };
+        window.refresh();
+        window
+    }
+
+    fn refresh(&mut self) {
+        let nes = self.nes.read().unwrap();
+
+        self.region_size = nes.memory_region_size(self.region);
+        self.paused = !nes.is_running();
+        self.selected_label = self.label(&nes);
+
+        let end = (self.page_start + PAGE_SIZE).min(self.region_size);
+        let bytes: Vec<u8> = (self.page_start..end)
+            .map(|address| nes.peek_region(self.region, address))
+            .collect();
+
+        self.changed = if bytes.len() == self.bytes.len() {
+            bytes.iter().zip(&self.bytes).map(|(a, b)| a != b).collect()
+        } else {
+            vec![false; bytes.len()]
+        };
+        self.bytes = bytes;
+    }
+
+    fn label(&self, nes: &NES) -> Option<String> {
+        match (self.region, self.selected) {
+            (MemoryRegion::Cpu, Some(address)) => nes.label(address as u16).map(str::to_string),
+            _ => None,
+        }
+    }
+
+    // Shows the page containing `address`, forgetting the previous contents
+    fn go_to(&mut self, address: usize) {
+        let last_page = self.region_size.saturating_sub(1) / PAGE_SIZE * PAGE_SIZE;
+        self.page_start = (address / PAGE_SIZE * PAGE_SIZE).min(last_page);
+        self.bytes.clear();
+        self.refresh();
+    }
+}
+
+impl MemoryWindow {
+    pub fn title(&self) -> String {
+        "Memory".into()
+    }
+
+    pub fn settings(&self) -> iced::window::Settings {
+        iced::window::Settings {
+            size: iced::Size::new(720.0, 600.0),
+            ..Default::default()
+        }
+    }
+
+    pub fn view(&self) -> Element<Message> {
+        let regions = Row::with_children(REGIONS.iter().map(|(label, region)| {
+            let style = if *region == self.region {
+                button::primary
+            } else {
+                button::secondary
+            };
+
+            button(*label)
+                .style(style)
+                .on_press(Message::SelectRegion(*region))
+                .into()
+        }))
+        .spacing(8);
+
+        let navigation = row![
+            button("<").on_press(Message::PreviousPage),
+            text_input("Address", &self.address_input)
+                .on_input(Message::AddressChanged)
+                .on_submit(Message::GoToAddress)
+                .width(100),
+            button("Go").on_press(Message::GoToAddress),
+            button(">").on_press(Message::NextPage),
+            text(format!("Size: ${:X}", self.region_size)),
+        ]
+        .spacing(8)
+        .align_y(iced::Alignment::Center);
+
+        let rows = self
+            .bytes
+            .chunks(BYTES_PER_ROW)
+            .enumerate()
+            .map(|(row_index, chunk)| {
+                let row_start = self.page_start + row_index * BYTES_PER_ROW;
+
+                let address = text(format!("{:04X}", row_start))
+                    .font(Font::MONOSPACE)
+                    .width(60);
+
+                let bytes = chunk.iter().enumerate().map(|(i, byte)| {
+                    let offset = row_index * BYTES_PER_ROW + i;
+                    let changed = self.changed[offset];
+                    let selected = self.selected == Some(self.page_start + offset);
+
+                    button(text(format!("{:02X}", byte)).font(Font::MONOSPACE))
+                        .padding([1, 4])
+                        .style(move |theme: &Theme, _status| {
+                            let palette = theme.extended_palette();
+
+                            button::Style {
+                                background: selected.then(|| palette.primary.weak.color.into()),
+                                text_color: if changed {
+                                    Color::from_rgb8(240, 200, 60)
+                                } else {
+                                    palette.background.base.text
+                                },
+                                ..Default::default()
+                            }
+                        })
+                        .on_press(Message::SelectByte(self.page_start + offset))
+                        .into()
+                });
+
+                Row::new()
+                    .push(address)
+                    .push(Row::with_children(bytes))
+                    .align_y(iced::Alignment::Center)
+                    .into()
+            });
+
+        let selected_address = |address: usize| match &self.selected_label {
+            Some(label) => format!("${:04X} ({})", address, label),
+            None => format!("${:04X}", address),
+        };
+
+        let editor: Element<Message> = match self.selected {
+            Some(address) if self.paused => row![
+                text(format!("{} =", selected_address(address))).font(Font::MONOSPACE),
+                text_input("Value", &self.value_input)
+                    .on_input(Message::ValueChanged)
+                    .on_submit(Message::WriteValue)
+                    .width(60),
+                button("Write").on_press(Message::WriteValue),
+            ]
+            .spacing(8)
+            .align_y(iced::Alignment::Center)
+            .into(),
+            Some(address) => text(format!(
+                "{}: pause the emulation to edit memory",
+                selected_address(address)
+            ))
+            .into(),
+            None => text("Select a byte to edit it").into(),
+        };
+
+        let columns = Column::new()
+            .spacing(10)
+            .padding(10)
+            .push(regions)
+            .push(navigation)
+            .push(Column::with_children(rows))
+            .push(editor);
+
+        container(columns)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .into()
+    }
+
+    pub fn update(&mut self, message: Message) -> Option<Action> {
+        match message {
+            Message::Refresh => self.refresh(),
+            Message::SelectRegion(region) => {
+                self.region = region;
+                self.selected = None;
+                self.region_size = self.nes.read().unwrap().memory_region_size(region);
+                self.go_to(0);
+            }
+            Message::PreviousPage => self.go_to(self.page_start.saturating_sub(PAGE_SIZE)),
+            Message::NextPage => self.go_to(self.page_start + PAGE_SIZE),
+            Message::AddressChanged(input) => self.address_input = input,
+            Message::GoToAddress => {
+                if let Ok(address) = usize::from_str_radix(self.address_input.trim(), 16) {
+                    self.go_to(address);
+                }
+            }
+            Message::SelectByte(address) => {
+                self.selected = Some(address);
+                self.value_input = format!("{:02X}", self.bytes[address - self.page_start]);
+                self.selected_label = self.label(&self.nes.read().unwrap());
+            }
+            Message::ValueChanged(input) => self.value_input = input,
+            Message::WriteValue => {
+                if let (Some(address), Ok(value)) = (
+                    self.selected,
+                    u8::from_str_radix(self.value_input.trim(), 16),
+                ) {
+                    let mut nes = self.nes.write().unwrap();
+
+                    if !nes.is_running() {
+                        nes.poke_region(self.region, address, value);
+                    }
+                }
+                self.refresh();
+            }
+        }
+
+        None
+    }
+
+    pub fn subscription(&self) -> Subscription<Message> {
+        let refresh_streaming =
+            futures::stream::unfold(self.receiver.take(), move |mut receiver| async {
+                receiver.as_mut().unwrap().recv().unwrap();
+                Some((Message::Refresh, receiver))
+            });
+
+        let refresh_handler = Subscription::run_with_id("memory", refresh_streaming);
+
+        Subscription::batch([refresh_handler])
+    }
+}
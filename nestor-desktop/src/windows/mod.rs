@@ -1,3 +1,7 @@
+pub mod debugger;
 pub mod emulator;
+pub mod events;
+pub mod memory;
 pub mod nametables;
 pub mod ppu;
+pub mod sprites;
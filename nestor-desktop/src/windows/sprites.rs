@@ -0,0 +1,259 @@
+use iced::widget::{button, container, image, row, scrollable, text, Column};
+use iced::{futures, Color, Font, Length, Subscription};
+use iced::{Element, Theme};
+
+use std::cell::RefCell;
+use std::sync::{mpsc, Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use nestor::{Sprite, NES};
+
+const SCREEN_WIDTH: usize = 256;
+const SCREEN_HEIGHT: usize = 240;
+
+const BOX_COLOR: [u8; 3] = [80, 220, 80];
+const SELECTED_BOX_COLOR: [u8; 3] = [255, 60, 60];
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    NewSnapshot(Snapshot),
+    Select(usize),
+}
+
+pub enum Action {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+    // 64x128 RGBA, see `NES::sprite_viewer`
+    tiles: Vec<u8>,
+    // The game screen, RGBA
+    screen: Vec<u8>,
+    sprites: Vec<Sprite>,
+    sprite_height: usize,
+}
+
+impl Snapshot {
+    fn new(nes: &NES) -> Self {
+        Snapshot {
+            tiles: nes.sprite_viewer().to_rgba(),
+            screen: nes.cpu.bus.ppu.frame.to_rgba(),
+            sprites: nes.sprites(),
+            sprite_height: nes.sprite_height(),
+        }
+    }
+}
+
+pub struct SpritesWindow {
+    receiver: RefCell<Option<mpsc::Receiver<Snapshot>>>,
+    snapshot: Snapshot,
+    // The screen with every sprite's bounding box drawn over it
+    overlay: Vec<u8>,
+    selected: Option<usize>,
+}
+
+impl SpritesWindow {
+    pub fn new(nes: Arc<RwLock<NES>>) -> Self {
+        let (tx, rx) = mpsc::channel::<Snapshot>();
+
+        {
+            let nes = nes.clone();
+
+            thread::spawn(move || loop {
+                thread::sleep(Duration::from_millis(250));
+
+                let nes = nes.read().unwrap();
+
+                if nes.rom.is_some() {
+                    let _ = tx.send(Snapshot::new(&nes));
+                }
+            });
+        }
+
+        Self {
+            receiver: RefCell::new(Some(rx)),
+            snapshot: Snapshot::default(),
+            overlay: Vec::new(),
+            selected: None,
+        }
+    }
+
+    fn draw_overlay(&mut self) {
+        let mut overlay = self.snapshot.screen.clone();
+
+        // The selected box goes last so it stays on top
+        let order = (0..self.snapshot.sprites.len())
+            .filter(|&n| Some(n) != self.selected)
+            .chain(self.selected);
+
+        for n in order {
+            let sprite = &self.snapshot.sprites[n];
+            let color = if Some(n) == self.selected {
+                SELECTED_BOX_COLOR
+            } else {
+                BOX_COLOR
+            };
+
+            draw_box(
+                &mut overlay,
+                sprite.x as usize,
+                sprite.y as usize,
+                8,
+                self.snapshot.sprite_height,
+                color,
+            );
+        }
+
+        self.overlay = overlay;
+    }
+}
+
+// Outlines a rectangle on a 256x240 RGBA buffer, clipped to the screen
+fn draw_box(buffer: &mut [u8], x: usize, y: usize, width: usize, height: usize, color: [u8; 3]) {
+    if buffer.len() < SCREEN_WIDTH * SCREEN_HEIGHT * 4 {
+        return;
+    }
+
+    let right = x + width - 1;
+    let bottom = y + height - 1;
+
+    for py in y..=bottom.min(SCREEN_HEIGHT - 1) {
+        for px in x..=right.min(SCREEN_WIDTH - 1) {
+            if px == x || px == right || py == y || py == bottom {
+                let base = (py * SCREEN_WIDTH + px) * 4;
+                buffer[base..base + 3].copy_from_slice(&color);
+            }
+        }
+    }
+}
+
+impl SpritesWindow {
+    pub fn title(&self) -> String {
+        "Sprites".into()
+    }
+
+    pub fn settings(&self) -> iced::window::Settings {
+        iced::window::Settings {
+            size: iced::Size::new(1060.0, 540.0),
+            ..Default::default()
+        }
+    }
+
+    pub fn view(&self) -> Element<Message> {
+        let tiles: Element<Message> = image(image::Handle::from_rgba(
+            64,
+            128,
+            self.snapshot.tiles.clone(),
+        ))
+        .filter_method(image::FilterMethod::Nearest)
+        .width(192)
+        .height(384)
+        .into();
+
+        let screen: Element<Message> = image(image::Handle::from_rgba(
+            SCREEN_WIDTH as u32,
+            SCREEN_HEIGHT as u32,
+            self.overlay.clone(),
+        ))
+        .filter_method(image::FilterMethod::Nearest)
+        .width(512)
+        .height(480)
+        .into();
+
+        let header = text(" #   X   Y  Tile Attr Pal Flip Pri").font(Font::MONOSPACE);
+
+        let entries = self.snapshot.sprites.iter().enumerate().map(|(n, sprite)| {
+            let flip = match (sprite.flip_h(), sprite.flip_v()) {
+                (true, true) => "HV",
+                (true, false) => "H ",
+                (false, true) => " V",
+                (false, false) => "  ",
+            };
+            let priority = if sprite.priority() { "FG" } else { "BG" };
+
+            let line = format!(
+                "{:2} {:3} {:3}  ${:02X}  ${:02X}  {}   {}   {}",
+                n,
+                sprite.x,
+                sprite.y,
+                sprite.tile,
+                sprite.attribute,
+                sprite.palette(),
+                flip,
+                priority
+            );
+
+            let selected = self.selected == Some(n);
+
+            button(text(line).font(Font::MONOSPACE))
+                .width(Length::Fill)
+                .padding([1, 4])
+                .style(move |theme: &Theme, _status| {
+                    let palette = theme.extended_palette();
+
+                    button::Style {
+                        background: selected.then(|| palette.primary.weak.color.into()),
+                        text_color: palette.background.base.text,
+                        ..Default::default()
+                    }
+                })
+                .on_press(Message::Select(n))
+                .into()
+        });
+
+        let list = Column::new()
+            .push(header)
+            .push(scrollable(Column::with_children(entries)).height(Length::Fill));
+
+        let info = match self.selected.and_then(|n| self.snapshot.sprites.get(n)) {
+            Some(sprite) => format!(
+                "Sprite {} at {},{}, 8x{}",
+                self.selected.unwrap_or_default(),
+                sprite.x,
+                sprite.y,
+                self.snapshot.sprite_height
+            ),
+            None => format!("8x{} sprites", self.snapshot.sprite_height),
+        };
+
+        let columns = Column::new()
+            .spacing(10)
+            .padding(10)
+            .push(text(info).color(Color::from_rgb8(200, 200, 200)))
+            .push(row![tiles, screen, list].spacing(10));
+
+        container(columns)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .into()
+    }
+
+    pub fn update(&mut self, message: Message) -> Option<Action> {
+        match message {
+            Message::NewSnapshot(snapshot) => self.snapshot = snapshot,
+            Message::Select(n) => {
+                self.selected = if self.selected == Some(n) {
+                    None
+                } else {
+                    Some(n)
+                };
+            }
+        }
+
+        self.draw_overlay();
+
+        None
+    }
+
+    pub fn subscription(&self) -> Subscription<Message> {
+        let snapshot_streaming =
+            futures::stream::unfold(self.receiver.take(), move |mut receiver| async {
+                let snapshot = receiver.as_mut().unwrap().recv().unwrap();
+                Some((Message::NewSnapshot(snapshot), receiver))
+            });
+
+        let snapshot_handler = Subscription::run_with_id("sprites", snapshot_streaming);
+
+        Subscription::batch([snapshot_handler])
+    }
+}
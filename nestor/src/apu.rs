@@ -0,0 +1,497 @@
+mod dmc;
+mod envelope;
+mod filter;
+mod length_counter;
+mod noise;
+mod pulse;
+mod triangle;
+
+use serde::{Deserialize, Serialize};
+
+use crate::region::Region;
+use dmc::DMC;
+use filter::Filter;
+use noise::Noise;
+use pulse::{Pulse, PulseChannel};
+use triangle::Triangle;
+
+const APU_STATUS: u16 = 0x4015;
+const APU_FRAME_COUNTER: u16 = 0x4017;
+
+// CPU cycles of the frame counter's steps, the 4-step mode ends on the
+// fourth and the 5-step mode on the fifth
+// https://www.nesdev.org/wiki/APU_Frame_Counter
+const FRAME_COUNTER_STEPS: [u32; 5] = [7457, 14913, 22371, 29829, 37281];
+const PAL_FRAME_COUNTER_STEPS: [u32; 5] = [8313, 16627, 24939, 33253, 41565];
+
+lazy_static! {
+    // https://www.nesdev.org/wiki/APU_Mixer#Lookup_Table
+    static ref PULSE_TABLE: Vec<f32> = (0..31)
+        .map(|n| if n == 0 { 0.0 } else { 95.52 / (8128.0 / n as f32 + 100.0) })
+        .collect();
+    static ref TND_TABLE: Vec<f32> = (0..203)
+        .map(|n| if n == 0 { 0.0 } else { 163.67 / (24329.0 / n as f32 + 100.0) })
+        .collect();
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+enum FrameCounterMode {
+    FourStep,
+    FiveStep,
+}
+
+// https://www.nesdev.org/wiki/APU
+#[derive(Serialize, Deserialize)]
+pub struct APU {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: DMC,
+
+    // https://www.nesdev.org/wiki/APU_Frame_Counter
+    frame_counter_mode: FrameCounterMode,
+    frame_irq_inhibit: bool,
+    frame_irq: bool,
+    frame_cycle: u32,
+    cycles: u64,
+
+    // Sets the frame counter's steps, the noise and DMC periods and the
+    // CPU clock the output is resampled from
+    region: Region,
+
+    // Output samples, resampled from the CPU clock by averaging
+    #[serde(skip)]
+    sample_rate: Option<u32>,
+    #[serde(skip)]
+    sample_timer: f64,
+    #[serde(skip)]
+    sample_sum: f32,
+    #[serde(skip)]
+    sample_count: u32,
+    #[serde(skip)]
+    filters: Vec<Filter>,
+    #[serde(skip)]
+    samples: Vec<f32>,
+}
+
+impl APU {
+    pub fn new() -> Self {
+        APU {
+            pulse1: Pulse::new(PulseChannel::One),
+            pulse2: Pulse::new(PulseChannel::Two),
+            triangle: Triangle::default(),
+            noise: Noise::new(),
+            dmc: DMC::new(),
+            frame_counter_mode: FrameCounterMode::FourStep,
+            frame_irq_inhibit: false,
+            frame_irq: false,
+            frame_cycle: 0,
+            cycles: 0,
+            region: Region::Ntsc,
+            sample_rate: None,
+            sample_timer: 0.0,
+            sample_sum: 0.0,
+            sample_count: 0,
+            filters: vec![],
+            samples: vec![],
+        }
+    }
+
+    // Samples are only produced once a sample rate is set
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        let rate = sample_rate as f32;
+
+        self.sample_rate = Some(sample_rate);
+        self.sample_timer = 0.0;
+        self.sample_sum = 0.0;
+        self.sample_count = 0;
+        self.samples.clear();
+        self.filters = vec![
+            Filter::high_pass(rate, 90.0),
+            Filter::high_pass(rate, 440.0),
+            Filter::low_pass(rate, 14000.0),
+        ];
+    }
+
+    // Dendy uses the NTSC timings
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.noise.set_region(region);
+        self.dmc.set_region(region);
+    }
+
+    // Keeps the audio output of the APU this one is replacing
+    pub fn reattach(&mut self, previous: &mut APU) {
+        // The channels' tables aren't part of a state
+        self.set_region(self.region);
+
+        self.sample_rate = previous.sample_rate;
+        self.sample_timer = previous.sample_timer;
+        self.sample_sum = previous.sample_sum;
+        self.sample_count = previous.sample_count;
+        self.filters = std::mem::take(&mut previous.filters);
+        self.samples = std::mem::take(&mut previous.samples);
+    }
+
+    pub fn drain_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+
+    // $4015 as a read would return it, without acknowledging the frame interrupt
+    pub fn peek_status(&self) -> u8 {
+        // IF-D NT21
+        let mut status = 0;
+
+        if self.pulse1.length_counter.is_active() {
+            status |= 0x01;
+        }
+        if self.pulse2.length_counter.is_active() {
+            status |= 0x02;
+        }
+        if self.triangle.length_counter.is_active() {
+            status |= 0x04;
+        }
+        if self.noise.length_counter.is_active() {
+            status |= 0x08;
+        }
+        if self.dmc.is_active() {
+            status |= 0x10;
+        }
+        if self.frame_irq {
+            status |= 0x40;
+        }
+        if self.dmc.irq_pending {
+            status |= 0x80;
+        }
+
+        status
+    }
+
+    pub fn read_status(&mut self) -> u8 {
+        let status = self.peek_status();
+
+        // Reading the status clears the frame interrupt flag
+        self.frame_irq = false;
+
+        status
+    }
+
+    pub fn write_register(&mut self, address: u16, data: u8) {
+        match address {
+            0x4000..=0x4003 => self.pulse1.write(address & 0x03, data),
+            0x4004..=0x4007 => self.pulse2.write(address & 0x03, data),
+            0x4008..=0x400B => self.triangle.write(address & 0x03, data),
+            0x400C..=0x400F => self.noise.write(address & 0x03, data),
+            0x4010..=0x4013 => self.dmc.write(address & 0x03, data),
+            APU_STATUS => {
+                // ---D NT21
+                self.pulse1.length_counter.set_enabled(data & 0x01 != 0);
+                self.pulse2.length_counter.set_enabled(data & 0x02 != 0);
+                self.triangle.length_counter.set_enabled(data & 0x04 != 0);
+                self.noise.length_counter.set_enabled(data & 0x08 != 0);
+                self.dmc.set_enabled(data & 0x10 != 0);
+                self.dmc.irq_pending = false;
+            }
+            APU_FRAME_COUNTER => {
+                // MI-- ----
+                self.frame_counter_mode = if data & 0x80 != 0 {
+                    FrameCounterMode::FiveStep
+                } else {
+                    FrameCounterMode::FourStep
+                };
+
+                self.frame_irq_inhibit = data & 0x40 != 0;
+                if self.frame_irq_inhibit {
+                    self.frame_irq = false;
+                }
+
+                self.frame_cycle = 0;
+
+                // The 5-step mode clocks the quarter and half frame units immediately
+                if self.frame_counter_mode == FrameCounterMode::FiveStep {
+                    self.quarter_frame();
+                    self.half_frame();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn frame_irq_pending(&self) -> bool {
+        self.frame_irq
+    }
+
+    pub fn dmc_irq_pending(&self) -> bool {
+        self.dmc.irq_pending
+    }
+
+    // The address the DMC wants to fetch from CPU memory, if any.
+    // The bus is responsible for reading it, stalling the CPU and
+    // handing the byte back through `load_dmc_sample`.
+    pub fn poll_dmc_read(&self) -> Option<u16> {
+        self.dmc.read_request()
+    }
+
+    pub fn load_dmc_sample(&mut self, value: u8) {
+        self.dmc.load_sample(value);
+    }
+
+    // Advances the APU by one CPU cycle
+    pub fn tick(&mut self) {
+        self.cycles += 1;
+
+        self.triangle.tick_timer();
+        self.noise.tick_timer();
+        self.dmc.tick_timer();
+
+        // Pulse timers are clocked every APU cycle, which is every other CPU cycle
+        if self.cycles.is_multiple_of(2) {
+            self.pulse1.tick_timer();
+            self.pulse2.tick_timer();
+        }
+
+        self.tick_frame_counter();
+
+        if self.sample_rate.is_some() {
+            self.sample();
+        }
+    }
+
+    fn tick_frame_counter(&mut self) {
+        self.frame_cycle += 1;
+
+        let [first, second, third, fourth, fifth] = match self.region {
+            Region::Pal => PAL_FRAME_COUNTER_STEPS,
+            Region::Ntsc | Region::Dendy => FRAME_COUNTER_STEPS,
+        };
+        let cycle = self.frame_cycle;
+
+        match self.frame_counter_mode {
+            _ if cycle == first || cycle == third => self.quarter_frame(),
+            _ if cycle == second => {
+                self.quarter_frame();
+                self.half_frame();
+            }
+            FrameCounterMode::FourStep if cycle == fourth - 1 => self.set_frame_irq(),
+            FrameCounterMode::FourStep if cycle == fourth => {
+                self.quarter_frame();
+                self.half_frame();
+                self.set_frame_irq();
+            }
+            FrameCounterMode::FourStep if cycle == fourth + 1 => {
+                self.set_frame_irq();
+                self.frame_cycle = 0;
+            }
+            FrameCounterMode::FiveStep if cycle == fifth => {
+                self.quarter_frame();
+                self.half_frame();
+            }
+            FrameCounterMode::FiveStep if cycle == fifth + 1 => self.frame_cycle = 0,
+            _ => {}
+        }
+    }
+
+    fn set_frame_irq(&mut self) {
+        if !self.frame_irq_inhibit {
+            self.frame_irq = true;
+        }
+    }
+
+    // Envelopes and the triangle's linear counter
+    fn quarter_frame(&mut self) {
+        self.pulse1.envelope.tick();
+        self.pulse2.envelope.tick();
+        self.triangle.tick_linear_counter();
+        self.noise.envelope.tick();
+    }
+
+    // Length counters and sweep units
+    fn half_frame(&mut self) {
+        self.pulse1.length_counter.tick();
+        self.pulse2.length_counter.tick();
+        self.triangle.length_counter.tick();
+        self.noise.length_counter.tick();
+
+        self.pulse1.tick_sweep();
+        self.pulse2.tick_sweep();
+    }
+
+    fn output(&self) -> f32 {
+        let pulse = self.pulse1.output() + self.pulse2.output();
+        let tnd = 3 * self.triangle.output() as usize
+            + 2 * self.noise.output() as usize
+            + self.dmc.output() as usize;
+
+        PULSE_TABLE[pulse as usize] + TND_TABLE[tnd]
+    }
+
+    fn sample(&mut self) {
+        let sample_rate = match self.sample_rate {
+            Some(rate) => rate,
+            None => return,
+        };
+
+        self.sample_sum += self.output();
+        self.sample_count += 1;
+        self.sample_timer += sample_rate as f64;
+
+        let clock_rate = self.region.cpu_clock_rate();
+        if self.sample_timer >= clock_rate {
+            self.sample_timer -= clock_rate;
+
+            let mut sample = self.sample_sum / self.sample_count as f32;
+            for filter in self.filters.iter_mut() {
+                sample = filter.step(sample);
+            }
+
+            self.sample_sum = 0.0;
+            self.sample_count = 0;
+
+            // Keep at most one second of audio around if nobody is draining it
+            if self.samples.len() < sample_rate as usize {
+                self.samples.push(sample);
+            }
+        }
+    }
+}
+
+impl Default for APU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_cycles(apu: &mut APU, cycles: u32) {
+        for _ in 0..cycles {
+            apu.tick();
+        }
+    }
+
+    #[test]
+    fn test_status_reports_length_counters() {
+        let mut apu = APU::new();
+
+        // Loading a length counter while the channel is disabled has no effect
+        apu.write_register(0x4003, 0x08);
+        assert_eq!(apu.read_status() & 0x01, 0);
+
+        apu.write_register(APU_STATUS, 0x0F);
+        apu.write_register(0x4003, 0x08);
+        apu.write_register(0x4007, 0x08);
+        apu.write_register(0x400B, 0x08);
+        apu.write_register(0x400F, 0x08);
+        assert_eq!(apu.read_status(), 0x0F);
+
+        // Disabling a channel clears its length counter
+        apu.write_register(APU_STATUS, 0x00);
+        assert_eq!(apu.read_status(), 0x00);
+    }
+
+    #[test]
+    fn test_length_counter_expires() {
+        let mut apu = APU::new();
+
+        apu.write_register(APU_STATUS, 0x01);
+        // Length index 1 loads 254, but index 3 loads 2
+        apu.write_register(0x4003, 0x18);
+
+        // Two half frames
+        run_cycles(&mut apu, 29829);
+        assert_eq!(apu.read_status() & 0x01, 0);
+    }
+
+    #[test]
+    fn test_frame_irq() {
+        let mut apu = APU::new();
+
+        run_cycles(&mut apu, 29827);
+        assert!(!apu.frame_irq_pending());
+
+        run_cycles(&mut apu, 1);
+        assert!(apu.frame_irq_pending());
+
+        // Peeking leaves it pending, reading $4015 acknowledges it
+        assert_eq!(apu.peek_status() & 0x40, 0x40);
+        assert!(apu.frame_irq_pending());
+        assert_eq!(apu.read_status() & 0x40, 0x40);
+        assert!(!apu.frame_irq_pending());
+    }
+
+    #[test]
+    fn test_pal_frame_irq() {
+        let mut apu = APU::new();
+        apu.set_region(Region::Pal);
+
+        run_cycles(&mut apu, 33251);
+        assert!(!apu.frame_irq_pending());
+        run_cycles(&mut apu, 1);
+        assert!(apu.frame_irq_pending());
+
+        // Dendy keeps the NTSC steps
+        let mut apu = APU::new();
+        apu.set_region(Region::Dendy);
+        run_cycles(&mut apu, 29828);
+        assert!(apu.frame_irq_pending());
+    }
+
+    #[test]
+    fn test_frame_irq_inhibit() {
+        let mut apu = APU::new();
+
+        apu.write_register(APU_FRAME_COUNTER, 0x40);
+        run_cycles(&mut apu, 30000);
+        assert!(!apu.frame_irq_pending());
+
+        // The 5-step mode never raises the interrupt
+        apu.write_register(APU_FRAME_COUNTER, 0x80);
+        run_cycles(&mut apu, 40000);
+        assert!(!apu.frame_irq_pending());
+    }
+
+    #[test]
+    fn test_dmc_reads_and_irq() {
+        let mut apu = APU::new();
+
+        // IRQ enabled, no loop, sample at $C000 with a length of 17 bytes
+        apu.write_register(0x4010, 0x8F);
+        apu.write_register(0x4012, 0x00);
+        apu.write_register(0x4013, 0x01);
+        apu.write_register(APU_STATUS, 0x10);
+
+        let mut reads = 0;
+        while let Some(address) = apu.poll_dmc_read() {
+            assert_eq!(address, 0xC000 + reads);
+            apu.load_dmc_sample(0xFF);
+            reads += 1;
+            // Let the output unit consume the sample buffer
+            run_cycles(&mut apu, 54 * 8);
+        }
+
+        assert_eq!(reads, 17);
+        assert!(apu.dmc_irq_pending());
+        assert_eq!(apu.read_status() & 0x90, 0x80);
+
+        // Writing $4015 clears the DMC interrupt
+        apu.write_register(APU_STATUS, 0x00);
+        assert!(!apu.dmc_irq_pending());
+    }
+
+    #[test]
+    fn test_samples_at_requested_rate() {
+        let mut apu = APU::new();
+        run_cycles(&mut apu, 1000);
+        assert!(apu.drain_samples().is_empty());
+
+        apu.set_sample_rate(44100);
+        run_cycles(&mut apu, Region::Ntsc.cpu_clock_rate() as u32 / 10);
+
+        let samples = apu.drain_samples();
+        assert!((4409..=4410).contains(&samples.len()));
+        assert!(apu.drain_samples().is_empty());
+    }
+}
@@ -0,0 +1,184 @@
+use serde::{Deserialize, Serialize};
+
+use crate::region::Region;
+
+// Periods in CPU cycles
+const DMC_RATE_TABLE: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+const PAL_DMC_RATE_TABLE: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+// https://www.nesdev.org/wiki/APU_DMC
+#[derive(Serialize, Deserialize)]
+pub struct DMC {
+    irq_enabled: bool,
+    pub irq_pending: bool,
+    looping: bool,
+
+    timer_period: u16,
+    timer: u16,
+    output_level: u8,
+
+    // Memory reader
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+
+    // Output unit
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+
+    // Picks the rate table, set again after loading a state
+    #[serde(skip)]
+    region: Region,
+}
+
+impl DMC {
+    pub fn new() -> Self {
+        DMC {
+            irq_enabled: false,
+            irq_pending: false,
+            looping: false,
+            timer_period: DMC_RATE_TABLE[0],
+            timer: 0,
+            output_level: 0,
+            sample_address: 0xC000,
+            sample_length: 1,
+            current_address: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+            region: Region::Ntsc,
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    pub fn write(&mut self, register: u16, data: u8) {
+        match register {
+            // IL-- RRRR
+            0 => {
+                self.irq_enabled = data & 0x80 != 0;
+                if !self.irq_enabled {
+                    self.irq_pending = false;
+                }
+                self.looping = data & 0x40 != 0;
+                let rates = match self.region {
+                    Region::Pal => &PAL_DMC_RATE_TABLE,
+                    Region::Ntsc | Region::Dendy => &DMC_RATE_TABLE,
+                };
+                self.timer_period = rates[(data & 0x0F) as usize];
+            }
+            // -DDD DDDD
+            1 => self.output_level = data & 0x7F,
+            // Sample address = %11AAAAAA.AA000000
+            2 => self.sample_address = 0xC000 | (data as u16) << 6,
+            // Sample length = %LLLL.LLLL0001
+            _ => self.sample_length = (data as u16) << 4 | 1,
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    // The address the memory reader wants to fetch, if the sample buffer is empty
+    pub fn read_request(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    pub fn load_sample(&mut self, value: u8) {
+        self.sample_buffer = Some(value);
+
+        // The address wraps around to $8000
+        self.current_address = if self.current_address == 0xFFFF {
+            0x8000
+        } else {
+            self.current_address + 1
+        };
+
+        self.bytes_remaining -= 1;
+
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    // Clocked every CPU cycle
+    pub fn tick_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period - 1;
+            self.clock_output();
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_output(&mut self) {
+        if !self.silence {
+            if self.shift_register & 0x01 != 0 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+
+        self.shift_register >>= 1;
+        self.bits_remaining -= 1;
+
+        // Start a new output cycle
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+
+            match self.sample_buffer.take() {
+                Some(sample) => {
+                    self.silence = false;
+                    self.shift_register = sample;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        self.output_level
+    }
+}
+
+impl Default for DMC {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+
+// https://www.nesdev.org/wiki/APU_Envelope
+#[derive(Default, Serialize, Deserialize)]
+pub struct Envelope {
+    start: bool,
+    divider: u8,
+    decay_level: u8,
+    looping: bool,
+    constant_volume: bool,
+    volume: u8,
+}
+
+impl Envelope {
+    // --LC VVVV
+    pub fn write(&mut self, data: u8) {
+        self.looping = data & 0x20 != 0;
+        self.constant_volume = data & 0x10 != 0;
+        self.volume = data & 0x0F;
+    }
+
+    pub fn restart(&mut self) {
+        self.start = true;
+    }
+
+    // Clocked by the frame counter on every quarter frame
+    pub fn tick(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay_level = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+
+            if self.decay_level > 0 {
+                self.decay_level -= 1;
+            } else if self.looping {
+                self.decay_level = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.constant_volume {
+            self.volume
+        } else {
+            self.decay_level
+        }
+    }
+}
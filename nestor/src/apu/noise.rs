@@ -0,0 +1,101 @@
+use serde::{Deserialize, Serialize};
+
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+use crate::region::Region;
+
+// Periods in CPU cycles
+const NOISE_PERIOD_TABLE: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+const PAL_NOISE_PERIOD_TABLE: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+// https://www.nesdev.org/wiki/APU_Noise
+#[derive(Serialize, Deserialize)]
+pub struct Noise {
+    mode: bool,
+    timer_period: u16,
+    timer: u16,
+    shift_register: u16,
+
+    pub envelope: Envelope,
+    pub length_counter: LengthCounter,
+
+    // Picks the period table, set again after loading a state
+    #[serde(skip)]
+    region: Region,
+}
+
+impl Noise {
+    pub fn new() -> Self {
+        Noise {
+            mode: false,
+            timer_period: NOISE_PERIOD_TABLE[0],
+            timer: 0,
+            // The shift register is 1 on power-up
+            shift_register: 1,
+            envelope: Envelope::default(),
+            length_counter: LengthCounter::default(),
+            region: Region::Ntsc,
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    pub fn write(&mut self, register: u16, data: u8) {
+        match register {
+            // --LC VVVV
+            0 => {
+                self.length_counter.halted = data & 0x20 != 0;
+                self.envelope.write(data);
+            }
+            1 => {}
+            // M--- PPPP
+            2 => {
+                self.mode = data & 0x80 != 0;
+                let periods = match self.region {
+                    Region::Pal => &PAL_NOISE_PERIOD_TABLE,
+                    Region::Ntsc | Region::Dendy => &NOISE_PERIOD_TABLE,
+                };
+                self.timer_period = periods[(data & 0x0F) as usize];
+            }
+            // LLLL L---
+            _ => {
+                self.length_counter.load(data >> 3);
+                self.envelope.restart();
+            }
+        }
+    }
+
+    // Clocked every CPU cycle
+    pub fn tick_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period - 1;
+
+            let other_bit = if self.mode { 6 } else { 1 };
+            let feedback = (self.shift_register ^ (self.shift_register >> other_bit)) & 0x01;
+
+            self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.shift_register & 0x01 != 0 || !self.length_counter.is_active() {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,143 @@
+use serde::{Deserialize, Serialize};
+
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+#[derive(PartialEq, Serialize, Deserialize)]
+pub enum PulseChannel {
+    One,
+    Two,
+}
+
+// https://www.nesdev.org/wiki/APU_Pulse
+#[derive(Serialize, Deserialize)]
+pub struct Pulse {
+    channel: PulseChannel,
+
+    duty: u8,
+    sequence_step: u8,
+    timer_period: u16,
+    timer: u16,
+
+    pub envelope: Envelope,
+    pub length_counter: LengthCounter,
+
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl Pulse {
+    pub fn new(channel: PulseChannel) -> Self {
+        Pulse {
+            channel,
+            duty: 0,
+            sequence_step: 0,
+            timer_period: 0,
+            timer: 0,
+            envelope: Envelope::default(),
+            length_counter: LengthCounter::default(),
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_divider: 0,
+            sweep_reload: false,
+        }
+    }
+
+    pub fn write(&mut self, register: u16, data: u8) {
+        match register {
+            // DDLC VVVV
+            0 => {
+                self.duty = data >> 6;
+                self.length_counter.halted = data & 0x20 != 0;
+                self.envelope.write(data);
+            }
+            // EPPP NSSS
+            1 => {
+                self.sweep_enabled = data & 0x80 != 0;
+                self.sweep_period = (data >> 4) & 0x07;
+                self.sweep_negate = data & 0x08 != 0;
+                self.sweep_shift = data & 0x07;
+                self.sweep_reload = true;
+            }
+            // TTTT TTTT
+            2 => self.timer_period = (self.timer_period & 0x0700) | data as u16,
+            // LLLL LTTT
+            _ => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((data & 0x07) as u16) << 8;
+                self.length_counter.load(data >> 3);
+                self.sequence_step = 0;
+                self.envelope.restart();
+            }
+        }
+    }
+
+    // Clocked every other CPU cycle
+    pub fn tick_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.sequence_step = (self.sequence_step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    // https://www.nesdev.org/wiki/APU_Sweep#Calculating_the_target_period
+    // Pulse 1 negates with ones' complement, pulse 2 with two's complement
+    fn target_period(&self) -> u16 {
+        let change = self.timer_period >> self.sweep_shift;
+
+        if self.sweep_negate {
+            let change = if self.channel == PulseChannel::One {
+                change + 1
+            } else {
+                change
+            };
+            self.timer_period.saturating_sub(change)
+        } else {
+            self.timer_period + change
+        }
+    }
+
+    fn is_muted(&self) -> bool {
+        self.timer_period < 8 || self.target_period() > 0x7FF
+    }
+
+    // Clocked by the frame counter on every half frame
+    pub fn tick_sweep(&mut self) {
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.is_muted()
+        {
+            self.timer_period = self.target_period();
+        }
+
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.is_muted()
+            || !self.length_counter.is_active()
+            || DUTY_TABLE[self.duty as usize][self.sequence_step as usize] == 0
+        {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use serde::{Deserialize, Serialize};
+
+use super::length_counter::LengthCounter;
+
+const TRIANGLE_SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+// https://www.nesdev.org/wiki/APU_Triangle
+#[derive(Default, Serialize, Deserialize)]
+pub struct Triangle {
+    control: bool,
+    linear_counter_reload_value: u8,
+    linear_counter: u8,
+    linear_counter_reload: bool,
+
+    timer_period: u16,
+    timer: u16,
+    sequence_step: u8,
+
+    pub length_counter: LengthCounter,
+}
+
+impl Triangle {
+    pub fn write(&mut self, register: u16, data: u8) {
+        match register {
+            // CRRR RRRR
+            0 => {
+                self.control = data & 0x80 != 0;
+                self.length_counter.halted = self.control;
+                self.linear_counter_reload_value = data & 0x7F;
+            }
+            1 => {}
+            // TTTT TTTT
+            2 => self.timer_period = (self.timer_period & 0x0700) | data as u16,
+            // LLLL LTTT
+            _ => {
+                self.timer_period = (self.timer_period & 0x00FF) | ((data & 0x07) as u16) << 8;
+                self.length_counter.load(data >> 3);
+                self.linear_counter_reload = true;
+            }
+        }
+    }
+
+    // Clocked every CPU cycle
+    pub fn tick_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+
+            if self.linear_counter > 0 && self.length_counter.is_active() {
+                self.sequence_step = (self.sequence_step + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    // Clocked by the frame counter on every quarter frame
+    pub fn tick_linear_counter(&mut self) {
+        if self.linear_counter_reload {
+            self.linear_counter = self.linear_counter_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+
+        if !self.control {
+            self.linear_counter_reload = false;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        // Silencing ultrasonic periods avoids popping, the real DAC output
+        // just averages out to the middle of the sequence anyway
+        if self.timer_period < 2 {
+            return 7;
+        }
+
+        TRIANGLE_SEQUENCE[self.sequence_step as usize]
+    }
+}
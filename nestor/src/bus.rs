@@ -1,10 +1,18 @@
 use std::sync::Arc;
 use std::sync::Mutex;
 
+use serde::{Deserialize, Serialize};
+use serde_big_array::BigArray;
+
 use crate::{
+    apu::APU,
+    cdl::CodeDataLogger,
+    cpu::AddressingMode,
     joypad::Joypad,
     mapper::Mapper,
-    ppu::{frame::Frame, PPU},
+    opcodes::{Mnemonic, OPCODES_MAP},
+    ppu::{events::PpuEventKind, frame::Frame, PPU},
+    region::Region,
     rom::ROM,
 };
 
@@ -12,6 +20,20 @@ pub trait Memory {
     fn mem_read(&mut self, addr: u16) -> u8;
     fn mem_write(&mut self, addr: u16, data: u8);
 
+    // What `mem_read` would return, without changing any state.
+    // For debuggers, tracers and memory viewers.
+    fn peek(&self, addr: u16) -> u8;
+    // Changes memory without the side effects a CPU write would have, for
+    // memory editors and debuggers
+    fn poke(&mut self, addr: u16, data: u8);
+
+    // Starts recording reads and writes from scratch, or stops when disabled,
+    // for watchpoints. Buses that can't record report no accesses.
+    fn record_accesses(&mut self, _enabled: bool) {}
+    fn accesses(&self) -> &[MemoryAccess] {
+        &[]
+    }
+
     fn mem_read_u16(&mut self, pos: u16) -> u16 {
         let lo = self.mem_read(pos);
         let hi = self.mem_read(pos.wrapping_add(1));
@@ -25,16 +47,56 @@ pub trait Memory {
     }
 }
 
+bitflags! {
+    // Devices that can pull the shared /IRQ line low. The line is
+    // level-triggered: it stays asserted while any source is set.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct IrqSource: u8 {
+        const MAPPER        = 0b00000001;
+        const FRAME_COUNTER = 0b00000010;
+        const DMC           = 0b00000100;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub address: u16,
+    pub value: u8,
+    pub kind: AccessKind,
+}
+
 pub trait CpuBus {
     fn poll_nmi_status(&mut self) -> Option<u8>;
+    fn poll_irq_status(&mut self) -> IrqSource;
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Bus {
+    #[serde(with = "BigArray")]
     cpu_vram: [u8; 2048],
     pub ppu: PPU,
+    pub apu: APU,
     pub joypad1: Joypad,
     pub joypad2: Joypad,
+    // CPU cycles lost to DMC fetches during the last tick, see `take_stall_cycles`
+    #[serde(skip)]
+    stall_cycles: u64,
+    region: Region,
+    // PPU dots owed to the PPU, in fractions of a dot, see `Region::ppu_dots_per_cpu_cycle`
+    ppu_clock: u8,
+    #[serde(skip)]
     mapper: Option<Arc<Mutex<Box<dyn Mapper + Send>>>>,
+    // Reads and writes made while recording is on, for the debugger
+    #[serde(skip)]
+    accesses: Option<Vec<MemoryAccess>>,
+    #[serde(skip)]
+    code_data_logger: Option<Arc<Mutex<CodeDataLogger>>>,
 }
 
 impl Bus {
@@ -43,9 +105,15 @@ impl Bus {
         Bus {
             cpu_vram: [0; 2048],
             ppu,
+            apu: APU::new(),
             joypad1: Joypad::new(),
             joypad2: Joypad::new(),
+            stall_cycles: 0,
+            region: Region::Ntsc,
+            ppu_clock: 0,
             mapper: None,
+            accesses: None,
+            code_data_logger: None,
         }
     }
 
@@ -54,13 +122,66 @@ impl Bus {
         self.mapper = Some(Arc::clone(&rom.mapper));
     }
 
+    // Takes over everything that isn't part of a save state (the cartridge,
+    // the frame buffer and the audio output) from the bus this one is replacing
+    pub fn reattach(&mut self, previous: &mut Bus) {
+        self.mapper = previous.mapper.take();
+        self.accesses = previous.accesses.take();
+        self.code_data_logger = previous.code_data_logger.take();
+        self.ppu.reattach(&mut previous.ppu);
+        self.apu.reattach(&mut previous.apu);
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.ppu_clock = 0;
+        self.ppu.set_region(region);
+        self.apu.set_region(region);
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
     pub fn tick(&mut self, cycles: u8) -> Option<&Frame> {
         let mut frame_complete = false;
+        let mut remaining = cycles as u16;
+
+        // The PPU is still where the instruction started
+        let region = self.region;
+        if let Some(logger) = self.ppu.event_logger_mut() {
+            logger.place_writes(cycles, region);
+        }
+
+        let (dots, per_cycles) = self.region.ppu_dots_per_cpu_cycle();
+
+        while remaining > 0 {
+            remaining -= 1;
 
-        for _ in 0..(cycles * 3) {
-            if self.ppu.tick() {
-                frame_complete = true;
-                break;
+            self.apu.tick();
+
+            // The DMC fetches its samples straight from CPU memory,
+            // halting the CPU for up to 4 cycles while it does so
+            if let Some(address) = self.apu.poll_dmc_read() {
+                // Samples are always in $8000-$FFFF. Reading the mapper directly keeps
+                // them from being logged as DATA or recorded as CPU accesses.
+                let value = self
+                    .mapper
+                    .as_ref()
+                    .map_or(0, |mapper| mapper.lock().unwrap().read(address));
+                self.log_data(address, CodeDataLogger::PCM_DATA);
+                self.apu.load_dmc_sample(value);
+                remaining += 4;
+                self.stall_cycles += 4;
+            }
+
+            self.ppu_clock += dots;
+            while self.ppu_clock >= per_cycles {
+                self.ppu_clock -= per_cycles;
+
+                if self.ppu.tick() {
+                    frame_complete = true;
+                }
             }
         }
 
@@ -71,6 +192,81 @@ impl Bus {
         }
     }
 
+    // The cycles `tick` ran on top of the instruction's, for the CPU's count
+    pub fn take_stall_cycles(&mut self) -> u64 {
+        std::mem::take(&mut self.stall_cycles)
+    }
+
+    fn record_access(&mut self, address: u16, value: u8, kind: AccessKind) {
+        if let Some(accesses) = &mut self.accesses {
+            accesses.push(MemoryAccess {
+                address,
+                value,
+                kind,
+            });
+        }
+    }
+
+    // Shared with the PPU, which marks the CHR side
+    pub fn set_code_data_logger(&mut self, logger: Option<Arc<Mutex<CodeDataLogger>>>) {
+        self.ppu.set_code_data_logger(logger.clone());
+        self.code_data_logger = logger;
+    }
+
+    pub fn code_data_logger(&self) -> Option<&Arc<Mutex<CodeDataLogger>>> {
+        self.code_data_logger.as_ref()
+    }
+
+    pub(crate) fn prg_rom_offset(&self, address: u16) -> Option<usize> {
+        self.mapper
+            .as_ref()?
+            .lock()
+            .unwrap()
+            .prg_rom_offset(address)
+    }
+
+    // What the code/data logger knows about the PRG-ROM byte at `address`
+    pub(crate) fn code_data_flags(&self, address: u16) -> Option<u8> {
+        let offset = self.prg_rom_offset(address)?;
+        let logger = self.code_data_logger.as_ref()?.lock().unwrap();
+
+        logger.prg().get(offset).copied()
+    }
+
+    // Marks the instruction at `address` as code, before the CPU runs it
+    pub(crate) fn log_instruction(&self, address: u16) {
+        let Some(logger) = &self.code_data_logger else {
+            return;
+        };
+
+        let opcode = OPCODES_MAP[&self.peek(address)];
+        let offsets: Vec<Option<usize>> = (0..opcode.len as u16)
+            .map(|i| self.prg_rom_offset(address.wrapping_add(i)))
+            .collect();
+
+        let indirect_data = matches!(
+            opcode.mode,
+            AddressingMode::IndirectX | AddressingMode::IndirectY
+        );
+        let indirect_jump = matches!(
+            (&opcode.mnemonic, &opcode.mode),
+            (Mnemonic::JMP, AddressingMode::Indirect)
+        );
+
+        logger
+            .lock()
+            .unwrap()
+            .log_instruction(address, &offsets, indirect_data, indirect_jump);
+    }
+
+    fn log_data(&self, address: u16, flags: u8) {
+        if let Some(logger) = &self.code_data_logger {
+            if let Some(offset) = self.prg_rom_offset(address) {
+                logger.lock().unwrap().log_data(offset, address, flags);
+            }
+        }
+    }
+
     fn dma_transfer(&mut self, data: u8) {
         let hi: u16 = (data as u16) << 8;
         for i in 0..256u16 {
@@ -83,14 +279,15 @@ impl Bus {
 
 impl Memory for Bus {
     fn mem_read(&mut self, addr: u16) -> u8 {
-        match addr {
+        let value = match addr {
             0x0000..=0x1FFF => {
                 let mirror_down_addr = addr & 0b00000111_11111111;
                 self.cpu_vram[mirror_down_addr as usize]
             }
             0x2000..=0x3FFF => self.ppu.cpu_read(addr),
-            0x4000..=0x4015 => {
-                //ignore APU
+            0x4015 => self.apu.read_status(),
+            0x4000..=0x4014 => {
+                // Write-only registers
                 0
             }
 
@@ -99,34 +296,88 @@ impl Memory for Bus {
 
             // SRAM
             0x6000..=0x7fff => self.mapper.as_ref().unwrap().lock().unwrap().read(addr),
-            0x8000..=0xFFFF => self.mapper.as_ref().unwrap().lock().unwrap().read(addr),
+            0x8000..=0xFFFF => {
+                self.log_data(addr, CodeDataLogger::DATA);
+                self.mapper.as_ref().unwrap().lock().unwrap().read(addr)
+            }
 
             _ => {
                 println!("Ignoring mem access at {:04X}", addr);
                 0
             }
+        };
+
+        self.record_access(addr, value, AccessKind::Read);
+
+        value
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x1FFF => self.cpu_vram[(addr & 0b00000111_11111111) as usize],
+            0x2000..=0x3FFF => self.ppu.peek(addr),
+            0x4015 => self.apu.peek_status(),
+            0x4016 => self.joypad1.peek(),
+            0x4017 => self.joypad2.peek(),
+            0x6000..=0xFFFF => match &self.mapper {
+                Some(mapper) => mapper.lock().unwrap().peek(addr),
+                None => 0,
+            },
+            _ => 0,
+        }
+    }
+
+    // Registers, unmapped addresses and PRG-ROM are left alone
+    fn poke(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.cpu_vram[(addr & 0b00000111_11111111) as usize] = data,
+            // Mappers only have registers from $8000 up
+            0x6000..=0x7FFF => {
+                if let Some(mapper) = &self.mapper {
+                    mapper.lock().unwrap().write(addr, data);
+                }
+            }
+            _ => {}
         }
     }
 
+    fn record_accesses(&mut self, enabled: bool) {
+        self.accesses = enabled.then(Vec::new);
+    }
+
+    fn accesses(&self) -> &[MemoryAccess] {
+        self.accesses.as_deref().unwrap_or_default()
+    }
+
     fn mem_write(&mut self, addr: u16, data: u8) {
+        self.record_access(addr, data, AccessKind::Write);
+
         match addr {
             0x0000..=0x1FFF => {
                 let mirror_down_addr = addr & 0b11111111111;
                 self.cpu_vram[mirror_down_addr as usize] = data;
             }
-            0x2000..=0x3FFF => self.ppu.cpu_write(addr, data),
-            0x4000..=0x4013 | 0x4015 => {
-                //ignore APU
+            0x2000..=0x3FFF => {
+                self.ppu.record_event(PpuEventKind::RegisterWrite {
+                    address: addr & 0x2007,
+                    value: data,
+                });
+                self.ppu.cpu_write(addr, data)
             }
+            0x4000..=0x4013 | 0x4015 => self.apu.write_register(addr, data),
 
             0x4016 => {
                 self.joypad1.write(data);
                 self.joypad2.write(data);
             }
-            0x4017 => {
-                //ignore for now
+            0x4017 => self.apu.write_register(addr, data),
+            0x4014 => {
+                self.ppu.record_event(PpuEventKind::RegisterWrite {
+                    address: addr,
+                    value: data,
+                });
+                self.dma_transfer(data)
             }
-            0x4014 => self.dma_transfer(data),
             // SRAM
             0x6000..=0x7fff => {
                 self.mapper
@@ -137,13 +388,16 @@ impl Memory for Bus {
                     .write(addr, data);
             }
             // PRG-ROM
-            0x8000..=0xFFFF => self
-                .mapper
-                .as_ref()
-                .unwrap()
-                .lock()
-                .unwrap()
-                .write(addr, data),
+            0x8000..=0xFFFF => {
+                self.mapper
+                    .as_ref()
+                    .unwrap()
+                    .lock()
+                    .unwrap()
+                    .write(addr, data);
+                // Mapper registers can switch the nametable mirroring
+                self.ppu.update_mirroring();
+            }
             _ => {
                 panic!("Ignoring mem write-access at {:04X}", addr);
             }
@@ -168,6 +422,24 @@ impl CpuBus for Bus {
     fn poll_nmi_status(&mut self) -> Option<u8> {
         self.ppu.poll_nmi_interrupt()
     }
+
+    fn poll_irq_status(&mut self) -> IrqSource {
+        let mut status = IrqSource::empty();
+
+        if let Some(ref mapper) = self.mapper {
+            status.set(IrqSource::MAPPER, mapper.lock().unwrap().irq_pending());
+        }
+
+        status.set(IrqSource::FRAME_COUNTER, self.apu.frame_irq_pending());
+        status.set(IrqSource::DMC, self.apu.dmc_irq_pending());
+
+        let (scanline, cycle) = (self.ppu.scanline, self.ppu.cycle);
+        if let Some(logger) = self.ppu.event_logger_mut() {
+            logger.update_irq(scanline, cycle, status);
+        }
+
+        status
+    }
 }
 
 impl Default for Bus {
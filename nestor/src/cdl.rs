@@ -0,0 +1,223 @@
+use std::fs;
+use std::path::Path;
+
+use crate::rom::ROM;
+
+// Marks which PRG-ROM bytes were executed or read and which CHR-ROM bytes were
+// rendered, by ROM offset. Saved in the FCEUX .cdl layout: one flag byte per
+// PRG-ROM byte followed by one per CHR-ROM byte.
+// https://fceux.com/web/help/CodeDataLogger.html
+pub struct CodeDataLogger {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+
+    // CPU addresses of the instruction being executed, so that fetching
+    // its operands doesn't count as reading data
+    instruction_start: u16,
+    instruction_len: u16,
+    // The instruction reads through a pointer, (zp,X) or (zp),Y
+    indirect_data: bool,
+    // The previous instruction was a JMP (abs)
+    indirect_jump: bool,
+}
+
+impl CodeDataLogger {
+    // PRG flags, xPdcAADC
+    pub const CODE: u8 = 0x01;
+    pub const DATA: u8 = 0x02;
+    // AA: which 8 KB slot of $8000-$FFFF the byte was mapped to
+    const BANK_SHIFT: u8 = 2;
+    pub const INDIRECT_CODE: u8 = 0x10;
+    pub const INDIRECT_DATA: u8 = 0x20;
+    // Fetched by the DMC as a sample
+    pub const PCM_DATA: u8 = 0x40;
+
+    // CHR flags, xxxxxxRD
+    pub const RENDERED: u8 = 0x01;
+    // Read by the CPU through PPUDATA
+    pub const READ: u8 = 0x02;
+
+    pub fn new(prg_rom_size: usize, chr_rom_size: usize) -> Self {
+        CodeDataLogger {
+            prg: vec![0; prg_rom_size],
+            chr: vec![0; chr_rom_size],
+            instruction_start: 0,
+            instruction_len: 0,
+            indirect_data: false,
+            indirect_jump: false,
+        }
+    }
+
+    // Sized for the cartridge, boards with CHR-RAM have no CHR flags
+    pub fn for_rom(rom: &ROM) -> Self {
+        Self::new(rom.prg_rom.len(), rom.chr_rom.len())
+    }
+
+    pub fn from_bytes(
+        data: &[u8],
+        prg_rom_size: usize,
+        chr_rom_size: usize,
+    ) -> Result<Self, String> {
+        if data.len() != prg_rom_size + chr_rom_size {
+            return Err(format!(
+                "Expected a {} byte code/data log, got {}",
+                prg_rom_size + chr_rom_size,
+                data.len()
+            ));
+        }
+
+        let mut logger = Self::new(prg_rom_size, chr_rom_size);
+        logger.prg.copy_from_slice(&data[..prg_rom_size]);
+        logger.chr.copy_from_slice(&data[prg_rom_size..]);
+
+        Ok(logger)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [self.prg.as_slice(), self.chr.as_slice()].concat()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P, rom: &ROM) -> Result<Self, String> {
+        let path = path.as_ref();
+        let data = fs::read(path).map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+
+        Self::from_bytes(&data, rom.prg_rom.len(), rom.chr_rom.len())
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        fs::write(path, self.to_bytes())
+            .map_err(|e| format!("Failed to write {}: {e}", path.display()))
+    }
+
+    // Flags for every PRG-ROM byte
+    pub fn prg(&self) -> &[u8] {
+        &self.prg
+    }
+
+    // Flags for every CHR-ROM byte
+    pub fn chr(&self) -> &[u8] {
+        &self.chr
+    }
+
+    pub fn clear(&mut self) {
+        self.prg.fill(0);
+        self.chr.fill(0);
+    }
+
+    fn mark_prg(&mut self, offset: usize, address: u16, flags: u8) {
+        let bank = ((address >> 13) & 0x03) as u8;
+
+        if let Some(byte) = self.prg.get_mut(offset) {
+            *byte |= flags | bank << Self::BANK_SHIFT;
+        }
+    }
+
+    // Starts a new instruction at `address`, `offsets` being where each of
+    // its bytes lives in the PRG-ROM
+    pub(crate) fn log_instruction(
+        &mut self,
+        address: u16,
+        offsets: &[Option<usize>],
+        indirect_data: bool,
+        indirect_jump: bool,
+    ) {
+        let flags = if self.indirect_jump {
+            Self::CODE | Self::INDIRECT_CODE
+        } else {
+            Self::CODE
+        };
+
+        for (i, offset) in offsets.iter().enumerate() {
+            if let Some(offset) = offset {
+                self.mark_prg(*offset, address.wrapping_add(i as u16), flags);
+            }
+        }
+
+        self.instruction_start = address;
+        self.instruction_len = offsets.len() as u16;
+        self.indirect_data = indirect_data;
+        self.indirect_jump = indirect_jump;
+    }
+
+    pub(crate) fn log_data(&mut self, offset: usize, address: u16, flags: u8) {
+        if address.wrapping_sub(self.instruction_start) < self.instruction_len {
+            return;
+        }
+
+        let flags = if self.indirect_data {
+            flags | Self::INDIRECT_DATA
+        } else {
+            flags
+        };
+
+        self.mark_prg(offset, address, flags);
+    }
+
+    pub(crate) fn log_chr(&mut self, offset: usize, flags: u8) {
+        if let Some(byte) = self.chr.get_mut(offset) {
+            *byte |= flags;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_operands_are_not_data() {
+        let mut logger = CodeDataLogger::new(0x8000, 0);
+
+        // LDA $8010 at $8000
+        logger.log_instruction(0x8000, &[Some(0), Some(1), Some(2)], false, false);
+        logger.log_data(1, 0x8001, CodeDataLogger::DATA);
+        logger.log_data(0x10, 0x8010, CodeDataLogger::DATA);
+
+        assert_eq!(&logger.prg()[0..3], &[CodeDataLogger::CODE; 3]);
+        assert_eq!(logger.prg()[0x10], CodeDataLogger::DATA);
+
+        // The AA bits hold the 8 KB slot, $E000 is the fourth
+        logger.log_instruction(0xE000, &[Some(0x6000)], false, false);
+        assert_eq!(logger.prg()[0x6000], CodeDataLogger::CODE | 0x0C);
+    }
+
+    #[test]
+    fn test_indirect_accesses() {
+        let mut logger = CodeDataLogger::new(0x8000, 0);
+
+        // JMP ($8100) lands on $8200
+        logger.log_instruction(0x8000, &[Some(0), Some(1), Some(2)], false, true);
+        logger.log_instruction(0x8200, &[Some(0x200)], false, false);
+        assert_eq!(
+            logger.prg()[0x200],
+            CodeDataLogger::CODE | CodeDataLogger::INDIRECT_CODE
+        );
+
+        // LDA ($10),Y reading $8300
+        logger.log_instruction(0x8201, &[Some(0x201), Some(0x202)], true, false);
+        logger.log_data(0x300, 0x8300, CodeDataLogger::DATA);
+        assert_eq!(
+            logger.prg()[0x300],
+            CodeDataLogger::DATA | CodeDataLogger::INDIRECT_DATA
+        );
+    }
+
+    #[test]
+    fn test_file_round_trip() {
+        let mut logger = CodeDataLogger::new(0x4000, 0x2000);
+        logger.log_instruction(0x8000, &[Some(0)], false, false);
+        logger.log_chr(0x10, CodeDataLogger::RENDERED);
+
+        let data = logger.to_bytes();
+        assert_eq!(data.len(), 0x6000);
+        assert_eq!(data[0], CodeDataLogger::CODE);
+        assert_eq!(data[0x4010], CodeDataLogger::RENDERED);
+
+        let loaded = CodeDataLogger::from_bytes(&data, 0x4000, 0x2000).unwrap();
+        assert_eq!(loaded.prg(), logger.prg());
+        assert_eq!(loaded.chr(), logger.chr());
+
+        assert!(CodeDataLogger::from_bytes(&data, 0x8000, 0x2000).is_err());
+    }
+}
@@ -1,7 +1,9 @@
 use std::fmt::Debug;
 
+use serde::{Deserialize, Serialize};
+
 use crate::{
-    bus::{CpuBus, Memory},
+    bus::{CpuBus, IrqSource, Memory},
     opcodes::{Mnemonic, OpCode, OPCODES_MAP},
 };
 
@@ -13,10 +15,12 @@ const BREAK_FLAG: u8 = 1 << 4;
 const OVERFLOW_FLAG: u8 = 1 << 6;
 const NEGATIVE_FLAG: u8 = 1 << 7;
 
-// const BRK_VECTOR: u16 = 0xfffe;
+const NMI_VECTOR: u16 = 0xfffa;
+const IRQ_VECTOR: u16 = 0xfffe;
 
 const STACK_RESET: u8 = 0xFD;
 
+#[derive(Serialize, Deserialize)]
 pub struct CPU<B: Memory + CpuBus> {
     pub register_a: u8,
     pub register_x: u8,
@@ -27,6 +31,14 @@ pub struct CPU<B: Memory + CpuBus> {
     pub bus: B,
     pub cycles: u64,
     pub halted: bool,
+
+    // The I flag as seen by the last interrupt poll. CLI, SEI and PLP change
+    // the flag after the poll, so their effect is delayed by one instruction.
+    irq_inhibit: bool,
+
+    // Set right after a BRK or IRQ sequence. An NMI detected at that point
+    // hijacks the sequence and reuses the already pushed return address.
+    hijack_window: bool,
 }
 
 #[derive(Debug)]
@@ -51,6 +63,89 @@ fn page_cross(addr1: u16, addr2: u16) -> bool {
     addr1 & 0xFF00 != addr2 & 0xFF00
 }
 
+fn read_u16<F: FnMut(u16) -> u8>(read: &mut F, address: u16) -> u16 {
+    let lo = read(address);
+    let hi = read(address.wrapping_add(1));
+    (hi as u16) << 8 | (lo as u16)
+}
+
+// Effective address for an operand at `address`, and whether indexing crossed a page
+fn resolve_address<F: FnMut(u16) -> u8>(
+    mut read: F,
+    mode: &AddressingMode,
+    address: u16,
+    register_x: u8,
+    register_y: u8,
+) -> (u16, bool) {
+    match mode {
+        AddressingMode::Immediate | AddressingMode::Implied => (address, false),
+
+        AddressingMode::ZeroPage => (read(address) as u16, false),
+
+        AddressingMode::Absolute => (read_u16(&mut read, address), false),
+
+        AddressingMode::ZeroPageX => {
+            let base = read(address);
+            let addr = base.wrapping_add(register_x) as u16;
+            (addr, false)
+        }
+        AddressingMode::ZeroPageY => {
+            let base = read(address);
+            let addr = base.wrapping_add(register_y) as u16;
+            (addr, false)
+        }
+
+        AddressingMode::AbsoluteX => {
+            let base = read_u16(&mut read, address);
+            let addr = base.wrapping_add(register_x as u16);
+            (addr, page_cross(base, addr))
+        }
+        AddressingMode::AbsoluteY => {
+            let base = read_u16(&mut read, address);
+            let addr = base.wrapping_add(register_y as u16);
+
+            (addr, page_cross(base, addr))
+        }
+
+        AddressingMode::Indirect => {
+            let indirect_address = read_u16(&mut read, address);
+
+            if indirect_address & 0x00FF == 0x00FF {
+                let lo = read(indirect_address);
+                let hi = read(indirect_address & 0xFF00);
+                ((hi as u16) << 8 | (lo as u16), false)
+            } else {
+                (read_u16(&mut read, indirect_address), false)
+            }
+        }
+        AddressingMode::IndirectX => {
+            let base = read(address);
+            let ptr = base.wrapping_add(register_x);
+
+            let lo = read(ptr as u16);
+            let hi = read(ptr.wrapping_add(1) as u16);
+            ((hi as u16) << 8 | (lo as u16), false)
+
+            // read_u16(&mut read, ptr as u16)
+        }
+        AddressingMode::IndirectY => {
+            let base = read(address);
+            // let ptr = read_u16(&mut read, base as u16);
+            // ptr.wrapping_add(register_y as u16)
+
+            let lo = read(base as u16);
+            let hi = read(base.wrapping_add(1) as u16);
+            let deref_base = (hi as u16) << 8 | (lo as u16);
+            let deref = deref_base.wrapping_add(register_y as u16);
+            (deref, page_cross(deref, deref_base))
+        }
+
+        _ => {
+            panic!("mode {:?} is not supported", mode);
+        }
+    }
+}
+
 impl<B: Memory + CpuBus> CPU<B> {
     pub fn new(bus: B) -> Self {
         CPU {
@@ -63,6 +158,8 @@ impl<B: Memory + CpuBus> CPU<B> {
             cycles: 0,
             bus,
             halted: false,
+            irq_inhibit: true,
+            hijack_window: false,
         }
     }
 
@@ -75,73 +172,29 @@ impl<B: Memory + CpuBus> CPU<B> {
         mode: &AddressingMode,
         address: u16,
     ) -> (u16, bool) {
-        match mode {
-            AddressingMode::Immediate | AddressingMode::Implied => (address, false),
-
-            AddressingMode::ZeroPage => (self.bus.mem_read(address) as u16, false),
-
-            AddressingMode::Absolute => (self.bus.mem_read_u16(address), false),
-
-            AddressingMode::ZeroPageX => {
-                let base = self.bus.mem_read(address);
-                let addr = base.wrapping_add(self.register_x) as u16;
-                (addr, false)
-            }
-            AddressingMode::ZeroPageY => {
-                let base = self.bus.mem_read(address);
-                let addr = base.wrapping_add(self.register_y) as u16;
-                (addr, false)
-            }
-
-            AddressingMode::AbsoluteX => {
-                let base = self.bus.mem_read_u16(address);
-                let addr = base.wrapping_add(self.register_x as u16);
-                (addr, page_cross(base, addr))
-            }
-            AddressingMode::AbsoluteY => {
-                let base = self.bus.mem_read_u16(address);
-                let addr = base.wrapping_add(self.register_y as u16);
-
-                (addr, page_cross(base, addr))
-            }
-
-            AddressingMode::Indirect => {
-                let indirect_address = self.bus.mem_read_u16(address);
-
-                if indirect_address & 0x00FF == 0x00FF {
-                    let lo = self.bus.mem_read(indirect_address);
-                    let hi = self.bus.mem_read(indirect_address & 0xFF00);
-                    ((hi as u16) << 8 | (lo as u16), false)
-                } else {
-                    (self.bus.mem_read_u16(indirect_address), false)
-                }
-            }
-            AddressingMode::IndirectX => {
-                let base = self.bus.mem_read(address);
-                let ptr = base.wrapping_add(self.register_x);
-
-                let lo = self.bus.mem_read(ptr as u16);
-                let hi = self.bus.mem_read(ptr.wrapping_add(1) as u16);
-                ((hi as u16) << 8 | (lo as u16), false)
-
-                // self.bus.mem_read_u16(ptr as u16)
-            }
-            AddressingMode::IndirectY => {
-                let base = self.bus.mem_read(address);
-                // let ptr = self.bus.mem_read_u16(base as u16);
-                // ptr.wrapping_add(self.register_y as u16)
-
-                let lo = self.bus.mem_read(base as u16);
-                let hi = self.bus.mem_read(base.wrapping_add(1) as u16);
-                let deref_base = (hi as u16) << 8 | (lo as u16);
-                let deref = deref_base.wrapping_add(self.register_y as u16);
-                (deref, page_cross(deref, deref_base))
-            }
-
-            _ => {
-                panic!("mode {:?} is not supported", mode);
-            }
-        }
+        let (register_x, register_y) = (self.register_x, self.register_y);
+        resolve_address(
+            |a| self.bus.mem_read(a),
+            mode,
+            address,
+            register_x,
+            register_y,
+        )
+    }
+
+    // Same as `get_address_by_addressing_mode`, but reading memory without side effects
+    pub fn peek_address_by_addressing_mode(
+        &self,
+        mode: &AddressingMode,
+        address: u16,
+    ) -> (u16, bool) {
+        resolve_address(
+            |a| self.bus.peek(a),
+            mode,
+            address,
+            self.register_x,
+            self.register_y,
+        )
     }
 
     fn pop_stack(&mut self) -> u8 {
@@ -314,7 +367,7 @@ impl<B: Memory + CpuBus> CPU<B> {
 
         self.set_flag(IRQ_FLAG, true);
 
-        self.program_counter = self.bus.mem_read_u16(0xFFFE);
+        self.program_counter = self.bus.mem_read_u16(IRQ_VECTOR);
     }
 
     fn clc(&mut self) {
@@ -958,14 +1011,34 @@ impl<B: Memory + CpuBus> CPU<B> {
         self.set_flag(NEGATIVE_FLAG, (value & 0x80) != 0);
     }
 
-    fn interrupt_nmi(&mut self) {
+    fn interrupt(&mut self, vector: u16) {
         self.push_stack16(self.program_counter);
-        self.php();
+        // The B flag is only set when the interrupt comes from BRK
+        self.push_stack((self.processor_status | 0x20) & !BREAK_FLAG);
 
         self.cycles += 7;
         self.set_flag(IRQ_FLAG, true);
+        self.irq_inhibit = true;
 
-        self.program_counter = self.bus.mem_read_u16(0xFFFA);
+        self.program_counter = self.bus.mem_read_u16(vector);
+    }
+
+    fn interrupt_nmi(&mut self) {
+        if self.hijack_window {
+            // https://www.nesdev.org/wiki/CPU_interrupts#Interrupt_hijacking
+            // The BRK/IRQ sequence already pushed PC and P, only the vector
+            // fetch is replaced.
+            self.hijack_window = false;
+            self.program_counter = self.bus.mem_read_u16(NMI_VECTOR);
+            return;
+        }
+
+        self.interrupt(NMI_VECTOR);
+    }
+
+    fn interrupt_irq(&mut self) {
+        self.interrupt(IRQ_VECTOR);
+        self.hijack_window = true;
     }
 
     pub fn reset(&mut self) {
@@ -975,6 +1048,8 @@ impl<B: Memory + CpuBus> CPU<B> {
         self.processor_status = 0x24;
         self.stack_pointer = STACK_RESET;
         self.halted = false;
+        self.irq_inhibit = true;
+        self.hijack_window = false;
         // self.cycles = 7;
         // self.bus.tick(7);
 
@@ -986,11 +1061,22 @@ impl<B: Memory + CpuBus> CPU<B> {
             return 1;
         }
 
+        let start_cycles = self.cycles;
+
+        // https://www.nesdev.org/wiki/CPU_interrupts
+        // Interrupts are serviced as a step of their own, with NMI taking
+        // priority over the level-triggered IRQ line.
         if self.bus.poll_nmi_status().is_some() {
             self.interrupt_nmi();
+            return (self.cycles - start_cycles) as u8;
         }
 
-        let start_cycles = self.cycles;
+        if self.bus.poll_irq_status() != IrqSource::empty() && !self.irq_inhibit {
+            self.interrupt_irq();
+            return (self.cycles - start_cycles) as u8;
+        }
+
+        let irq_flag = self.get_flag(IRQ_FLAG);
 
         let code = self.bus.mem_read(self.program_counter);
         self.program_counter = self.program_counter.wrapping_add(1);
@@ -1081,6 +1167,12 @@ impl<B: Memory + CpuBus> CPU<B> {
             self.program_counter = self.program_counter.wrapping_add((opcode.len - 1) as u16);
         }
 
+        self.irq_inhibit = match opcode.mnemonic {
+            Mnemonic::CLI | Mnemonic::SEI | Mnemonic::PLP => irq_flag,
+            _ => self.get_flag(IRQ_FLAG),
+        };
+        self.hijack_window = matches!(opcode.mnemonic, Mnemonic::BRK);
+
         self.cycles += opcode.cycles as u64;
 
         (self.cycles - start_cycles) as u8
@@ -1094,12 +1186,16 @@ mod tests {
 
     struct MockBus {
         memory: [u8; 0x10000],
+        nmi: Option<u8>,
+        irq: IrqSource,
     }
 
     impl MockBus {
         pub fn new() -> Self {
             let mut bus = Self {
                 memory: [0; 0x10000],
+                nmi: None,
+                irq: IrqSource::empty(),
             };
 
             bus.mem_write_u16(0xFFFC, 0x8000);
@@ -1120,11 +1216,23 @@ mod tests {
         fn mem_write(&mut self, addr: u16, data: u8) {
             self.memory[addr as usize] = data;
         }
+
+        fn peek(&self, addr: u16) -> u8 {
+            self.memory[addr as usize]
+        }
+
+        fn poke(&mut self, addr: u16, data: u8) {
+            self.memory[addr as usize] = data;
+        }
     }
 
     impl CpuBus for MockBus {
         fn poll_nmi_status(&mut self) -> Option<u8> {
-            None
+            self.nmi.take()
+        }
+
+        fn poll_irq_status(&mut self) -> IrqSource {
+            self.irq
         }
     }
 
@@ -1702,4 +1810,229 @@ mod tests {
         assert!(cpu.get_flag(NEGATIVE_FLAG));
         assert!(!cpu.get_flag(CARRY_FLAG));
     }
+
+    #[test]
+    fn test_irq_vectors_through_fffe() {
+        let mut mock_bus = MockBus::new();
+        mock_bus.load(&[0xEA]); // NOP
+        mock_bus.mem_write_u16(0xFFFE, 0x9000);
+        mock_bus.irq = IrqSource::MAPPER;
+
+        let mut cpu = CPU::new(mock_bus);
+        cpu.reset();
+        cpu.irq_inhibit = false;
+        cpu.set_flag(IRQ_FLAG, false);
+
+        let cycles = cpu.run();
+
+        assert_eq!(cycles, 7);
+        assert_eq!(cpu.program_counter, 0x9000);
+        assert!(cpu.get_flag(IRQ_FLAG));
+
+        // Return address and status pushed with B clear
+        assert_eq!(cpu.bus.mem_read(0x01FD), 0x80);
+        assert_eq!(cpu.bus.mem_read(0x01FC), 0x00);
+        assert_eq!(cpu.bus.mem_read(0x01FB) & BREAK_FLAG, 0);
+        assert_eq!(cpu.bus.mem_read(0x01FB) & 0x20, 0x20);
+    }
+
+    #[test]
+    fn test_irq_masked_by_i_flag() {
+        let mut mock_bus = MockBus::new();
+        mock_bus.load(&[0xEA]); // NOP
+        mock_bus.mem_write_u16(0xFFFE, 0x9000);
+        mock_bus.irq = IrqSource::MAPPER;
+
+        let mut cpu = CPU::new(mock_bus);
+        cpu.reset();
+
+        cpu.run();
+
+        assert_eq!(cpu.program_counter, 0x8001);
+    }
+
+    #[test]
+    fn test_irq_sources_are_ored() {
+        let mut mock_bus = MockBus::new();
+        mock_bus.load(&[0x58, 0xEA]); // CLI, NOP
+        mock_bus.mem_write_u16(0xFFFE, 0x9000);
+        mock_bus.irq = IrqSource::MAPPER | IrqSource::DMC;
+
+        let mut cpu = CPU::new(mock_bus);
+        cpu.reset();
+
+        cpu.run(); // CLI
+        cpu.run(); // NOP
+
+        // Acknowledging one source keeps the line asserted
+        cpu.bus.irq.remove(IrqSource::MAPPER);
+        cpu.run();
+
+        assert_eq!(cpu.program_counter, 0x9000);
+    }
+
+    #[test]
+    fn test_nmi_has_priority_over_irq() {
+        let mut mock_bus = MockBus::new();
+        mock_bus.load(&[0xEA]); // NOP
+        mock_bus.mem_write_u16(0xFFFA, 0xA000);
+        mock_bus.mem_write_u16(0xFFFE, 0x9000);
+        mock_bus.irq = IrqSource::MAPPER;
+        mock_bus.nmi = Some(1);
+
+        let mut cpu = CPU::new(mock_bus);
+        cpu.reset();
+        cpu.irq_inhibit = false;
+        cpu.set_flag(IRQ_FLAG, false);
+
+        cpu.run();
+
+        assert_eq!(cpu.program_counter, 0xA000);
+    }
+
+    #[test]
+    fn test_cli_delays_irq_by_one_instruction() {
+        let mut mock_bus = MockBus::new();
+        mock_bus.load(&[0x58, 0xEA, 0xEA]); // CLI, NOP, NOP
+        mock_bus.mem_write_u16(0xFFFE, 0x9000);
+        mock_bus.irq = IrqSource::MAPPER;
+
+        let mut cpu = CPU::new(mock_bus);
+        cpu.reset();
+
+        cpu.run(); // CLI
+        assert_eq!(cpu.program_counter, 0x8001);
+
+        cpu.run(); // NOP still runs
+        assert_eq!(cpu.program_counter, 0x8002);
+
+        cpu.run(); // IRQ
+        assert_eq!(cpu.program_counter, 0x9000);
+    }
+
+    #[test]
+    fn test_irq_taken_after_sei() {
+        let mut mock_bus = MockBus::new();
+        mock_bus.load(&[0x58, 0x78, 0xEA]); // CLI, SEI, NOP
+        mock_bus.mem_write_u16(0xFFFE, 0x9000);
+        mock_bus.irq = IrqSource::MAPPER;
+
+        let mut cpu = CPU::new(mock_bus);
+        cpu.reset();
+
+        cpu.run(); // CLI
+        cpu.run(); // SEI
+        cpu.run(); // IRQ sneaks in right after SEI
+
+        assert_eq!(cpu.program_counter, 0x9000);
+        // The pushed status already has I set
+        assert_eq!(cpu.bus.mem_read(0x01FB) & IRQ_FLAG, IRQ_FLAG);
+    }
+
+    #[test]
+    fn test_plp_delays_irq_by_one_instruction() {
+        let mut mock_bus = MockBus::new();
+        mock_bus.load(&[0x28, 0xEA, 0xEA]); // PLP, NOP, NOP
+        mock_bus.mem_write_u16(0xFFFE, 0x9000);
+        mock_bus.mem_write(0x01FE, 0x00); // Status with I clear
+        mock_bus.irq = IrqSource::MAPPER;
+
+        let mut cpu = CPU::new(mock_bus);
+        cpu.reset();
+
+        cpu.run(); // PLP
+        assert!(!cpu.get_flag(IRQ_FLAG));
+
+        cpu.run(); // NOP
+        assert_eq!(cpu.program_counter, 0x8002);
+
+        cpu.run(); // IRQ
+        assert_eq!(cpu.program_counter, 0x9000);
+    }
+
+    #[test]
+    fn test_rti_enables_irq_immediately() {
+        let mut mock_bus = MockBus::new();
+        mock_bus.load(&[0x40]); // RTI
+        mock_bus.mem_write_u16(0xFFFE, 0x9000);
+        mock_bus.mem_write(0x01FF, 0x80); // PCH
+        mock_bus.mem_write(0x01FE, 0x20); // PCL
+        mock_bus.mem_write(0x01FD, 0x00); // Status with I clear
+        mock_bus.irq = IrqSource::MAPPER;
+
+        let mut cpu = CPU::new(mock_bus);
+        cpu.reset();
+        cpu.stack_pointer = 0xFC;
+
+        cpu.run(); // RTI
+        assert_eq!(cpu.program_counter, 0x8020);
+
+        cpu.run(); // IRQ
+        assert_eq!(cpu.program_counter, 0x9000);
+    }
+
+    #[test]
+    fn test_nmi_hijacks_brk() {
+        let mut mock_bus = MockBus::new();
+        mock_bus.load(&[0x00]); // BRK
+        mock_bus.mem_write_u16(0xFFFA, 0xA000);
+        mock_bus.mem_write_u16(0xFFFE, 0x9000);
+
+        let mut cpu = CPU::new(mock_bus);
+        cpu.reset();
+
+        cpu.run(); // BRK
+        assert_eq!(cpu.program_counter, 0x9000);
+
+        // NMI arrives while BRK is still in flight
+        cpu.bus.nmi = Some(1);
+        cpu.run();
+
+        assert_eq!(cpu.program_counter, 0xA000);
+        // Only the BRK frame is on the stack, with B set
+        assert_eq!(cpu.stack_pointer, STACK_RESET.wrapping_sub(3));
+        assert_eq!(cpu.bus.mem_read(0x01FB) & BREAK_FLAG, BREAK_FLAG);
+    }
+
+    #[test]
+    fn test_nmi_hijacks_irq() {
+        let mut mock_bus = MockBus::new();
+        mock_bus.load(&[0xEA]); // NOP
+        mock_bus.mem_write_u16(0xFFFA, 0xA000);
+        mock_bus.mem_write_u16(0xFFFE, 0x9000);
+        mock_bus.irq = IrqSource::MAPPER;
+
+        let mut cpu = CPU::new(mock_bus);
+        cpu.reset();
+        cpu.irq_inhibit = false;
+        cpu.set_flag(IRQ_FLAG, false);
+
+        cpu.run(); // IRQ
+        cpu.bus.nmi = Some(1);
+        cpu.run();
+
+        assert_eq!(cpu.program_counter, 0xA000);
+        assert_eq!(cpu.stack_pointer, STACK_RESET.wrapping_sub(3));
+    }
+
+    #[test]
+    fn test_nmi_after_handler_instruction_is_not_hijack() {
+        let mut mock_bus = MockBus::new();
+        mock_bus.load(&[0x00]); // BRK
+        mock_bus.mem_write(0x9000, 0xEA); // NOP
+        mock_bus.mem_write_u16(0xFFFA, 0xA000);
+        mock_bus.mem_write_u16(0xFFFE, 0x9000);
+
+        let mut cpu = CPU::new(mock_bus);
+        cpu.reset();
+
+        cpu.run(); // BRK
+        cpu.run(); // NOP in the BRK handler
+        cpu.bus.nmi = Some(1);
+        cpu.run();
+
+        assert_eq!(cpu.program_counter, 0xA000);
+        assert_eq!(cpu.stack_pointer, STACK_RESET.wrapping_sub(6));
+        assert_eq!(cpu.bus.mem_read(0x01F8) & BREAK_FLAG, 0);
+    }
 }
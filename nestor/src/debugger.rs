@@ -0,0 +1,396 @@
+use std::collections::BTreeSet;
+
+use crate::{
+    bus::{AccessKind, Bus, Memory, MemoryAccess},
+    cpu::CPU,
+};
+
+const JSR: u8 = 0x20;
+const RTI: u8 = 0x40;
+const RTS: u8 = 0x60;
+
+// Watches a CPU address range, PPU and APU registers included
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub read: bool,
+    pub write: bool,
+}
+
+impl Watchpoint {
+    pub fn new(start: u16, end: u16, read: bool, write: bool) -> Self {
+        Watchpoint {
+            start,
+            end,
+            read,
+            write,
+        }
+    }
+
+    pub(crate) fn matches(&self, access: &MemoryAccess) -> bool {
+        let kind_matches = match access.kind {
+            AccessKind::Read => self.read,
+            AccessKind::Write => self.write,
+        };
+
+        kind_matches && (self.start..=self.end).contains(&access.address)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    // Stopped before executing the instruction at this address
+    Breakpoint(u16),
+    // Stopped after the instruction that made this access
+    Watchpoint(MemoryAccess),
+    Step,
+    Scanline(usize),
+    Frame,
+    // The CPU executed a JAM opcode
+    Halted,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RunMode {
+    Continue,
+    StepInto,
+    StepOver {
+        return_address: u16,
+        stack_pointer: u8,
+    },
+    StepOut {
+        stack_pointer: u8,
+    },
+    ToScanline(usize),
+    ToFrame,
+}
+
+// Breakpoints, watchpoints and stepping on top of `CPU::run`. The NES owns
+// one and goes through it whenever it has something to check, pausing the
+// emulation when it reports a stop.
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+    mode: RunMode,
+    stop_reason: Option<StopReason>,
+    // Lets the emulation leave the breakpoint it stopped on
+    resume_address: Option<u16>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            mode: RunMode::Continue,
+            stop_reason: None,
+            resume_address: None,
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) {
+        self.breakpoints.remove(&address);
+    }
+
+    pub fn has_breakpoint(&self, address: u16) -> bool {
+        self.breakpoints.contains(&address)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) {
+        self.watchpoints.retain(|w| w != watchpoint);
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    // Why the emulation was last paused by the debugger, cleared on resume
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        self.stop_reason
+    }
+
+    pub(crate) fn is_active(&self) -> bool {
+        !self.breakpoints.is_empty()
+            || !self.watchpoints.is_empty()
+            || self.mode != RunMode::Continue
+    }
+
+    pub(crate) fn resume(&mut self, mode: RunMode, program_counter: u16) {
+        self.mode = mode;
+        self.stop_reason = None;
+        self.resume_address = Some(program_counter);
+    }
+
+    // Step over only differs from step into on a JSR
+    pub(crate) fn step_over_mode(cpu: &CPU<Bus>) -> RunMode {
+        if cpu.bus.peek(cpu.program_counter) == JSR {
+            RunMode::StepOver {
+                return_address: cpu.program_counter.wrapping_add(3),
+                stack_pointer: cpu.stack_pointer,
+            }
+        } else {
+            RunMode::StepInto
+        }
+    }
+
+    // Checked before `step`, stops when the next instruction is on a breakpoint
+    pub(crate) fn check_breakpoint(&mut self, cpu: &CPU<Bus>) -> bool {
+        self.stop_reason = None;
+
+        let program_counter = cpu.program_counter;
+        let resuming = self.resume_address.take() == Some(program_counter);
+
+        if !resuming && self.breakpoints.contains(&program_counter) {
+            self.stop(StopReason::Breakpoint(program_counter));
+            return true;
+        }
+
+        false
+    }
+
+    // Runs one instruction (or interrupt) and ticks the bus, like
+    // `NES::emulate_frame` does, checking for a reason to stop around it.
+    // Returns whether a frame was completed.
+    pub(crate) fn step(&mut self, cpu: &mut CPU<Bus>) -> bool {
+        let program_counter = cpu.program_counter;
+
+        let opcode = match self.mode {
+            RunMode::StepOut { .. } => cpu.bus.peek(program_counter),
+            _ => 0,
+        };
+        let scanline = cpu.bus.ppu.scanline;
+        let halted = cpu.halted;
+
+        cpu.bus.record_accesses(!self.watchpoints.is_empty());
+
+        let cycles = cpu.run();
+        let frame_complete = cpu.bus.tick(cycles).is_some();
+        cpu.cycles += cpu.bus.take_stall_cycles();
+
+        let watch_hit = cpu
+            .bus
+            .accesses()
+            .iter()
+            .find(|access| self.watchpoints.iter().any(|w| w.matches(access)))
+            .copied();
+
+        cpu.bus.record_accesses(false);
+
+        if let Some(access) = watch_hit {
+            self.stop(StopReason::Watchpoint(access));
+        } else if !halted && cpu.halted {
+            self.stop(StopReason::Halted);
+        } else if let Some(reason) = self.mode_stop_reason(cpu, opcode, scanline, frame_complete) {
+            self.stop(reason);
+        }
+
+        frame_complete
+    }
+
+    fn mode_stop_reason(
+        &self,
+        cpu: &CPU<Bus>,
+        opcode: u8,
+        scanline: usize,
+        frame_complete: bool,
+    ) -> Option<StopReason> {
+        let done = match self.mode {
+            RunMode::Continue => false,
+            RunMode::StepInto => true,
+            RunMode::StepOver {
+                return_address,
+                stack_pointer,
+            } => cpu.program_counter == return_address && cpu.stack_pointer == stack_pointer,
+            // Returning from the current routine leaves the stack above where it started,
+            // interrupts and nested calls return back to the same level
+            RunMode::StepOut { stack_pointer } => {
+                matches!(opcode, RTS | RTI) && cpu.stack_pointer > stack_pointer
+            }
+            RunMode::ToScanline(target) => {
+                return (scanline != target && cpu.bus.ppu.scanline == target)
+                    .then_some(StopReason::Scanline(target));
+            }
+            RunMode::ToFrame => return frame_complete.then_some(StopReason::Frame),
+        };
+
+        done.then_some(StopReason::Step)
+    }
+
+    fn stop(&mut self, reason: StopReason) {
+        self.mode = RunMode::Continue;
+        self.stop_reason = Some(reason);
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{rom::ROM, NES};
+
+    use super::*;
+
+    // $8000  LDA #$05
+    // $8002  JSR $8010
+    // $8005  STA $0200
+    // $8008  BIT $2002
+    // $800B  JMP $8000
+    // $8010  INX
+    // $8011  JSR $8020
+    // $8014  RTS
+    // $8020  INY
+    // $8021  RTS
+    fn test_nes() -> NES {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut prg_rom = vec![0xEA; 0x4000];
+        prg_rom[0x00..0x0E].copy_from_slice(&[
+            0xA9, 0x05, 0x20, 0x10, 0x80, 0x8D, 0x00, 0x02, 0x2C, 0x02, 0x20, 0x4C, 0x00, 0x80,
+        ]);
+        prg_rom[0x10..0x15].copy_from_slice(&[0xE8, 0x20, 0x20, 0x80, 0x60]);
+        prg_rom[0x20..0x22].copy_from_slice(&[0xC8, 0x60]);
+        prg_rom[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        raw.extend(prg_rom);
+        raw.extend(vec![0; 0x2000]);
+
+        let mut nes = NES::new();
+        nes.insert_cartridge(ROM::from_bytes(&raw).unwrap());
+        nes
+    }
+
+    fn run_until_stop(nes: &mut NES) -> StopReason {
+        for _ in 0..1_000_000 {
+            if !nes.is_running() {
+                return nes.debugger.stop_reason().unwrap();
+            }
+            nes.emulate_frame();
+        }
+
+        panic!("The debugger never stopped");
+    }
+
+    #[test]
+    fn test_breakpoint() {
+        let mut nes = test_nes();
+        nes.debugger.add_breakpoint(0x8005);
+
+        assert_eq!(run_until_stop(&mut nes), StopReason::Breakpoint(0x8005));
+        assert_eq!(nes.cpu.program_counter, 0x8005);
+        assert_eq!(nes.cpu.register_x, 1);
+
+        // Continuing leaves the breakpoint and stops on it the next time around
+        nes.continue_emulation();
+        assert_eq!(run_until_stop(&mut nes), StopReason::Breakpoint(0x8005));
+        assert_eq!(nes.cpu.register_x, 2);
+    }
+
+    #[test]
+    fn test_write_watchpoint() {
+        let mut nes = test_nes();
+        nes.debugger
+            .add_watchpoint(Watchpoint::new(0x0200, 0x02FF, false, true));
+
+        let access = MemoryAccess {
+            address: 0x0200,
+            value: 0x05,
+            kind: AccessKind::Write,
+        };
+        assert_eq!(run_until_stop(&mut nes), StopReason::Watchpoint(access));
+        assert_eq!(nes.cpu.program_counter, 0x8008);
+    }
+
+    #[test]
+    fn test_ppu_register_watchpoint() {
+        let mut nes = test_nes();
+        nes.debugger
+            .add_watchpoint(Watchpoint::new(0x2000, 0x2007, true, false));
+
+        match run_until_stop(&mut nes) {
+            StopReason::Watchpoint(access) => {
+                assert_eq!(access.address, 0x2002);
+                assert_eq!(access.kind, AccessKind::Read);
+            }
+            reason => panic!("Unexpected stop: {reason:?}"),
+        }
+        assert_eq!(nes.cpu.program_counter, 0x800B);
+    }
+
+    #[test]
+    fn test_stepping() {
+        let mut nes = test_nes();
+        nes.debugger.add_breakpoint(0x8002);
+        run_until_stop(&mut nes);
+        nes.debugger.remove_breakpoint(0x8002);
+
+        nes.step_into();
+        assert_eq!(run_until_stop(&mut nes), StopReason::Step);
+        assert_eq!(nes.cpu.program_counter, 0x8010);
+
+        nes.step_into();
+        run_until_stop(&mut nes);
+        assert_eq!(nes.cpu.program_counter, 0x8011);
+
+        // Runs the whole nested routine
+        nes.step_over();
+        run_until_stop(&mut nes);
+        assert_eq!(nes.cpu.program_counter, 0x8014);
+        assert_eq!(nes.cpu.register_y, 1);
+
+        nes.step_over();
+        run_until_stop(&mut nes);
+        assert_eq!(nes.cpu.program_counter, 0x8005);
+
+        nes.debugger.add_breakpoint(0x8011);
+        nes.continue_emulation();
+        run_until_stop(&mut nes);
+
+        // Returns through the nested routine and stops back in the caller
+        nes.step_out();
+        assert_eq!(run_until_stop(&mut nes), StopReason::Step);
+        assert_eq!(nes.cpu.program_counter, 0x8005);
+        assert_eq!(nes.cpu.register_y, 2);
+    }
+
+    #[test]
+    fn test_run_to_scanline_and_frame() {
+        let mut nes = test_nes();
+        nes.pause_emulation();
+
+        nes.run_to_scanline(100);
+        assert_eq!(run_until_stop(&mut nes), StopReason::Scanline(100));
+        assert_eq!(nes.cpu.bus.ppu.scanline, 100);
+
+        nes.run_to_frame();
+        assert_eq!(run_until_stop(&mut nes), StopReason::Frame);
+        assert_eq!(nes.cpu.bus.ppu.scanline, 0);
+    }
+
+    #[test]
+    fn test_halt() {
+        let mut nes = test_nes();
+        nes.pause_emulation();
+        nes.cpu.bus.mem_write(0x0300, 0x02);
+        nes.cpu.program_counter = 0x0300;
+
+        nes.step_into();
+        assert_eq!(run_until_stop(&mut nes), StopReason::Halted);
+    }
+}
@@ -0,0 +1,314 @@
+use std::fmt;
+
+use crate::{
+    cpu::AddressingMode,
+    opcodes::{Mnemonic, OpCode, OPCODES_MAP},
+};
+
+// Longest instruction, used to look for a starting point before an address
+const MAX_INSTRUCTION_LEN: u16 = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub mnemonic: &'static str,
+    pub operand: String,
+    pub official: bool,
+    // Destination of a branch, JMP or JSR, when known without running the code
+    pub target: Option<u16>,
+    // Memory the operand names, before any indexing or indirection
+    pub operand_address: Option<u16>,
+}
+
+impl Instruction {
+    pub fn size(&self) -> u16 {
+        self.bytes.len() as u16
+    }
+
+    pub fn next_address(&self) -> u16 {
+        self.address.wrapping_add(self.size())
+    }
+}
+
+// Same layout as the trace, unofficial opcodes are marked with a *
+// C000  4C F5 C5  JMP $C5F5
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes = self
+            .bytes
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<Vec<String>>()
+            .join(" ");
+        let mnemonic = if self.official {
+            self.mnemonic.to_string()
+        } else {
+            format!("*{}", self.mnemonic)
+        };
+
+        let line = format!(
+            "{:04X}  {:8} {: >4} {}",
+            self.address, bytes, mnemonic, self.operand
+        );
+
+        write!(f, "{}", line.trim_end())
+    }
+}
+
+fn is_official(opcode: &OpCode) -> bool {
+    match opcode.mnemonic {
+        Mnemonic::NOP => opcode.code == 0xEA,
+        Mnemonic::SBC => opcode.code != 0xEB,
+        Mnemonic::AHX
+        | Mnemonic::ALR
+        | Mnemonic::ANC
+        | Mnemonic::ARR
+        | Mnemonic::AXS
+        | Mnemonic::DCP
+        | Mnemonic::ISB
+        | Mnemonic::JAM
+        | Mnemonic::LAS
+        | Mnemonic::LAX
+        | Mnemonic::RLA
+        | Mnemonic::RRA
+        | Mnemonic::SAX
+        | Mnemonic::SHX
+        | Mnemonic::SHY
+        | Mnemonic::SLO
+        | Mnemonic::SRE
+        | Mnemonic::TAS
+        | Mnemonic::XAA => false,
+        _ => true,
+    }
+}
+
+// Decodes the instruction at `address`. Only the instruction bytes are read,
+// operands aren't resolved since that would depend on the CPU registers.
+pub fn decode<F: FnMut(u16) -> u8>(read: &mut F, address: u16) -> Instruction {
+    let opcode = OPCODES_MAP[&read(address)];
+
+    let bytes: Vec<u8> = (0..opcode.len as u16)
+        .map(|i| read(address.wrapping_add(i)))
+        .collect();
+
+    let byte = bytes.get(1).copied().unwrap_or(0);
+    let word = (bytes.get(2).copied().unwrap_or(0) as u16) << 8 | byte as u16;
+
+    let mut target = None;
+
+    let operand_address = match (&opcode.mode, opcode.len) {
+        (
+            AddressingMode::ZeroPage
+            | AddressingMode::ZeroPageX
+            | AddressingMode::ZeroPageY
+            | AddressingMode::IndirectX
+            | AddressingMode::IndirectY,
+            _,
+        ) => Some(byte as u16),
+        (AddressingMode::Accumulator | AddressingMode::Immediate, _) => None,
+        (_, 3) => Some(word),
+        _ => None,
+    };
+
+    let operand = match (&opcode.mode, opcode.len) {
+        (AddressingMode::Accumulator, _) => "A".to_string(),
+        (AddressingMode::Immediate, _) => format!("#${:02X}", byte),
+        (AddressingMode::ZeroPage, _) => format!("${:02X}", byte),
+        (AddressingMode::ZeroPageX, _) => format!("${:02X},X", byte),
+        (AddressingMode::ZeroPageY, _) => format!("${:02X},Y", byte),
+        (AddressingMode::IndirectX, _) => format!("(${:02X},X)", byte),
+        (AddressingMode::IndirectY, _) => format!("(${:02X}),Y", byte),
+        (AddressingMode::AbsoluteX, _) => format!("${:04X},X", word),
+        (AddressingMode::AbsoluteY, _) => format!("${:04X},Y", word),
+        (AddressingMode::Indirect, _) => format!("(${:04X})", word),
+        (AddressingMode::Absolute, _) => {
+            if matches!(opcode.mnemonic, Mnemonic::JMP | Mnemonic::JSR) {
+                target = Some(word);
+            }
+            format!("${:04X}", word)
+        }
+        // Branches, relative to the next instruction
+        (AddressingMode::Relative | AddressingMode::NoneAddressing, 2) => {
+            let destination = address.wrapping_add(2).wrapping_add(byte as i8 as u16);
+            target = Some(destination);
+            format!("${:04X}", destination)
+        }
+        (_, 3) => format!("${:04X}", word),
+        _ => String::new(),
+    };
+
+    Instruction {
+        address,
+        bytes,
+        mnemonic: opcode._mnemonic_name.trim_start_matches('*'),
+        operand,
+        official: is_official(opcode),
+        target,
+        operand_address,
+    }
+}
+
+// Decodes every instruction starting within `start..=end`
+pub fn disassemble<F: FnMut(u16) -> u8>(mut read: F, start: u16, end: u16) -> Vec<Instruction> {
+    let mut instructions = vec![];
+    let mut address = start;
+
+    while address <= end {
+        let instruction = decode(&mut read, address);
+        let next_address = instruction.next_address();
+        instructions.push(instruction);
+
+        // Wrapped around the address space
+        if next_address <= address {
+            break;
+        }
+        address = next_address;
+    }
+
+    instructions
+}
+
+// Decodes a block of raw bytes, such as a PRG-ROM bank, as if it was mapped
+// at `base_address`. An instruction cut off at the end reads zeros.
+pub fn disassemble_bytes(data: &[u8], base_address: u16) -> Vec<Instruction> {
+    if data.is_empty() {
+        return vec![];
+    }
+
+    let end = base_address.saturating_add((data.len() - 1) as u16);
+    let read = |address: u16| {
+        data.get(address.wrapping_sub(base_address) as usize)
+            .copied()
+            .unwrap_or(0)
+    };
+
+    disassemble(read, base_address, end)
+}
+
+// Up to `before` instructions leading to `address`, followed by `address` itself
+// and `after` more. Code can't be reliably decoded backwards, so this looks for
+// the earliest starting point that lines up with `address`.
+pub fn disassemble_around<F: FnMut(u16) -> u8>(
+    mut read: F,
+    address: u16,
+    before: usize,
+    after: usize,
+) -> Vec<Instruction> {
+    let lookback = before as u16 * MAX_INSTRUCTION_LEN;
+    let mut instructions = vec![];
+
+    for start in address.saturating_sub(lookback)..address {
+        let mut candidate = vec![];
+        let mut current = start;
+
+        while current < address {
+            let instruction = decode(&mut read, current);
+            current = current.wrapping_add(instruction.size());
+            candidate.push(instruction);
+        }
+
+        if current == address {
+            let skip = candidate.len().saturating_sub(before);
+            instructions.extend(candidate.into_iter().skip(skip));
+            break;
+        }
+    }
+
+    let mut current = address;
+    for _ in 0..=after {
+        let instruction = decode(&mut read, current);
+        current = instruction.next_address();
+        instructions.push(instruction);
+    }
+
+    instructions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_official() {
+        let program = [
+            0xA9, 0x05, // LDA #$05
+            0x8D, 0x00, 0x02, // STA $0200
+            0xB1, 0x10, // LDA ($10),Y
+            0x0A, // ASL A
+            0x6C, 0xFC, 0xFF, // JMP ($FFFC)
+            0x20, 0x00, 0x80, // JSR $8000
+        ];
+        let instructions = disassemble_bytes(&program, 0xC000);
+
+        let lines: Vec<String> = instructions.iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "C000  A9 05     LDA #$05",
+                "C002  8D 00 02  STA $0200",
+                "C005  B1 10     LDA ($10),Y",
+                "C007  0A        ASL A",
+                "C008  6C FC FF  JMP ($FFFC)",
+                "C00B  20 00 80  JSR $8000",
+            ]
+        );
+        assert_eq!(instructions[4].target, None);
+        assert_eq!(instructions[5].target, Some(0x8000));
+
+        assert_eq!(instructions[0].operand_address, None);
+        assert_eq!(instructions[1].operand_address, Some(0x0200));
+        assert_eq!(instructions[2].operand_address, Some(0x0010));
+        assert_eq!(instructions[4].operand_address, Some(0xFFFC));
+    }
+
+    #[test]
+    fn test_decode_unofficial() {
+        let program = [0x04, 0xA9, 0xEB, 0x01, 0xA7, 0x20, 0x02, 0xEA];
+        let instructions = disassemble_bytes(&program, 0x8000);
+
+        let lines: Vec<String> = instructions.iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "8000  04 A9    *NOP $A9",
+                "8002  EB 01    *SBC #$01",
+                "8004  A7 20    *LAX $20",
+                "8006  02       *JAM",
+                "8007  EA        NOP",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_branch_targets() {
+        let program = [
+            0xD0, 0xFE, // BNE to itself
+            0x10, 0x02, // BPL forward
+            0xF0, 0x80, // BEQ backwards across a page
+        ];
+        let instructions = disassemble_bytes(&program, 0x8000);
+
+        assert_eq!(instructions[0].target, Some(0x8000));
+        assert_eq!(instructions[1].target, Some(0x8006));
+        assert_eq!(instructions[2].target, Some(0x7F86));
+        assert_eq!(instructions[2].operand, "$7F86");
+    }
+
+    #[test]
+    fn test_disassemble_around() {
+        // LDA #$00; STA $2000; INX; INX
+        let program = [0xA9, 0x00, 0x8D, 0x00, 0x20, 0xE8, 0xE8];
+        let read = |address: u16| {
+            program
+                .get(address.wrapping_sub(0x8000) as usize)
+                .copied()
+                .unwrap_or(0)
+        };
+
+        let instructions = disassemble_around(read, 0x8005, 2, 1);
+        let addresses: Vec<u16> = instructions.iter().map(|i| i.address).collect();
+
+        assert_eq!(addresses, vec![0x8000, 0x8002, 0x8005, 0x8006]);
+    }
+}
@@ -0,0 +1,624 @@
+use std::collections::BTreeSet;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+
+use crate::bus::{AccessKind, CpuBus, Memory, MemoryAccess};
+use crate::cpu::CPU;
+use crate::debugger::Watchpoint;
+
+// https://sourceware.org/gdb/current/onlinedocs/gdb.html/Remote-Protocol.html
+//
+// There's no 6502 target in GDB, so the register layout is our own. `g`
+// returns A, X, Y, P and SP as one byte each followed by the PC as two bytes,
+// little endian. `p`/`P` use the same order: 0 = A ... 4 = SP, 5 = PC.
+const REGISTER_COUNT: usize = 6;
+const PC_REGISTER: usize = 5;
+
+// Instructions run between checks for a Ctrl-C from the client
+const INTERRUPT_POLL_INTERVAL: usize = 1000;
+
+// Signals reported in stop replies
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+// Listens on localhost for a GDB remote serial protocol client
+pub struct GdbServer {
+    listener: TcpListener,
+}
+
+impl GdbServer {
+    // Port 0 picks a free port, see `local_addr`
+    pub fn bind(port: u16) -> Result<Self, String> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+            .map_err(|e| format!("Failed to listen on port {port}: {e}"))?;
+
+        Ok(GdbServer { listener })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, String> {
+        self.listener.local_addr().map_err(|e| e.to_string())
+    }
+
+    // Blocks until a client connects
+    pub fn accept(&self) -> Result<GdbSession, String> {
+        let (stream, _) = self
+            .listener
+            .accept()
+            .map_err(|e| format!("Failed to accept a GDB connection: {e}"))?;
+
+        // Packets are small and every one waits for a reply
+        let _ = stream.set_nodelay(true);
+
+        Ok(GdbSession::new(stream))
+    }
+}
+
+enum Command {
+    Reply(String),
+    Detach,
+    Kill,
+}
+
+// A connected client. It has full control of the CPU while `run` is serving it.
+pub struct GdbSession {
+    stream: TcpStream,
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+    last_stop: String,
+}
+
+impl GdbSession {
+    fn new(stream: TcpStream) -> Self {
+        GdbSession {
+            stream,
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            last_stop: format!("S{:02x}", SIGTRAP),
+        }
+    }
+
+    // Serves requests until the client detaches, kills the program or
+    // disconnects. `step` runs a single instruction, along with anything
+    // that has to be clocked with it.
+    pub fn run<B, F>(&mut self, cpu: &mut CPU<B>, mut step: F) -> Result<(), String>
+    where
+        B: Memory + CpuBus,
+        F: FnMut(&mut CPU<B>),
+    {
+        while let Some(packet) = self.read_packet()? {
+            match self.handle(&packet, cpu, &mut step)? {
+                Some(Command::Reply(reply)) => self.send(&reply)?,
+                Some(Command::Detach) => {
+                    self.send("OK")?;
+                    break;
+                }
+                Some(Command::Kill) => break,
+                // Disconnected while running
+                None => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle<B, F>(
+        &mut self,
+        packet: &str,
+        cpu: &mut CPU<B>,
+        step: &mut F,
+    ) -> Result<Option<Command>, String>
+    where
+        B: Memory + CpuBus,
+        F: FnMut(&mut CPU<B>),
+    {
+        let (command, arguments) = packet.split_at(packet.len().min(1));
+
+        let reply = match command {
+            "?" => self.last_stop.clone(),
+            "g" => read_registers(cpu),
+            "G" => match write_registers(cpu, arguments) {
+                Some(()) => "OK".to_string(),
+                None => "E01".to_string(),
+            },
+            "p" => match usize::from_str_radix(arguments, 16) {
+                Ok(register) if register < REGISTER_COUNT => {
+                    read_registers(cpu)[register * 2..][..register_width(register)].to_string()
+                }
+                _ => "E01".to_string(),
+            },
+            "P" => match write_register(cpu, arguments) {
+                Some(()) => "OK".to_string(),
+                None => "E01".to_string(),
+            },
+            "m" => match parse_memory_range(arguments) {
+                Some((address, length)) => (0..length)
+                    .map(|i| format!("{:02x}", cpu.bus.peek(address.wrapping_add(i))))
+                    .collect(),
+                None => "E01".to_string(),
+            },
+            "M" => match write_memory(cpu, arguments) {
+                Some(()) => "OK".to_string(),
+                None => "E01".to_string(),
+            },
+            "Z" | "z" => match self.set_breakpoint(arguments, command == "Z") {
+                Some(true) => "OK".to_string(),
+                // Unsupported type
+                Some(false) => String::new(),
+                None => "E01".to_string(),
+            },
+            "c" | "s" => {
+                if let Ok(address) = u16::from_str_radix(arguments, 16) {
+                    cpu.program_counter = address;
+                }
+
+                match self.resume(cpu, step, command == "s")? {
+                    Some(stop) => {
+                        self.last_stop = stop.clone();
+                        stop
+                    }
+                    None => return Ok(None),
+                }
+            }
+            "D" => return Ok(Some(Command::Detach)),
+            "k" => return Ok(Some(Command::Kill)),
+            // Single thread
+            "H" => "OK".to_string(),
+            "q" | "Q" => query(packet),
+            _ => String::new(),
+        };
+
+        Ok(Some(Command::Reply(reply)))
+    }
+
+    // Z0/Z1 breakpoints, Z2 write, Z3 read and Z4 access watchpoints.
+    // Some(false) for unsupported types.
+    fn set_breakpoint(&mut self, arguments: &str, insert: bool) -> Option<bool> {
+        let mut fields = arguments.split(',');
+        let kind = fields.next()?;
+        let address = u16::from_str_radix(fields.next()?, 16).ok()?;
+        let length = u16::from_str_radix(fields.next()?, 16).ok()?;
+
+        let (read, write) = match kind {
+            // Software and hardware breakpoints are the same thing here
+            "0" | "1" => {
+                if insert {
+                    self.breakpoints.insert(address);
+                } else {
+                    self.breakpoints.remove(&address);
+                }
+                return Some(true);
+            }
+            "2" => (false, true),
+            "3" => (true, false),
+            "4" => (true, true),
+            _ => return Some(false),
+        };
+
+        let end = address.saturating_add(length.max(1) - 1);
+        let watchpoint = Watchpoint::new(address, end, read, write);
+
+        if insert {
+            self.watchpoints.push(watchpoint);
+        } else {
+            self.watchpoints.retain(|w| *w != watchpoint);
+        }
+
+        Some(true)
+    }
+
+    // Runs until a stop and returns its reply, or None when the client went away
+    fn resume<B, F>(
+        &mut self,
+        cpu: &mut CPU<B>,
+        step: &mut F,
+        single_step: bool,
+    ) -> Result<Option<String>, String>
+    where
+        B: Memory + CpuBus,
+        F: FnMut(&mut CPU<B>),
+    {
+        let mut steps: usize = 0;
+
+        loop {
+            if cpu.halted {
+                return Ok(Some(format!("S{:02x}", SIGILL)));
+            }
+
+            let watching = !self.watchpoints.is_empty();
+            cpu.bus.record_accesses(watching);
+
+            step(cpu);
+
+            if watching {
+                let access = cpu
+                    .bus
+                    .accesses()
+                    .iter()
+                    .find(|access| self.watchpoints.iter().any(|w| w.matches(access)))
+                    .copied();
+                cpu.bus.record_accesses(false);
+
+                if let Some(access) = access {
+                    return Ok(Some(self.watchpoint_stop(&access)));
+                }
+            }
+
+            if cpu.halted {
+                return Ok(Some(format!("S{:02x}", SIGILL)));
+            }
+
+            if single_step || self.breakpoints.contains(&cpu.program_counter) {
+                return Ok(Some(format!("S{:02x}", SIGTRAP)));
+            }
+
+            steps += 1;
+            if steps.is_multiple_of(INTERRUPT_POLL_INTERVAL) {
+                match self.poll_interrupt()? {
+                    Some(true) => return Ok(Some(format!("S{:02x}", SIGINT))),
+                    Some(false) => {}
+                    None => return Ok(None),
+                }
+            }
+        }
+    }
+
+    fn watchpoint_stop(&self, access: &MemoryAccess) -> String {
+        // Reported with the kind of the watchpoint that fired
+        let watchpoint = self.watchpoints.iter().find(|w| w.matches(access));
+        let kind = match watchpoint {
+            Some(w) if w.read && w.write => "awatch",
+            _ if access.kind == AccessKind::Read => "rwatch",
+            _ => "watch",
+        };
+
+        format!("T{:02x}{}:{:04x};", SIGTRAP, kind, access.address)
+    }
+
+    // Whether the client sent a Ctrl-C, None once it has disconnected
+    fn poll_interrupt(&mut self) -> Result<Option<bool>, String> {
+        self.stream
+            .set_nonblocking(true)
+            .map_err(|e| e.to_string())?;
+
+        let mut byte = [0];
+        let result = self.stream.read(&mut byte);
+
+        self.stream
+            .set_nonblocking(false)
+            .map_err(|e| e.to_string())?;
+
+        match result {
+            Ok(0) => Ok(None),
+            Ok(_) => Ok(Some(byte[0] == 0x03)),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(Some(false)),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    fn read_byte(&mut self) -> Result<Option<u8>, String> {
+        let mut byte = [0];
+
+        match self.stream.read(&mut byte) {
+            Ok(0) => Ok(None),
+            Ok(_) => Ok(Some(byte[0])),
+            Err(e) => Err(format!("GDB connection failed: {e}")),
+        }
+    }
+
+    // Next `$data#checksum` packet, acknowledged with a +. Acks from the
+    // client are skipped: TCP doesn't lose packets, so nothing is resent.
+    fn read_packet(&mut self) -> Result<Option<String>, String> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'$') => break,
+                // A Ctrl-C while stopped, there's nothing to interrupt
+                Some(_) => {}
+            }
+        }
+
+        let mut data = vec![];
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'#') => break,
+                Some(byte) => data.push(byte),
+            }
+        }
+
+        // The checksum
+        for _ in 0..2 {
+            if self.read_byte()?.is_none() {
+                return Ok(None);
+            }
+        }
+
+        self.stream
+            .write_all(b"+")
+            .map_err(|e| format!("GDB connection failed: {e}"))?;
+
+        Ok(Some(String::from_utf8_lossy(&data).into_owned()))
+    }
+
+    fn send(&mut self, data: &str) -> Result<(), String> {
+        let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        let packet = format!("${}#{:02x}", data, checksum);
+
+        self.stream
+            .write_all(packet.as_bytes())
+            .map_err(|e| format!("GDB connection failed: {e}"))
+    }
+}
+
+fn query(packet: &str) -> String {
+    let name = packet.split([':', ',']).next().unwrap_or_default();
+
+    match name {
+        "qSupported" => "PacketSize=1000".to_string(),
+        "qAttached" => "1".to_string(),
+        "qC" => "QC1".to_string(),
+        "qfThreadInfo" => "m1".to_string(),
+        "qsThreadInfo" => "l".to_string(),
+        _ => String::new(),
+    }
+}
+
+fn register_width(register: usize) -> usize {
+    if register == PC_REGISTER {
+        4
+    } else {
+        2
+    }
+}
+
+fn read_registers<B: Memory + CpuBus>(cpu: &CPU<B>) -> String {
+    let [pc_lo, pc_hi] = cpu.program_counter.to_le_bytes();
+
+    [
+        cpu.register_a,
+        cpu.register_x,
+        cpu.register_y,
+        cpu.processor_status,
+        cpu.stack_pointer,
+        pc_lo,
+        pc_hi,
+    ]
+    .iter()
+    .map(|byte| format!("{:02x}", byte))
+    .collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn set_register<B: Memory + CpuBus>(cpu: &mut CPU<B>, register: usize, value: &[u8]) {
+    match register {
+        0 => cpu.register_a = value[0],
+        1 => cpu.register_x = value[0],
+        2 => cpu.register_y = value[0],
+        3 => cpu.processor_status = value[0],
+        4 => cpu.stack_pointer = value[0],
+        _ => cpu.program_counter = u16::from_le_bytes([value[0], value[1]]),
+    }
+}
+
+fn write_registers<B: Memory + CpuBus>(cpu: &mut CPU<B>, hex: &str) -> Option<()> {
+    let bytes = decode_hex(hex)?;
+
+    if bytes.len() != REGISTER_COUNT + 1 {
+        return None;
+    }
+
+    for register in 0..REGISTER_COUNT {
+        set_register(cpu, register, &bytes[register..]);
+    }
+
+    Some(())
+}
+
+fn write_register<B: Memory + CpuBus>(cpu: &mut CPU<B>, arguments: &str) -> Option<()> {
+    let (register, value) = arguments.split_once('=')?;
+    let register = usize::from_str_radix(register, 16).ok()?;
+    let value = decode_hex(value)?;
+
+    if register >= REGISTER_COUNT || value.len() != register_width(register) / 2 {
+        return None;
+    }
+
+    set_register(cpu, register, &value);
+    Some(())
+}
+
+fn parse_memory_range(arguments: &str) -> Option<(u16, u16)> {
+    let (address, length) = arguments.split_once(',')?;
+
+    Some((
+        u16::from_str_radix(address, 16).ok()?,
+        u16::from_str_radix(length, 16).ok()?,
+    ))
+}
+
+fn write_memory<B: Memory + CpuBus>(cpu: &mut CPU<B>, arguments: &str) -> Option<()> {
+    let (range, data) = arguments.split_once(':')?;
+    let (address, length) = parse_memory_range(range)?;
+    let data = decode_hex(data)?;
+
+    if data.len() != length as usize {
+        return None;
+    }
+
+    for (i, value) in data.into_iter().enumerate() {
+        cpu.bus.poke(address.wrapping_add(i as u16), value);
+    }
+
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::IrqSource;
+    use std::thread;
+
+    struct TestBus {
+        memory: Vec<u8>,
+        accesses: Option<Vec<MemoryAccess>>,
+    }
+
+    impl Memory for TestBus {
+        fn mem_read(&mut self, addr: u16) -> u8 {
+            let value = self.memory[addr as usize];
+            if let Some(accesses) = &mut self.accesses {
+                accesses.push(MemoryAccess {
+                    address: addr,
+                    value,
+                    kind: AccessKind::Read,
+                });
+            }
+            value
+        }
+
+        fn mem_write(&mut self, addr: u16, data: u8) {
+            // Like the real bus
+            if let 0x4018..=0x5FFF = addr {
+                panic!("Ignoring mem write-access at {:04X}", addr);
+            }
+
+            if let Some(accesses) = &mut self.accesses {
+                accesses.push(MemoryAccess {
+                    address: addr,
+                    value: data,
+                    kind: AccessKind::Write,
+                });
+            }
+            self.memory[addr as usize] = data;
+        }
+
+        fn peek(&self, addr: u16) -> u8 {
+            self.memory[addr as usize]
+        }
+
+        fn poke(&mut self, addr: u16, data: u8) {
+            self.memory[addr as usize] = data;
+        }
+
+        fn record_accesses(&mut self, enabled: bool) {
+            self.accesses = enabled.then(Vec::new);
+        }
+
+        fn accesses(&self) -> &[MemoryAccess] {
+            self.accesses.as_deref().unwrap_or_default()
+        }
+    }
+
+    impl CpuBus for TestBus {
+        fn poll_nmi_status(&mut self) -> Option<u8> {
+            None
+        }
+
+        fn poll_irq_status(&mut self) -> IrqSource {
+            IrqSource::empty()
+        }
+    }
+
+    struct Client(TcpStream);
+
+    impl Client {
+        // Sends a packet and returns the reply
+        fn request(&mut self, data: &str) -> String {
+            let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+            write!(self.0, "${}#{:02x}", data, checksum).unwrap();
+
+            let mut reply = vec![];
+            let mut byte = [0];
+            loop {
+                self.0.read_exact(&mut byte).unwrap();
+                match byte[0] {
+                    b'+' | b'$' => {}
+                    b'#' => break,
+                    b => reply.push(b),
+                }
+            }
+            // Checksum
+            self.0.read_exact(&mut [0; 2]).unwrap();
+
+            String::from_utf8(reply).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_session() {
+        let mut memory = vec![0; 0x10000];
+        memory[0x8000..0x8008].copy_from_slice(&[
+            0xA9, 0x42, // LDA #$42
+            0x8D, 0x00, 0x02, // STA $0200
+            0xE8, // INX
+            0x02, // JAM
+            0xEA,
+        ]);
+
+        let mut cpu = CPU::new(TestBus {
+            memory,
+            accesses: None,
+        });
+        cpu.program_counter = 0x8000;
+        cpu.processor_status = 0x24;
+        cpu.stack_pointer = 0xFD;
+
+        let server = GdbServer::bind(0).unwrap();
+        let address = server.local_addr().unwrap();
+
+        let handle = thread::spawn(move || {
+            let mut session = server.accept().unwrap();
+            session
+                .run(&mut cpu, |cpu| {
+                    cpu.run();
+                })
+                .unwrap();
+            cpu
+        });
+
+        let stream = TcpStream::connect(address).unwrap();
+        stream.set_nodelay(true).unwrap();
+        let mut client = Client(stream);
+
+        assert_eq!(client.request("g"), "00000024fd0080");
+        assert_eq!(client.request("s"), "S05");
+        assert_eq!(client.request("p0"), "42");
+
+        assert_eq!(client.request("Z2,200,1"), "OK");
+        assert_eq!(client.request("c"), "T05watch:0200;");
+        assert_eq!(client.request("m200,1"), "42");
+        assert_eq!(client.request("z2,200,1"), "OK");
+
+        assert_eq!(client.request("Z0,8006,1"), "OK");
+        assert_eq!(client.request("c"), "S05");
+        assert_eq!(client.request("p5"), "0680");
+        assert_eq!(client.request("p1"), "01");
+
+        // JAM
+        assert_eq!(client.request("c"), "S04");
+        assert_eq!(client.request("?"), "S04");
+
+        assert_eq!(client.request("M300,2:abcd"), "OK");
+        assert_eq!(client.request("m300,2"), "abcd");
+        // Unmapped on the NES, CPU writes there panic
+        assert_eq!(client.request("M5000,1:ff"), "OK");
+        assert_eq!(client.request("P2=99"), "OK");
+        assert_eq!(client.request("D"), "OK");
+
+        let cpu = handle.join().unwrap();
+        assert_eq!(cpu.register_y, 0x99);
+        assert_eq!(cpu.bus.memory[0x0301], 0xCD);
+        assert_eq!(cpu.bus.memory[0x5000], 0xFF);
+    }
+}
@@ -3,7 +3,7 @@ use serde::{Deserialize, Serialize};
 
 bitflags! {
     // https://wiki.nesdev.com/w/index.php/Controller_reading_code
-    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
     #[repr(transparent)]
     pub struct JoypadButton: u8 {
         const RIGHT             = 0b10000000;
@@ -17,7 +17,7 @@ bitflags! {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Joypad {
     strobe: bool,
     button_index: u8,
@@ -45,16 +45,21 @@ impl Joypad {
     }
 
     pub fn read(&mut self) -> u8 {
-        if self.button_index > 7 {
-            return 1;
-        }
-        let response = (self.button_status.bits() & (1 << self.button_index)) >> self.button_index;
+        let response = self.peek();
         if !self.strobe && self.button_index <= 7 {
             self.button_index += 1;
         }
         response
     }
 
+    // The bit the next read returns, without shifting to the following button
+    pub fn peek(&self) -> u8 {
+        if self.button_index > 7 {
+            return 1;
+        }
+        (self.button_status.bits() & (1 << self.button_index)) >> self.button_index
+    }
+
     pub fn set_button_pressed_status(&mut self, button: JoypadButton, pressed: bool) {
         self.button_status.set(button, pressed);
     }
@@ -90,6 +95,22 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_peek_does_not_shift() {
+        let mut joypad = Joypad::new();
+        joypad.set_button_pressed_status(JoypadButton::BUTTON_A, true);
+        joypad.write(1);
+        joypad.write(0);
+
+        assert_eq!(joypad.peek(), 1);
+        assert_eq!(joypad.peek(), 1);
+        assert_eq!(joypad.read(), 1);
+
+        // Button B
+        assert_eq!(joypad.peek(), 0);
+        assert_eq!(joypad.button_index, 1);
+    }
+
     #[test]
     fn test_write_strobe() {
         let mut joypad = Joypad::new();
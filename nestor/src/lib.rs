@@ -1,19 +1,42 @@
+mod apu;
 mod bus;
+mod cdl;
 mod cpu;
+mod debugger;
+pub mod disassembler;
+mod gdb;
 mod joypad;
 mod mapper;
 mod mappers;
 mod nes;
+#[cfg(feature = "ntsc")]
+mod ntsc;
 mod opcodes;
+mod overscan;
 mod ppu;
+mod region;
 mod rom;
+mod symbols;
 mod trace;
 
+pub use bus::{AccessKind, IrqSource, MemoryAccess};
+pub use cdl::CodeDataLogger;
+pub use debugger::{Debugger, StopReason, Watchpoint};
+pub use gdb::{GdbServer, GdbSession};
 pub use joypad::JoypadButton;
-pub use nes::PlayerJoypad;
 pub use nes::NES;
-pub use ppu::frame;
-pub use rom::ROM;
+pub use nes::{MemoryRegion, PlayerJoypad};
+#[cfg(feature = "ntsc")]
+pub use ntsc::{NtscFilter, NtscSettings, NtscSignal};
+pub use overscan::{Overscan, PixelAspect};
+pub use ppu::events::{PpuEvent, PpuEventKind, PpuEventLogger};
+pub use ppu::frame::{self, DisplayFrame, PixelFormat};
+pub use ppu::palette::{BuiltinPalette, Palette};
+pub use ppu::sprite::Sprite;
+pub use region::Region;
+pub use rom::{ConsoleType, HeaderFormat, Mirroring, RomHeader, Timing, ROM};
+pub use symbols::Symbols;
+pub use trace::{TraceFormat, TraceLogger};
 
 #[macro_use]
 extern crate lazy_static;
@@ -1,4 +1,57 @@
+use serde::de::DeserializeOwned;
+
+use crate::rom::Mirroring;
+
 pub trait Mapper {
     fn read(&self, address: u16) -> u8;
     fn write(&mut self, address: u16, value: u8);
+
+    // What `read` would return, without side effects. Only boards whose
+    // reads change state need to override it.
+    fn peek(&self, address: u16) -> u8 {
+        self.read(address)
+    }
+    fn mirroring(&self) -> Mirroring;
+
+    // Where the byte at CPU `address` lives in the PRG-ROM with the current
+    // banking, None outside of PRG-ROM
+    fn prg_rom_offset(&self, _address: u16) -> Option<usize> {
+        None
+    }
+
+    // Where the byte at PPU `address` lives in the CHR memory
+    fn chr_rom_offset(&self, _address: u16) -> Option<usize> {
+        None
+    }
+
+    // Called for every address the PPU puts on its bus, along with a
+    // monotonically increasing PPU cycle count. Mappers such as the MMC3
+    // watch these to clock their scanline counters.
+    fn ppu_address(&mut self, _address: u16, _ppu_cycle: u64) {}
+
+    // Level of the cartridge IRQ line
+    fn irq_pending(&self) -> bool {
+        false
+    }
+
+    // PRG-RAM mapped at $6000-$7FFF, empty for boards without any
+    fn prg_ram(&self) -> &[u8] {
+        &[]
+    }
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
+
+    // CHR-ROM, or CHR-RAM on boards without any
+    fn chr(&self) -> &[u8];
+    fn chr_mut(&mut self) -> &mut [u8];
+
+    // Bank registers, PRG-RAM and any other internal state, for save states.
+    // Neither the PRG-ROM nor the CHR memory is included.
+    fn save_state(&self) -> Vec<u8>;
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String>;
+}
+
+pub fn decode_state<M: DeserializeOwned>(data: &[u8]) -> Result<M, String> {
+    bincode::deserialize(data).map_err(|e| format!("Invalid mapper state: {e}"))
 }
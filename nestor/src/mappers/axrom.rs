@@ -0,0 +1,172 @@
+use serde::{Deserialize, Serialize};
+
+use crate::mapper::{self, Mapper};
+use crate::rom::Mirroring;
+
+const PRG_BANK_SIZE: usize = 0x8000;
+
+// https://www.nesdev.org/wiki/AxROM
+#[derive(Serialize, Deserialize)]
+pub struct AxROM {
+    #[serde(skip)]
+    chr_rom: Vec<u8>,
+    #[serde(skip)]
+    prg_rom: Vec<u8>,
+    prg_bank: usize,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+}
+
+impl AxROM {
+    pub fn new(prg_rom: &[u8], chr_rom: &[u8], bus_conflicts: bool) -> Self {
+        Self {
+            prg_rom: prg_rom.to_vec(),
+            chr_rom: chr_rom.to_vec(),
+            prg_bank: 0,
+            mirroring: Mirroring::SingleScreenA,
+            bus_conflicts,
+        }
+    }
+}
+
+impl AxROM {
+    fn prg_rom_index(&self, address: u16) -> usize {
+        let index = self.prg_bank * PRG_BANK_SIZE + (address as usize & 0x7FFF);
+        index % self.prg_rom.len()
+    }
+
+    fn chr_rom_index(&self, address: u16) -> usize {
+        address as usize % self.chr_rom.len()
+    }
+}
+
+impl Mapper for AxROM {
+    fn read(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1FFF => self.chr_rom[self.chr_rom_index(address)],
+            0x8000..=0xFFFF => self.prg_rom[self.prg_rom_index(address)],
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, address: u16, val: u8) {
+        match address {
+            // CHR-RAM
+            0x0000..=0x1FFF => {
+                let len = self.chr_rom.len();
+                self.chr_rom[address as usize % len] = val;
+            }
+            0x8000..=0xFFFF => {
+                let val = if self.bus_conflicts {
+                    val & self.read(address)
+                } else {
+                    val
+                };
+
+                // 7  bit  0
+                // ---- ----
+                // xxxM xPPP
+                //    |  |||
+                //    |  +++- Select 32 KB PRG ROM bank for CPU $8000-$FFFF
+                //    +------ Select 1 KB VRAM page for all 4 nametables
+                let bank_count = (self.prg_rom.len() / PRG_BANK_SIZE).max(1);
+                self.prg_bank = (val & 0x07) as usize % bank_count;
+                self.mirroring = if val & 0x10 == 0 {
+                    Mirroring::SingleScreenA
+                } else {
+                    Mirroring::SingleScreenB
+                };
+            }
+            _ => {}
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring.clone()
+    }
+
+    fn prg_rom_offset(&self, address: u16) -> Option<usize> {
+        (address >= 0x8000).then(|| self.prg_rom_index(address))
+    }
+
+    fn chr_rom_offset(&self, address: u16) -> Option<usize> {
+        (address < 0x2000 && !self.chr_rom.is_empty()).then(|| self.chr_rom_index(address))
+    }
+
+    fn chr(&self) -> &[u8] {
+        &self.chr_rom
+    }
+    fn chr_mut(&mut self) -> &mut [u8] {
+        &mut self.chr_rom
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let state: AxROM = mapper::decode_state(data)?;
+
+        *self = AxROM {
+            prg_rom: std::mem::take(&mut self.prg_rom),
+            chr_rom: std::mem::take(&mut self.chr_rom),
+            ..state
+        };
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_prg_rom() -> Vec<u8> {
+        let mut prg_rom = vec![0; 8 * PRG_BANK_SIZE];
+        for (bank, chunk) in prg_rom.chunks_mut(PRG_BANK_SIZE).enumerate() {
+            chunk.fill(bank as u8);
+        }
+        prg_rom
+    }
+
+    #[test]
+    fn test_bank_switching() {
+        let mut mapper = AxROM::new(&test_prg_rom(), &[0; 0x2000], false);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenA);
+
+        mapper.write(0x8000, 0x15);
+        assert_eq!(mapper.read(0x8000), 5);
+        assert_eq!(mapper.read(0xFFFF), 5);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenB);
+
+        mapper.write(0x8000, 0x02);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenA);
+    }
+
+    #[test]
+    fn test_small_prg_rom() {
+        // 16 KB is mirrored over the whole window
+        let mut prg_rom = vec![0; 0x4000];
+        prg_rom[0] = 0x42;
+
+        let mapper = AxROM::new(&prg_rom, &[0; 0x2000], false);
+        assert_eq!(mapper.read(0xC000), 0x42);
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        let mut prg_rom = test_prg_rom();
+        // Bank 0 holds 0x03 at $8010
+        prg_rom[0x10] = 0x03;
+
+        let mut mapper = AxROM::new(&prg_rom, &[0; 0x2000], true);
+        mapper.write(0x8010, 0x16);
+        assert_eq!(mapper.read(0x8000), 2);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenA);
+
+        let mut mapper = AxROM::new(&prg_rom, &[0; 0x2000], false);
+        mapper.write(0x8010, 0x16);
+        assert_eq!(mapper.read(0x8000), 6);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenB);
+    }
+}
@@ -1,40 +1,55 @@
-use crate::mapper::Mapper;
+use serde::{Deserialize, Serialize};
 
+use crate::mapper::{self, Mapper};
+use crate::rom::Mirroring;
+
+#[derive(Serialize, Deserialize)]
 pub struct CNROM {
+    #[serde(skip)]
     chr_rom: Vec<u8>,
+    #[serde(skip)]
     prg_rom: Vec<u8>,
     chr_bank: usize,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
 }
 
 impl CNROM {
-    pub fn new(prg_rom: &[u8], chr_rom: &[u8]) -> Self {
+    pub fn new(prg_rom: &[u8], chr_rom: &[u8], mirroring: Mirroring, bus_conflicts: bool) -> Self {
         Self {
             prg_rom: prg_rom.to_vec(),
             chr_rom: chr_rom.to_vec(),
             chr_bank: 0,
+            mirroring,
+            bus_conflicts,
         }
     }
 }
 
+impl CNROM {
+    fn prg_rom_index(&self, address: u16) -> usize {
+        let mut bank = address as usize - 0x8000;
+        if self.prg_rom.len() == 16384 {
+            bank %= 16384;
+        }
+        bank
+    }
+
+    fn chr_rom_index(&self, address: u16) -> usize {
+        let chr_bank_size = 8192;
+        let bank_offset = self.chr_bank * chr_bank_size;
+        bank_offset | address as usize & 0x1fff
+    }
+}
+
 impl Mapper for CNROM {
     fn read(&self, address: u16) -> u8 {
         match address {
             // CHR-ROM
-            0x0000..=0x1fff => {
-                let chr_bank_size = 8192;
-                let bank_offset = self.chr_bank * chr_bank_size;
-                let index = bank_offset | address as usize & 0x1fff;
-                self.chr_rom[index]
-            }
+            0x0000..=0x1fff => self.chr_rom[self.chr_rom_index(address)],
 
             // PRG-ROM
-            0x8000..=0xffff => {
-                let mut bank = address as usize - 0x8000;
-                if self.prg_rom.len() == 16384 {
-                    bank %= 16384;
-                }
-                self.prg_rom[bank]
-            }
+            0x8000..=0xffff => self.prg_rom[self.prg_rom_index(address)],
 
             _ => 0,
         }
@@ -44,14 +59,18 @@ impl Mapper for CNROM {
         match address {
             // CHR-ROM
             0x0000..=0x1fff => {
-                let chr_bank_size = 8192;
-                let bank_offset = self.chr_bank * chr_bank_size;
-                let index = bank_offset | address as usize & 0x1fff;
+                let index = self.chr_rom_index(address);
                 self.chr_rom[index] = val;
             }
 
             // PRG-ROM
             0x8000..=0xffff => {
+                let val = if self.bus_conflicts {
+                    val & self.read(address)
+                } else {
+                    val
+                };
+
                 // CNROM only uses the first 2 bits, but other boards may use
                 // the rest, apparently.
                 self.chr_bank = (val & 0x03) as usize;
@@ -59,4 +78,39 @@ impl Mapper for CNROM {
             _ => {}
         }
     }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring.clone()
+    }
+
+    fn prg_rom_offset(&self, address: u16) -> Option<usize> {
+        (address >= 0x8000).then(|| self.prg_rom_index(address))
+    }
+
+    fn chr_rom_offset(&self, address: u16) -> Option<usize> {
+        (address < 0x2000 && !self.chr_rom.is_empty()).then(|| self.chr_rom_index(address))
+    }
+
+    fn chr(&self) -> &[u8] {
+        &self.chr_rom
+    }
+    fn chr_mut(&mut self) -> &mut [u8] {
+        &mut self.chr_rom
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let state: CNROM = mapper::decode_state(data)?;
+
+        *self = CNROM {
+            prg_rom: std::mem::take(&mut self.prg_rom),
+            chr_rom: std::mem::take(&mut self.chr_rom),
+            ..state
+        };
+
+        Ok(())
+    }
 }
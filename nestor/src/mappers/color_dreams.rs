@@ -0,0 +1,162 @@
+use serde::{Deserialize, Serialize};
+
+use crate::mapper::{self, Mapper};
+use crate::rom::Mirroring;
+
+const PRG_BANK_SIZE: usize = 0x8000;
+const CHR_BANK_SIZE: usize = 0x2000;
+
+// https://www.nesdev.org/wiki/Color_Dreams
+#[derive(Serialize, Deserialize)]
+pub struct ColorDreams {
+    #[serde(skip)]
+    chr_rom: Vec<u8>,
+    #[serde(skip)]
+    prg_rom: Vec<u8>,
+    prg_bank: usize,
+    chr_bank: usize,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+}
+
+impl ColorDreams {
+    pub fn new(prg_rom: &[u8], chr_rom: &[u8], mirroring: Mirroring, bus_conflicts: bool) -> Self {
+        Self {
+            prg_rom: prg_rom.to_vec(),
+            chr_rom: chr_rom.to_vec(),
+            prg_bank: 0,
+            chr_bank: 0,
+            mirroring,
+            bus_conflicts,
+        }
+    }
+}
+
+impl ColorDreams {
+    fn prg_rom_index(&self, address: u16) -> usize {
+        let index = self.prg_bank * PRG_BANK_SIZE + (address as usize & 0x7FFF);
+        index % self.prg_rom.len()
+    }
+
+    fn chr_rom_index(&self, address: u16) -> usize {
+        let index = self.chr_bank * CHR_BANK_SIZE + address as usize;
+        index % self.chr_rom.len()
+    }
+}
+
+impl Mapper for ColorDreams {
+    fn read(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1FFF => self.chr_rom[self.chr_rom_index(address)],
+            0x8000..=0xFFFF => self.prg_rom[self.prg_rom_index(address)],
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, address: u16, val: u8) {
+        if let 0x8000..=0xFFFF = address {
+            let val = if self.bus_conflicts {
+                val & self.read(address)
+            } else {
+                val
+            };
+
+            // 7  bit  0
+            // ---- ----
+            // CCCC LLPP
+            // |||| ||||
+            // |||| ||++- Select 32 KB PRG ROM bank for CPU $8000-$FFFF
+            // |||| ++--- Used for lockout defeat
+            // ++++------ Select 8 KB CHR ROM bank for PPU $0000-$1FFF
+            self.prg_bank = (val & 0x03) as usize;
+            self.chr_bank = (val >> 4) as usize;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring.clone()
+    }
+
+    fn prg_rom_offset(&self, address: u16) -> Option<usize> {
+        (address >= 0x8000).then(|| self.prg_rom_index(address))
+    }
+
+    fn chr_rom_offset(&self, address: u16) -> Option<usize> {
+        (address < 0x2000 && !self.chr_rom.is_empty()).then(|| self.chr_rom_index(address))
+    }
+
+    fn chr(&self) -> &[u8] {
+        &self.chr_rom
+    }
+    fn chr_mut(&mut self) -> &mut [u8] {
+        &mut self.chr_rom
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let state: ColorDreams = mapper::decode_state(data)?;
+
+        *self = ColorDreams {
+            prg_rom: std::mem::take(&mut self.prg_rom),
+            chr_rom: std::mem::take(&mut self.chr_rom),
+            ..state
+        };
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every byte holds its bank number
+    fn test_rom(size: usize, bank_size: usize) -> Vec<u8> {
+        let mut rom = vec![0; size];
+        for (bank, chunk) in rom.chunks_mut(bank_size).enumerate() {
+            chunk.fill(bank as u8);
+        }
+        rom
+    }
+
+    #[test]
+    fn test_bank_switching() {
+        let mut mapper = ColorDreams::new(
+            &test_rom(4 * PRG_BANK_SIZE, PRG_BANK_SIZE),
+            &test_rom(16 * CHR_BANK_SIZE, CHR_BANK_SIZE),
+            Mirroring::Vertical,
+            false,
+        );
+
+        // PRG bank 2, CHR bank 3
+        mapper.write(0x8000, 0x32);
+        assert_eq!(mapper.read(0x8000), 2);
+        assert_eq!(mapper.read(0xFFFF), 2);
+        assert_eq!(mapper.read(0x0000), 3);
+        assert_eq!(mapper.read(0x1FFF), 3);
+
+        // Mirroring is soldered on the board
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        let mut prg_rom = test_rom(4 * PRG_BANK_SIZE, PRG_BANK_SIZE);
+        // PRG bank 3, CHR bank 1 in ROM at $8010
+        prg_rom[0x10] = 0x13;
+        let chr_rom = test_rom(16 * CHR_BANK_SIZE, CHR_BANK_SIZE);
+
+        let mut mapper = ColorDreams::new(&prg_rom, &chr_rom, Mirroring::Vertical, true);
+        mapper.write(0x8010, 0x32);
+        assert_eq!(mapper.read(0x8000), 2);
+        assert_eq!(mapper.read(0x0000), 1);
+
+        let mut mapper = ColorDreams::new(&prg_rom, &chr_rom, Mirroring::Vertical, false);
+        mapper.write(0x8010, 0x32);
+        assert_eq!(mapper.read(0x8000), 2);
+        assert_eq!(mapper.read(0x0000), 3);
+    }
+}
@@ -0,0 +1,322 @@
+use serde::{Deserialize, Serialize};
+
+use crate::mapper::{self, Mapper};
+use crate::rom::Mirroring;
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x1000;
+
+// https://www.nesdev.org/wiki/MMC1
+#[derive(Serialize, Deserialize)]
+pub struct MMC1 {
+    #[serde(skip)]
+    chr_rom: Vec<u8>,
+    #[serde(skip)]
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+
+    shift_register: u8,
+    write_count: u8,
+
+    // 4bit0
+    // -----
+    // CPPMM
+    // |||||
+    // |||++- Mirroring (0: one-screen, lower bank; 1: one-screen, upper bank;
+    // |||               2: vertical; 3: horizontal)
+    // |++--- PRG ROM bank mode (0, 1: switch 32 KB at $8000, ignoring low bit of bank number;
+    // |                         2: fix first bank at $8000 and switch 16 KB bank at $C000;
+    // |                         3: fix last bank at $C000 and switch 16 KB bank at $8000)
+    // +----- CHR ROM bank mode (0: switch 8 KB at a time; 1: switch two separate 4 KB banks)
+    control: u8,
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    prg_bank: u8,
+}
+
+impl MMC1 {
+    pub fn new(prg_rom: &[u8], chr_rom: &[u8], prg_ram_size: usize) -> Self {
+        Self {
+            prg_rom: prg_rom.to_vec(),
+            chr_rom: chr_rom.to_vec(),
+            prg_ram: vec![0; prg_ram_size],
+            shift_register: 0,
+            write_count: 0,
+            // The last bank is fixed at $C000 on power-up
+            control: 0x0C,
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.prg_bank & 0x10 == 0 && !self.prg_ram.is_empty()
+    }
+
+    // SUROM (512 KB) boards use bit 4 of the CHR bank register to select
+    // which 256 KB half of the PRG-ROM is visible.
+    fn prg_outer_bank(&self) -> usize {
+        if self.prg_rom.len() > 0x40000 {
+            (self.chr_bank_0 & 0x10) as usize
+        } else {
+            0
+        }
+    }
+
+    fn prg_rom_index(&self, address: u16) -> usize {
+        let bank_count = (self.prg_rom.len() / PRG_BANK_SIZE).max(1);
+        let bank = (self.prg_bank & 0x0F) as usize;
+        let offset = address as usize & 0x3FFF;
+
+        let bank = match (self.control >> 2) & 0x03 {
+            0 | 1 => (bank & 0x0E) | ((address as usize >> 14) & 0x01),
+            2 => {
+                if address < 0xC000 {
+                    0
+                } else {
+                    bank
+                }
+            }
+            _ => {
+                if address < 0xC000 {
+                    bank
+                } else {
+                    0x0F
+                }
+            }
+        };
+
+        let bank = (self.prg_outer_bank() | bank) % bank_count;
+        bank * PRG_BANK_SIZE + offset
+    }
+
+    fn chr_rom_index(&self, address: u16) -> usize {
+        let offset = address as usize & 0x0FFF;
+
+        let bank = if self.control & 0x10 == 0 {
+            (self.chr_bank_0 & 0x1E) as usize | (address as usize >> 12)
+        } else if address < 0x1000 {
+            self.chr_bank_0 as usize
+        } else {
+            self.chr_bank_1 as usize
+        };
+
+        (bank * CHR_BANK_SIZE + offset) % self.chr_rom.len()
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0x8000..=0x9FFF => self.control = value,
+            0xA000..=0xBFFF => self.chr_bank_0 = value,
+            0xC000..=0xDFFF => self.chr_bank_1 = value,
+            _ => self.prg_bank = value,
+        }
+    }
+}
+
+impl Mapper for MMC1 {
+    fn read(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1FFF => {
+                if self.chr_rom.is_empty() {
+                    return 0;
+                }
+                self.chr_rom[self.chr_rom_index(address)]
+            }
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                self.prg_ram[(address as usize - 0x6000) % self.prg_ram.len()]
+            }
+            0x8000..=0xFFFF => self.prg_rom[self.prg_rom_index(address)],
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => {
+                if self.chr_rom.is_empty() {
+                    return;
+                }
+                let index = self.chr_rom_index(address);
+                self.chr_rom[index] = value;
+            }
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(address as usize - 0x6000) % len] = value;
+            }
+            0x8000..=0xFFFF => {
+                // Writing a value with bit 7 set clears the shift register
+                // and locks the PRG-ROM at $C000 to the last bank.
+                if value & 0x80 != 0 {
+                    self.shift_register = 0;
+                    self.write_count = 0;
+                    self.control |= 0x0C;
+                    return;
+                }
+
+                // Bits are shifted in from the MSB side, LSB first
+                self.shift_register = (self.shift_register >> 1) | ((value & 0x01) << 4);
+                self.write_count += 1;
+
+                // On the fifth write the address selects the internal register
+                if self.write_count == 5 {
+                    self.write_register(address, self.shift_register);
+                    self.shift_register = 0;
+                    self.write_count = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0x03 {
+            0 => Mirroring::SingleScreenA,
+            1 => Mirroring::SingleScreenB,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn prg_rom_offset(&self, address: u16) -> Option<usize> {
+        (address >= 0x8000).then(|| self.prg_rom_index(address))
+    }
+
+    fn chr_rom_offset(&self, address: u16) -> Option<usize> {
+        (address < 0x2000 && !self.chr_rom.is_empty()).then(|| self.chr_rom_index(address))
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn chr(&self) -> &[u8] {
+        &self.chr_rom
+    }
+    fn chr_mut(&mut self) -> &mut [u8] {
+        &mut self.chr_rom
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let state: MMC1 = mapper::decode_state(data)?;
+
+        *self = MMC1 {
+            prg_rom: std::mem::take(&mut self.prg_rom),
+            chr_rom: std::mem::take(&mut self.chr_rom),
+            ..state
+        };
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_serial(mapper: &mut MMC1, address: u16, value: u8) {
+        for i in 0..5 {
+            mapper.write(address, (value >> i) & 0x01);
+        }
+    }
+
+    fn test_prg_rom(banks: usize) -> Vec<u8> {
+        let mut prg_rom = vec![0; banks * PRG_BANK_SIZE];
+        for (bank, chunk) in prg_rom.chunks_mut(PRG_BANK_SIZE).enumerate() {
+            chunk.fill(bank as u8);
+        }
+        prg_rom
+    }
+
+    #[test]
+    fn test_power_up_fixes_last_bank() {
+        let mapper = MMC1::new(&test_prg_rom(8), &[0; 0x2000], 0x2000);
+
+        assert_eq!(mapper.read(0x8000), 0);
+        assert_eq!(mapper.read(0xC000), 7);
+    }
+
+    #[test]
+    fn test_shift_register_writes_control() {
+        let mut mapper = MMC1::new(&test_prg_rom(8), &[0; 0x2000], 0x2000);
+
+        write_serial(&mut mapper, 0x8000, 0b00010);
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+
+        write_serial(&mut mapper, 0x8000, 0b00011);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+
+        write_serial(&mut mapper, 0x8000, 0b00001);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenB);
+    }
+
+    #[test]
+    fn test_reset_clears_shift_register() {
+        let mut mapper = MMC1::new(&test_prg_rom(8), &[0; 0x2000], 0x2000);
+
+        mapper.write(0x8000, 1);
+        mapper.write(0x8000, 1);
+        mapper.write(0x8000, 0x80);
+
+        write_serial(&mut mapper, 0xE000, 3);
+        assert_eq!(mapper.read(0x8000), 3);
+    }
+
+    #[test]
+    fn test_prg_bank_modes() {
+        let mut mapper = MMC1::new(&test_prg_rom(8), &[0; 0x2000], 0x2000);
+
+        // Mode 3: switch $8000, fix last bank at $C000
+        write_serial(&mut mapper, 0xE000, 2);
+        assert_eq!(mapper.read(0x8000), 2);
+        assert_eq!(mapper.read(0xC000), 7);
+
+        // Mode 2: fix first bank at $8000, switch $C000
+        write_serial(&mut mapper, 0x8000, 0b01000);
+        assert_eq!(mapper.read(0x8000), 0);
+        assert_eq!(mapper.read(0xC000), 2);
+
+        // Mode 0: switch 32 KB, ignoring the low bit
+        write_serial(&mut mapper, 0x8000, 0b00000);
+        write_serial(&mut mapper, 0xE000, 5);
+        assert_eq!(mapper.read(0x8000), 4);
+        assert_eq!(mapper.read(0xC000), 5);
+    }
+
+    #[test]
+    fn test_chr_4kb_mode() {
+        let mut chr_rom = vec![0; 0x8000];
+        for (bank, chunk) in chr_rom.chunks_mut(CHR_BANK_SIZE).enumerate() {
+            chunk.fill(bank as u8);
+        }
+        let mut mapper = MMC1::new(&test_prg_rom(2), &chr_rom, 0x2000);
+
+        write_serial(&mut mapper, 0x8000, 0b11100);
+        write_serial(&mut mapper, 0xA000, 3);
+        write_serial(&mut mapper, 0xC000, 6);
+
+        assert_eq!(mapper.read(0x0000), 3);
+        assert_eq!(mapper.read(0x1000), 6);
+    }
+
+    #[test]
+    fn test_prg_ram() {
+        let mut mapper = MMC1::new(&test_prg_rom(2), &[0; 0x2000], 0x2000);
+
+        mapper.write(0x6000, 0x42);
+        assert_eq!(mapper.read(0x6000), 0x42);
+
+        // Disable PRG-RAM
+        write_serial(&mut mapper, 0xE000, 0x10);
+        assert_eq!(mapper.read(0x6000), 0);
+    }
+}
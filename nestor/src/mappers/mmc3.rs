@@ -0,0 +1,436 @@
+use serde::{Deserialize, Serialize};
+
+use crate::mapper::{self, Mapper};
+use crate::rom::Mirroring;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+// Number of PPU cycles A12 has to stay low before a rising edge clocks the
+// scanline counter. The real chip filters on M2, roughly 3 CPU cycles.
+const A12_FILTER_CYCLES: u64 = 10;
+
+// https://www.nesdev.org/wiki/MMC3
+#[derive(Serialize, Deserialize)]
+pub struct MMC3 {
+    #[serde(skip)]
+    chr_rom: Vec<u8>,
+    #[serde(skip)]
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+
+    // 7  bit  0
+    // ---- ----
+    // CPMx xRRR
+    // |||   |||
+    // |||   +++- Specify which bank register to update on next write to Bank Data register
+    // |||          000: R0: Select 2 KB CHR bank at PPU $0000-$07FF (or $1000-$17FF)
+    // |||          001: R1: Select 2 KB CHR bank at PPU $0800-$0FFF (or $1800-$1FFF)
+    // |||          010: R2: Select 1 KB CHR bank at PPU $1000-$13FF (or $0000-$03FF)
+    // |||          011: R3: Select 1 KB CHR bank at PPU $1400-$17FF (or $0400-$07FF)
+    // |||          100: R4: Select 1 KB CHR bank at PPU $1800-$1BFF (or $0800-$0BFF)
+    // |||          101: R5: Select 1 KB CHR bank at PPU $1C00-$1FFF (or $0C00-$0FFF)
+    // |||          110: R6: Select 8 KB PRG ROM bank at $8000-$9FFF (or $C000-$DFFF)
+    // |||          111: R7: Select 8 KB PRG ROM bank at $A000-$BFFF
+    // ||+------- Nothing on the MMC3, see MMC6
+    // |+-------- PRG ROM bank mode (0: $8000-$9FFF swappable,
+    // |                                $C000-$DFFF fixed to second-last bank;
+    // |                             1: $C000-$DFFF swappable,
+    // |                                $8000-$9FFF fixed to second-last bank)
+    // +--------- CHR A12 inversion (0: two 2 KB banks at $0000-$0FFF,
+    //                                  four 1 KB banks at $1000-$1FFF;
+    //                               1: two 2 KB banks at $1000-$1FFF,
+    //                                  four 1 KB banks at $0000-$0FFF)
+    bank_select: u8,
+    bank_registers: [u8; 8],
+
+    mirroring: Mirroring,
+    four_screen: bool,
+
+    prg_ram_enabled: bool,
+    prg_ram_write_protected: bool,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+
+    a12: bool,
+    a12_low_since: u64,
+}
+
+impl MMC3 {
+    pub fn new(prg_rom: &[u8], chr_rom: &[u8], prg_ram_size: usize, mirroring: Mirroring) -> Self {
+        Self {
+            prg_rom: prg_rom.to_vec(),
+            chr_rom: chr_rom.to_vec(),
+            prg_ram: vec![0; prg_ram_size],
+            bank_select: 0,
+            bank_registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            four_screen: mirroring == Mirroring::FourScreen,
+            mirroring,
+            prg_ram_enabled: true,
+            prg_ram_write_protected: false,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            a12: false,
+            a12_low_since: 0,
+        }
+    }
+
+    fn prg_rom_index(&self, address: u16) -> usize {
+        let bank_count = (self.prg_rom.len() / PRG_BANK_SIZE).max(1);
+        let second_last = bank_count.saturating_sub(2);
+        let prg_mode = self.bank_select & 0x40 != 0;
+
+        let bank = match (address, prg_mode) {
+            (0x8000..=0x9FFF, false) | (0xC000..=0xDFFF, true) => self.bank_registers[6] as usize,
+            (0x8000..=0x9FFF, true) | (0xC000..=0xDFFF, false) => second_last,
+            (0xA000..=0xBFFF, _) => self.bank_registers[7] as usize,
+            _ => bank_count - 1,
+        };
+
+        let index = (bank % bank_count) * PRG_BANK_SIZE + (address as usize & 0x1FFF);
+        index % self.prg_rom.len()
+    }
+
+    fn chr_rom_index(&self, address: u16) -> usize {
+        // With the inversion bit set the 2 KB and 1 KB halves swap places
+        let address = if self.bank_select & 0x80 != 0 {
+            address ^ 0x1000
+        } else {
+            address
+        };
+
+        let bank = match address {
+            0x0000..=0x07FF => {
+                (self.bank_registers[0] & 0xFE) as usize | (address as usize >> 10 & 1)
+            }
+            0x0800..=0x0FFF => {
+                (self.bank_registers[1] & 0xFE) as usize | (address as usize >> 10 & 1)
+            }
+            0x1000..=0x13FF => self.bank_registers[2] as usize,
+            0x1400..=0x17FF => self.bank_registers[3] as usize,
+            0x1800..=0x1BFF => self.bank_registers[4] as usize,
+            _ => self.bank_registers[5] as usize,
+        };
+
+        (bank * CHR_BANK_SIZE + (address as usize & 0x03FF)) % self.chr_rom.len()
+    }
+
+    // https://www.nesdev.org/wiki/MMC3#IRQ_Specifics
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        let even = address & 0x01 == 0;
+
+        match (address, even) {
+            (0x8000..=0x9FFF, true) => self.bank_select = value,
+            (0x8000..=0x9FFF, false) => {
+                self.bank_registers[(self.bank_select & 0x07) as usize] = value;
+            }
+            (0xA000..=0xBFFF, true) => {
+                if !self.four_screen {
+                    self.mirroring = if value & 0x01 == 0 {
+                        Mirroring::Vertical
+                    } else {
+                        Mirroring::Horizontal
+                    };
+                }
+            }
+            (0xA000..=0xBFFF, false) => {
+                self.prg_ram_enabled = value & 0x80 != 0;
+                self.prg_ram_write_protected = value & 0x40 != 0;
+            }
+            (0xC000..=0xDFFF, true) => self.irq_latch = value,
+            (0xC000..=0xDFFF, false) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            (_, true) => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            (_, false) => self.irq_enabled = true,
+        }
+    }
+}
+
+impl Mapper for MMC3 {
+    fn read(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1FFF => self.chr_rom[self.chr_rom_index(address)],
+            0x6000..=0x7FFF if self.prg_ram_enabled && !self.prg_ram.is_empty() => {
+                self.prg_ram[(address as usize - 0x6000) % self.prg_ram.len()]
+            }
+            0x8000..=0xFFFF => self.prg_rom[self.prg_rom_index(address)],
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => {
+                let index = self.chr_rom_index(address);
+                self.chr_rom[index] = value;
+            }
+            0x6000..=0x7FFF
+                if self.prg_ram_enabled
+                    && !self.prg_ram_write_protected
+                    && !self.prg_ram.is_empty() =>
+            {
+                let len = self.prg_ram.len();
+                self.prg_ram[(address as usize - 0x6000) % len] = value;
+            }
+            0x8000..=0xFFFF => self.write_register(address, value),
+            _ => {}
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring.clone()
+    }
+
+    fn prg_rom_offset(&self, address: u16) -> Option<usize> {
+        (address >= 0x8000).then(|| self.prg_rom_index(address))
+    }
+
+    fn chr_rom_offset(&self, address: u16) -> Option<usize> {
+        (address < 0x2000 && !self.chr_rom.is_empty()).then(|| self.chr_rom_index(address))
+    }
+
+    fn ppu_address(&mut self, address: u16, ppu_cycle: u64) {
+        let a12 = address & 0x1000 != 0;
+
+        if a12 && !self.a12 && ppu_cycle.saturating_sub(self.a12_low_since) >= A12_FILTER_CYCLES {
+            self.clock_irq_counter();
+        } else if !a12 && self.a12 {
+            self.a12_low_since = ppu_cycle;
+        }
+
+        self.a12 = a12;
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn chr(&self) -> &[u8] {
+        &self.chr_rom
+    }
+    fn chr_mut(&mut self) -> &mut [u8] {
+        &mut self.chr_rom
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let state: MMC3 = mapper::decode_state(data)?;
+
+        *self = MMC3 {
+            prg_rom: std::mem::take(&mut self.prg_rom),
+            chr_rom: std::mem::take(&mut self.chr_rom),
+            ..state
+        };
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_mapper() -> MMC3 {
+        let mut prg_rom = vec![0; 16 * PRG_BANK_SIZE];
+        for (bank, chunk) in prg_rom.chunks_mut(PRG_BANK_SIZE).enumerate() {
+            chunk.fill(bank as u8);
+        }
+        let mut chr_rom = vec![0; 64 * CHR_BANK_SIZE];
+        for (bank, chunk) in chr_rom.chunks_mut(CHR_BANK_SIZE).enumerate() {
+            chunk.fill(bank as u8);
+        }
+
+        MMC3::new(&prg_rom, &chr_rom, 0x2000, Mirroring::Vertical)
+    }
+
+    // Simulates one scanline worth of PPU fetches with the background on
+    // $0000 and sprites on $1000, producing a single A12 rising edge.
+    fn scanline(mapper: &mut MMC3, ppu_cycle: &mut u64) {
+        mapper.ppu_address(0x0000, *ppu_cycle);
+        *ppu_cycle += 260;
+        mapper.ppu_address(0x1000, *ppu_cycle);
+        *ppu_cycle += 81;
+    }
+
+    #[test]
+    fn test_prg_bank_modes() {
+        let mut mapper = test_mapper();
+
+        mapper.write(0x8000, 6);
+        mapper.write(0x8001, 3);
+        mapper.write(0x8000, 7);
+        mapper.write(0x8001, 5);
+
+        assert_eq!(mapper.read(0x8000), 3);
+        assert_eq!(mapper.read(0xA000), 5);
+        assert_eq!(mapper.read(0xC000), 14);
+        assert_eq!(mapper.read(0xE000), 15);
+
+        mapper.write(0x8000, 0x40);
+
+        assert_eq!(mapper.read(0x8000), 14);
+        assert_eq!(mapper.read(0xC000), 3);
+    }
+
+    #[test]
+    fn test_small_prg_rom() {
+        // 4 KB is mirrored over every bank
+        let mut prg_rom = vec![0; 0x1000];
+        prg_rom[0x10] = 0x42;
+
+        let mut mapper = MMC3::new(&prg_rom, &[0; 0x2000], 0x2000, Mirroring::Vertical);
+        mapper.write(0x8000, 6);
+        mapper.write(0x8001, 5);
+        assert_eq!(mapper.read(0x8010), 0x42);
+        assert_eq!(mapper.read(0xE010), 0x42);
+    }
+
+    #[test]
+    fn test_chr_inversion() {
+        let mut mapper = test_mapper();
+
+        mapper.write(0x8000, 0);
+        mapper.write(0x8001, 9);
+        mapper.write(0x8000, 2);
+        mapper.write(0x8001, 20);
+
+        assert_eq!(mapper.read(0x0000), 8);
+        assert_eq!(mapper.read(0x0400), 9);
+        assert_eq!(mapper.read(0x1000), 20);
+
+        mapper.write(0x8000, 0x80);
+
+        assert_eq!(mapper.read(0x0000), 20);
+        assert_eq!(mapper.read(0x1000), 8);
+        assert_eq!(mapper.read(0x1400), 9);
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut mapper = test_mapper();
+
+        mapper.write(0xA000, 1);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+
+        mapper.write(0xA000, 0);
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_irq_reload_and_decrement() {
+        let mut mapper = test_mapper();
+        let mut ppu_cycle = 0;
+
+        mapper.write(0xC000, 2); // latch
+        mapper.write(0xC001, 0); // reload
+        mapper.write(0xE001, 0); // enable
+
+        // Reload: counter = 2
+        scanline(&mut mapper, &mut ppu_cycle);
+        assert_eq!(mapper.irq_counter, 2);
+        assert!(!mapper.irq_pending());
+
+        // Decrement: counter = 1
+        scanline(&mut mapper, &mut ppu_cycle);
+        assert_eq!(mapper.irq_counter, 1);
+        assert!(!mapper.irq_pending());
+
+        // Decrement to zero fires the IRQ
+        scanline(&mut mapper, &mut ppu_cycle);
+        assert_eq!(mapper.irq_counter, 0);
+        assert!(mapper.irq_pending());
+
+        // Counter at zero reloads from the latch
+        scanline(&mut mapper, &mut ppu_cycle);
+        assert_eq!(mapper.irq_counter, 2);
+    }
+
+    #[test]
+    fn test_irq_acknowledge() {
+        let mut mapper = test_mapper();
+        let mut ppu_cycle = 0;
+
+        mapper.write(0xC000, 0);
+        mapper.write(0xC001, 0);
+        mapper.write(0xE001, 0);
+
+        // A latch of zero fires on every scanline
+        scanline(&mut mapper, &mut ppu_cycle);
+        assert!(mapper.irq_pending());
+
+        // Writing $E000 acknowledges and disables
+        mapper.write(0xE000, 0);
+        assert!(!mapper.irq_pending());
+
+        scanline(&mut mapper, &mut ppu_cycle);
+        assert!(!mapper.irq_pending());
+    }
+
+    #[test]
+    fn test_irq_disabled_does_not_fire() {
+        let mut mapper = test_mapper();
+        let mut ppu_cycle = 0;
+
+        mapper.write(0xC000, 1);
+        mapper.write(0xC001, 0);
+
+        for _ in 0..4 {
+            scanline(&mut mapper, &mut ppu_cycle);
+        }
+
+        assert!(!mapper.irq_pending());
+    }
+
+    #[test]
+    fn test_a12_filter() {
+        let mut mapper = test_mapper();
+
+        mapper.write(0xC000, 5);
+        mapper.write(0xC001, 0);
+
+        mapper.ppu_address(0x0000, 100);
+        mapper.ppu_address(0x1000, 120);
+        assert_eq!(mapper.irq_counter, 5);
+
+        // Rapid toggles (e.g. 8x16 sprites mixing pattern tables) are ignored
+        mapper.ppu_address(0x0000, 121);
+        mapper.ppu_address(0x1000, 122);
+        assert_eq!(mapper.irq_counter, 5);
+    }
+}
@@ -3,3 +3,21 @@ pub use self::nrom::NROM;
 
 mod cnrom;
 pub use self::cnrom::CNROM;
+
+mod mmc1;
+pub use self::mmc1::MMC1;
+
+mod mmc3;
+pub use self::mmc3::MMC3;
+
+mod uxrom;
+pub use self::uxrom::UxROM;
+
+mod axrom;
+pub use self::axrom::AxROM;
+
+mod gxrom;
+pub use self::gxrom::GxROM;
+
+mod color_dreams;
+pub use self::color_dreams::ColorDreams;
@@ -1,15 +1,36 @@
-use crate::mapper::Mapper;
+use serde::{Deserialize, Serialize};
 
+use crate::mapper::{self, Mapper};
+use crate::rom::Mirroring;
+
+#[derive(Serialize, Deserialize)]
 pub struct NROM {
+    #[serde(skip)]
     chr_rom: Vec<u8>,
+    #[serde(skip)]
     prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    mirroring: Mirroring,
 }
 
 impl NROM {
-    pub fn new(prg_rom: &[u8], chr_rom: &[u8]) -> Self {
+    pub fn new(prg_rom: &[u8], chr_rom: &[u8], prg_ram_size: usize, mirroring: Mirroring) -> Self {
         Self {
             prg_rom: prg_rom.to_vec(),
             chr_rom: chr_rom.to_vec(),
+            prg_ram: vec![0; prg_ram_size],
+            mirroring,
+        }
+    }
+}
+
+impl NROM {
+    fn prg_rom_index(&self, address: u16) -> usize {
+        // Ensure mirroring if there's only one bank
+        if self.prg_rom.len() > 0x4000 {
+            address as usize & 0x7FFF
+        } else {
+            address as usize & 0x3FFF
         }
     }
 }
@@ -25,15 +46,11 @@ impl Mapper for NROM {
                 let len = self.chr_rom.len();
                 self.chr_rom[address as usize % len]
             }
-            0x8000..=0xFFFF => {
-                // PRG-ROM: Ensure mirroring if there's only one bank.
-                let bank = if self.prg_rom.len() > 0x4000 {
-                    address as usize & 0x7FFF
-                } else {
-                    address as usize & 0x3FFF
-                };
-                self.prg_rom[bank]
+            // Family Basic boards have PRG-RAM here
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                self.prg_ram[(address as usize - 0x6000) % self.prg_ram.len()]
             }
+            0x8000..=0xFFFF => self.prg_rom[self.prg_rom_index(address)],
             _ => 0,
         }
     }
@@ -44,10 +61,55 @@ impl Mapper for NROM {
                 let len = self.chr_rom.len();
                 self.chr_rom[address as usize % len] = val;
             }
-            0x6000..=0x7fff => {
-                self.prg_rom[address as usize - 0x6000] = val;
+            0x6000..=0x7fff if !self.prg_ram.is_empty() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(address as usize - 0x6000) % len] = val;
             }
             _ => {}
         }
     }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring.clone()
+    }
+
+    fn prg_rom_offset(&self, address: u16) -> Option<usize> {
+        (address >= 0x8000).then(|| self.prg_rom_index(address))
+    }
+
+    fn chr_rom_offset(&self, address: u16) -> Option<usize> {
+        (address < 0x2000 && !self.chr_rom.is_empty())
+            .then(|| address as usize % self.chr_rom.len())
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    fn chr(&self) -> &[u8] {
+        &self.chr_rom
+    }
+    fn chr_mut(&mut self) -> &mut [u8] {
+        &mut self.chr_rom
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let state: NROM = mapper::decode_state(data)?;
+
+        *self = NROM {
+            prg_rom: std::mem::take(&mut self.prg_rom),
+            chr_rom: std::mem::take(&mut self.chr_rom),
+            ..state
+        };
+
+        Ok(())
+    }
 }
@@ -0,0 +1,169 @@
+use serde::{Deserialize, Serialize};
+
+use crate::mapper::{self, Mapper};
+use crate::rom::Mirroring;
+
+const PRG_BANK_SIZE: usize = 0x4000;
+
+// https://www.nesdev.org/wiki/UxROM
+#[derive(Serialize, Deserialize)]
+pub struct UxROM {
+    #[serde(skip)]
+    chr_rom: Vec<u8>,
+    #[serde(skip)]
+    prg_rom: Vec<u8>,
+    prg_bank: usize,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+}
+
+impl UxROM {
+    pub fn new(prg_rom: &[u8], chr_rom: &[u8], mirroring: Mirroring, bus_conflicts: bool) -> Self {
+        Self {
+            prg_rom: prg_rom.to_vec(),
+            chr_rom: chr_rom.to_vec(),
+            prg_bank: 0,
+            mirroring,
+            bus_conflicts,
+        }
+    }
+}
+
+impl UxROM {
+    fn bank_count(&self) -> usize {
+        (self.prg_rom.len() / PRG_BANK_SIZE).max(1)
+    }
+
+    fn prg_rom_index(&self, address: u16) -> usize {
+        let bank = if address < 0xC000 {
+            // Switchable bank
+            self.prg_bank
+        } else {
+            // Fixed to the last bank
+            self.bank_count() - 1
+        };
+
+        (bank * PRG_BANK_SIZE + (address as usize & 0x3FFF)) % self.prg_rom.len()
+    }
+
+    fn chr_rom_index(&self, address: u16) -> usize {
+        address as usize % self.chr_rom.len()
+    }
+}
+
+impl Mapper for UxROM {
+    fn read(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1FFF => self.chr_rom[self.chr_rom_index(address)],
+            0x8000..=0xFFFF => self.prg_rom[self.prg_rom_index(address)],
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, address: u16, val: u8) {
+        match address {
+            // CHR-RAM
+            0x0000..=0x1FFF => {
+                let len = self.chr_rom.len();
+                self.chr_rom[address as usize % len] = val;
+            }
+            0x8000..=0xFFFF => {
+                // The ROM drives the data bus at the same time as the CPU,
+                // the board sees the AND of both values
+                let val = if self.bus_conflicts {
+                    val & self.read(address)
+                } else {
+                    val
+                };
+
+                self.prg_bank = val as usize % self.bank_count();
+            }
+            _ => {}
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring.clone()
+    }
+
+    fn prg_rom_offset(&self, address: u16) -> Option<usize> {
+        (address >= 0x8000).then(|| self.prg_rom_index(address))
+    }
+
+    fn chr_rom_offset(&self, address: u16) -> Option<usize> {
+        (address < 0x2000 && !self.chr_rom.is_empty()).then(|| self.chr_rom_index(address))
+    }
+
+    fn chr(&self) -> &[u8] {
+        &self.chr_rom
+    }
+    fn chr_mut(&mut self) -> &mut [u8] {
+        &mut self.chr_rom
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let state: UxROM = mapper::decode_state(data)?;
+
+        *self = UxROM {
+            prg_rom: std::mem::take(&mut self.prg_rom),
+            chr_rom: std::mem::take(&mut self.chr_rom),
+            ..state
+        };
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_prg_rom() -> Vec<u8> {
+        let mut prg_rom = vec![0; 8 * PRG_BANK_SIZE];
+        for (bank, chunk) in prg_rom.chunks_mut(PRG_BANK_SIZE).enumerate() {
+            chunk.fill(bank as u8);
+        }
+        prg_rom
+    }
+
+    #[test]
+    fn test_bank_switching() {
+        let mut mapper = UxROM::new(&test_prg_rom(), &[0; 0x2000], Mirroring::Vertical, false);
+
+        mapper.write(0x8000, 5);
+
+        assert_eq!(mapper.read(0x8000), 5);
+        assert_eq!(mapper.read(0xC000), 7);
+    }
+
+    #[test]
+    fn test_small_prg_rom() {
+        // 8 KB is mirrored over both banks
+        let mut prg_rom = vec![0; 0x2000];
+        prg_rom[0x10] = 0x42;
+
+        let mut mapper = UxROM::new(&prg_rom, &[0; 0x2000], Mirroring::Vertical, false);
+        mapper.write(0x8000, 3);
+        assert_eq!(mapper.read(0x8010), 0x42);
+        assert_eq!(mapper.read(0xE010), 0x42);
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        let mut prg_rom = test_prg_rom();
+        // Last bank holds 0x03 at $FFF0
+        prg_rom[8 * PRG_BANK_SIZE - 0x10] = 0x03;
+
+        let mut mapper = UxROM::new(&prg_rom, &[0; 0x2000], Mirroring::Vertical, true);
+        mapper.write(0xFFF0, 0x06);
+        assert_eq!(mapper.read(0x8000), 2);
+
+        let mut mapper = UxROM::new(&prg_rom, &[0; 0x2000], Mirroring::Vertical, false);
+        mapper.write(0xFFF0, 0x06);
+        assert_eq!(mapper.read(0x8000), 6);
+    }
+}
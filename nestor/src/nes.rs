@@ -1,11 +1,34 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
 use crate::{
-    bus::Bus,
+    bus::{Bus, Memory},
+    cdl::CodeDataLogger,
     cpu::CPU,
-    ppu::{frame::Frame, palette},
+    debugger::{Debugger, RunMode},
+    disassembler::{self, Instruction},
+    gdb::GdbSession,
+    overscan::Overscan,
+    ppu::{
+        events::PpuEventLogger,
+        frame::{DisplayFrame, Frame, PixelFormat},
+        palette::Palette,
+        sprite::Sprite,
+    },
+    region::Region,
     rom::{Mirroring, ROM},
+    symbols::Symbols,
+    trace::{self, TraceFormat, TraceLogger},
     JoypadButton,
 };
 
+// Save states start with a small header so that states from another
+// version or for another game are rejected before decoding anything
+const SAVE_STATE_MAGIC: &[u8; 4] = b"NSTS";
+// Bump whenever the serialized layout changes: 2 moved PRG-RAM into the
+// mappers, 3 added the region
+const SAVE_STATE_VERSION: u16 = 3;
+const SAVE_STATE_HEADER_SIZE: usize = 10;
+
 #[derive(PartialEq, Eq)]
 pub enum EmulationStatus {
     Stopped,
@@ -13,6 +36,18 @@ pub enum EmulationStatus {
     Paused,
 }
 
+// Memory a debugger can inspect and edit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryRegion {
+    // $0000-$FFFF as the CPU sees it
+    Cpu,
+    // $0000-$3FFF: pattern tables, nametables and palettes
+    Ppu,
+    Oam,
+    // The cartridge's PRG-RAM, unbanked
+    PrgRam,
+}
+
 #[derive(Clone, Debug)]
 pub enum PlayerJoypad {
     One,
@@ -23,6 +58,13 @@ pub struct NES {
     pub cpu: CPU<Bus>,
     pub rom: Option<ROM>,
     pub status: EmulationStatus,
+    pub debugger: Debugger,
+    // Labels used by the trace and the debugger
+    pub symbols: Symbols,
+    tracer: Option<TraceLogger>,
+    overscan: Overscan,
+    // The cropped frame, kept between calls to `display_frame`
+    cropped: Frame,
 }
 
 impl NES {
@@ -34,17 +76,220 @@ impl NES {
             cpu,
             rom: None,
             status: EmulationStatus::Stopped,
+            debugger: Debugger::new(),
+            symbols: Symbols::new(),
+            tracer: None,
+            overscan: Overscan::default(),
+            cropped: Frame::default(),
         }
     }
 
     pub fn emulate_frame(&mut self) -> Option<&Frame> {
-        if self.is_running() {
-            let cycles = self.cpu.run();
+        if !self.is_running() {
+            return None;
+        }
+
+        if self.debugger.is_active() && self.debugger.check_breakpoint(&self.cpu) {
+            self.status = EmulationStatus::Paused;
+            return None;
+        }
+
+        // Stepping can leave the debugger inactive, ask before running
+        let debugging = self.debugger.is_active();
+        let debugger = debugging.then_some(&mut self.debugger);
+        let frame_complete =
+            Self::step_instruction(&mut self.cpu, &mut self.tracer, &self.symbols, debugger);
+
+        if debugging && self.debugger.stop_reason().is_some() {
+            self.status = EmulationStatus::Paused;
+        }
+
+        frame_complete.then_some(&self.cpu.bus.ppu.frame)
+    }
+
+    // Logs the next instruction to the tracer, the code/data logger and the
+    // PPU event logger, then runs it and ticks the bus, through the debugger
+    // when one is given. Returns whether a frame was completed.
+    fn step_instruction(
+        cpu: &mut CPU<Bus>,
+        tracer: &mut Option<TraceLogger>,
+        symbols: &Symbols,
+        debugger: Option<&mut Debugger>,
+    ) -> bool {
+        if let Some(tracer) = tracer.as_mut() {
+            tracer.log(cpu, symbols);
+        }
+
+        cpu.bus.log_instruction(cpu.program_counter);
+
+        if let Some(logger) = cpu.bus.ppu.event_logger_mut() {
+            logger.set_instruction(cpu.program_counter);
+        }
+
+        match debugger {
+            Some(debugger) => debugger.step(cpu),
+            None => {
+                let cycles = cpu.run();
+                let frame_complete = cpu.bus.tick(cycles).is_some();
+                cpu.cycles += cpu.bus.take_stall_cycles();
+                frame_complete
+            }
+        }
+    }
+
+    // Mixed audio is only generated once a sample rate has been chosen
+    pub fn set_audio_sample_rate(&mut self, sample_rate: u32) {
+        self.cpu.bus.apu.set_sample_rate(sample_rate);
+    }
+
+    // Takes the audio samples produced since the last call, as mono
+    // f32 values at the rate given to `set_audio_sample_rate`
+    pub fn drain_audio_samples(&mut self) -> Vec<f32> {
+        self.cpu.bus.apu.drain_samples()
+    }
+
+    // Reads from the CPU address space without side effects
+    pub fn peek_memory(&self, address: u16) -> u8 {
+        self.cpu.bus.peek(address)
+    }
+
+    // Label for a CPU address, following the current PRG banking
+    pub fn label(&self, address: u16) -> Option<&str> {
+        self.symbols.label_at(&self.cpu.bus, address)
+    }
+
+    // Disassembles live memory around `address`, without side effects
+    pub fn disassemble_around(
+        &self,
+        address: u16,
+        before: usize,
+        after: usize,
+    ) -> Vec<Instruction> {
+        disassembler::disassemble_around(|a| self.cpu.bus.peek(a), address, before, after)
+    }
+
+    // Disassembles every instruction starting within `start..=end` in live memory
+    pub fn disassemble(&self, start: u16, end: u16) -> Vec<Instruction> {
+        disassembler::disassemble(|a| self.cpu.bus.peek(a), start, end)
+    }
 
-            return self.cpu.bus.tick(cycles);
+    pub fn memory_region_size(&self, region: MemoryRegion) -> usize {
+        match region {
+            MemoryRegion::Cpu => 0x10000,
+            MemoryRegion::Ppu => 0x4000,
+            MemoryRegion::Oam => self.cpu.bus.ppu.oam_data.len(),
+            MemoryRegion::PrgRam => match &self.rom {
+                Some(rom) => rom.mapper.lock().unwrap().prg_ram().len(),
+                None => 0,
+            },
         }
+    }
+
+    // Reads a byte of `region` without side effects, 0 when out of range
+    pub fn peek_region(&self, region: MemoryRegion, address: usize) -> u8 {
+        match region {
+            MemoryRegion::Cpu => self.cpu.bus.peek(address as u16),
+            MemoryRegion::Ppu => self.cpu.bus.ppu.peek_vram(address as u16),
+            MemoryRegion::Oam => self.cpu.bus.ppu.oam_data.get(address).copied().unwrap_or(0),
+            MemoryRegion::PrgRam => match &self.rom {
+                Some(rom) => rom
+                    .mapper
+                    .lock()
+                    .unwrap()
+                    .prg_ram()
+                    .get(address)
+                    .copied()
+                    .unwrap_or(0),
+                None => 0,
+            },
+        }
+    }
+
+    // Changes a byte of `region` without side effects. ROM and registers can't be changed.
+    pub fn poke_region(&mut self, region: MemoryRegion, address: usize, value: u8) {
+        match region {
+            MemoryRegion::Cpu => self.cpu.bus.poke(address as u16, value),
+            MemoryRegion::Ppu => self.cpu.bus.ppu.poke_vram(address as u16, value),
+            MemoryRegion::Oam => {
+                if let Some(byte) = self.cpu.bus.ppu.oam_data.get_mut(address) {
+                    *byte = value;
+                }
+            }
+            MemoryRegion::PrgRam => {
+                if let Some(rom) = &self.rom {
+                    if let Some(byte) = rom.mapper.lock().unwrap().prg_ram_mut().get_mut(address) {
+                        *byte = value;
+                    }
+                }
+            }
+        }
+    }
+
+    // Trace line for the instruction at the current PC,
+    // to be called before `emulate_frame` executes it
+    pub fn trace(&self) -> String {
+        trace::trace(&self.cpu, TraceFormat::default(), &self.symbols)
+    }
+
+    // Logs every instruction from now on, replacing the current logger
+    pub fn start_trace(&mut self, logger: TraceLogger) {
+        self.tracer = Some(logger);
+    }
+
+    // Hands the logger back, to read or flush what it collected
+    pub fn stop_trace(&mut self) -> Option<TraceLogger> {
+        self.tracer.take()
+    }
+
+    pub fn trace_logger(&self) -> Option<&TraceLogger> {
+        self.tracer.as_ref()
+    }
+
+    // Marks the PRG and CHR bytes used from now on, replacing the current logger
+    pub fn start_code_data_log(&mut self, logger: CodeDataLogger) {
+        self.cpu
+            .bus
+            .set_code_data_logger(Some(Arc::new(Mutex::new(logger))));
+    }
+
+    // Hands the logger back, to save what it collected
+    pub fn stop_code_data_log(&mut self) -> Option<CodeDataLogger> {
+        let logger = self.cpu.bus.code_data_logger().cloned();
+        self.cpu.bus.set_code_data_logger(None);
+
+        Arc::into_inner(logger?).map(|logger| logger.into_inner().unwrap())
+    }
+
+    pub fn code_data_logger(&self) -> Option<MutexGuard<'_, CodeDataLogger>> {
+        self.cpu
+            .bus
+            .code_data_logger()
+            .map(|logger| logger.lock().unwrap())
+    }
+
+    // Records PPU register writes, NMIs, sprite 0 hits and IRQs from now on,
+    // replacing the current logger
+    pub fn start_ppu_event_log(&mut self, logger: PpuEventLogger) {
+        self.cpu.bus.ppu.set_event_logger(Some(logger));
+    }
+
+    pub fn stop_ppu_event_log(&mut self) -> Option<PpuEventLogger> {
+        self.cpu.bus.ppu.set_event_logger(None)
+    }
 
-        None
+    pub fn ppu_event_logger(&self) -> Option<&PpuEventLogger> {
+        self.cpu.bus.ppu.event_logger()
+    }
+
+    // Hands the CPU to a GDB client until it detaches. The trace and the
+    // code/data logger keep running, the debugger is left alone.
+    pub fn serve_gdb(&mut self, session: &mut GdbSession) -> Result<(), String> {
+        let tracer = &mut self.tracer;
+        let symbols = &self.symbols;
+
+        session.run(&mut self.cpu, |cpu| {
+            Self::step_instruction(cpu, tracer, symbols, None);
+        })
     }
 
     pub fn button_pressed(&mut self, player: PlayerJoypad, key: JoypadButton, pressed: bool) {
@@ -54,13 +299,56 @@ impl NES {
         }
     }
 
+    // The region follows the cartridge's header, `set_region` overrides it
     pub fn insert_cartridge(&mut self, rom: ROM) {
         self.cpu.bus.load_rom(&rom);
+        self.set_region(Region::from_timing(rom.header.timing));
 
         self.rom = Some(rom);
         self.start_emulation();
     }
 
+    pub fn region(&self) -> Region {
+        self.cpu.bus.region()
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.cpu.bus.set_region(region);
+    }
+
+    pub fn overscan(&self) -> Overscan {
+        self.overscan
+    }
+
+    pub fn set_overscan(&mut self, overscan: Overscan) {
+        self.overscan = overscan;
+    }
+
+    // The last frame as the frontends show it, with the overscan cropped.
+    // `filtered` is the NTSC filter's output for it, when one is used.
+    pub fn display_frame(&mut self, filtered: Option<&Frame>, output: &mut DisplayFrame) {
+        let frame = filtered.unwrap_or(&self.cpu.bus.ppu.frame);
+        self.overscan.crop(frame, &mut self.cropped);
+
+        output.width = self.cropped.width() as u32;
+        output.height = self.cropped.height() as u32;
+        output.rgba.resize(self.cropped.data.len() / 3 * 4, 0);
+        self.cropped.copy_to(&mut output.rgba, PixelFormat::Rgba);
+    }
+
+    pub fn frame(&self) -> &Frame {
+        &self.cpu.bus.ppu.frame
+    }
+
+    pub fn palette(&self) -> &Palette {
+        self.cpu.bus.ppu.palette()
+    }
+
+    // Used for the screen and the viewers, a save state keeps the current one
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.cpu.bus.ppu.set_palette(palette);
+    }
+
     pub fn start_emulation(&mut self) {
         self.cpu.reset();
         self.status = EmulationStatus::Running;
@@ -72,7 +360,38 @@ impl NES {
         }
     }
     pub fn continue_emulation(&mut self) {
+        self.resume(RunMode::Continue);
+    }
+
+    // Runs a single instruction, entering subroutines
+    pub fn step_into(&mut self) {
+        self.resume(RunMode::StepInto);
+    }
+
+    // Runs a single instruction, or a whole subroutine when it's a JSR
+    pub fn step_over(&mut self) {
+        let mode = Debugger::step_over_mode(&self.cpu);
+        self.resume(mode);
+    }
+
+    // Runs until the current subroutine or interrupt handler returns
+    pub fn step_out(&mut self) {
+        let stack_pointer = self.cpu.stack_pointer;
+        self.resume(RunMode::StepOut { stack_pointer });
+    }
+
+    pub fn run_to_scanline(&mut self, scanline: usize) {
+        self.resume(RunMode::ToScanline(scanline));
+    }
+
+    pub fn run_to_frame(&mut self) {
+        self.resume(RunMode::ToFrame);
+    }
+
+    // The emulation keeps running until the debugger reports a stop
+    fn resume(&mut self, mode: RunMode) {
         if self.status == EmulationStatus::Paused {
+            self.debugger.resume(mode, self.cpu.program_counter);
             self.status = EmulationStatus::Running;
         }
     }
@@ -81,6 +400,114 @@ impl NES {
         self.status == EmulationStatus::Running
     }
 
+    // Header (magic, version, ROM CRC32) followed by the CPU, with everything
+    // reachable from its bus, the mapper state and the CHR-RAM, if any
+    pub fn save_state(&self) -> Vec<u8> {
+        let (rom_crc, mapper_state, chr_ram) = match &self.rom {
+            Some(rom) => {
+                let mapper = rom.mapper.lock().unwrap();
+                // CHR-ROM can always be restored from the cartridge
+                let chr_ram = if rom.header.chr_rom_size == 0 {
+                    mapper.chr().to_vec()
+                } else {
+                    vec![]
+                };
+                (rom.crc32(), mapper.save_state(), chr_ram)
+            }
+            None => (0, vec![], vec![]),
+        };
+
+        let mut state = Vec::new();
+        state.extend_from_slice(SAVE_STATE_MAGIC);
+        state.extend_from_slice(&SAVE_STATE_VERSION.to_le_bytes());
+        state.extend_from_slice(&rom_crc.to_le_bytes());
+
+        bincode::serialize_into(&mut state, &(&self.cpu, mapper_state, chr_ram)).unwrap();
+
+        state
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let rom = self.rom.as_ref().ok_or("No cartridge inserted")?;
+
+        if data.len() < SAVE_STATE_HEADER_SIZE || &data[0..4] != SAVE_STATE_MAGIC {
+            return Err("Not a save state".to_string());
+        }
+
+        let version = u16::from_le_bytes([data[4], data[5]]);
+        if version != SAVE_STATE_VERSION {
+            return Err(format!("Unsupported save state version {version}"));
+        }
+
+        let rom_crc = u32::from_le_bytes([data[6], data[7], data[8], data[9]]);
+        if rom_crc != rom.crc32() {
+            return Err("Save state belongs to a different ROM".to_string());
+        }
+
+        let (mut cpu, mapper_state, chr_ram): (CPU<Bus>, Vec<u8>, Vec<u8>) =
+            bincode::deserialize(&data[SAVE_STATE_HEADER_SIZE..])
+                .map_err(|e| format!("Invalid save state: {e}"))?;
+
+        {
+            let mut mapper = rom.mapper.lock().unwrap();
+            if !chr_ram.is_empty() && chr_ram.len() != mapper.chr().len() {
+                return Err("Invalid save state: wrong CHR-RAM size".to_string());
+            }
+
+            mapper.load_state(&mapper_state)?;
+            if !chr_ram.is_empty() {
+                mapper.chr_mut().copy_from_slice(&chr_ram);
+            }
+        }
+
+        cpu.bus.reattach(&mut self.cpu.bus);
+        self.cpu = cpu;
+
+        Ok(())
+    }
+
+    // Battery-backed PRG-RAM, for the frontend to persist between sessions.
+    // None when the cartridge has no battery.
+    pub fn battery_ram(&self) -> Option<Vec<u8>> {
+        let rom = self.rom.as_ref()?;
+
+        if !rom.header.has_battery {
+            return None;
+        }
+
+        let mapper = rom.mapper.lock().unwrap();
+        let prg_ram = mapper.prg_ram();
+
+        if prg_ram.is_empty() {
+            None
+        } else {
+            Some(prg_ram.to_vec())
+        }
+    }
+
+    pub fn load_battery_ram(&mut self, data: &[u8]) -> Result<(), String> {
+        let rom = self.rom.as_ref().ok_or("No cartridge inserted")?;
+
+        if !rom.header.has_battery {
+            return Err("Cartridge has no battery-backed RAM".to_string());
+        }
+
+        let mut mapper = rom.mapper.lock().unwrap();
+        let prg_ram = mapper.prg_ram_mut();
+
+        if prg_ram.len() != data.len() {
+            return Err(format!(
+                "Expected {} bytes of battery-backed RAM, got {}",
+                prg_ram.len(),
+                data.len()
+            ));
+        }
+
+        prg_ram.copy_from_slice(data);
+
+        Ok(())
+    }
+
     fn pattern_table(&self, bank_index: usize) -> Frame {
         let mut pattern_table = Frame::new(128, 128);
 
@@ -112,7 +539,7 @@ impl NES {
                     lower >>= 1;
 
                     let rgb = match value {
-                        0..=3 => palette::SYSTEM_PALETTE[palette[value as usize] as usize],
+                        0..=3 => self.palette().color(palette[value as usize] as u16),
                         _ => panic!("can't be"),
                     };
 
@@ -135,7 +562,7 @@ impl NES {
 
         let mut tile_x = 0;
         for color in self.cpu.bus.ppu.palette_table {
-            let rgb = palette::SYSTEM_PALETTE[color as usize];
+            let rgb = self.palette().color(color as u16);
             for y in 0..8 {
                 for x in 0..8 {
                     frame.set_pixel(tile_x + x, y, rgb);
@@ -147,6 +574,80 @@ impl NES {
         frame
     }
 
+    // The 64 OAM entries, in order
+    pub fn sprites(&self) -> Vec<Sprite> {
+        self.cpu
+            .bus
+            .ppu
+            .oam_data
+            .chunks_exact(4)
+            .enumerate()
+            .map(|(n, entry)| Sprite::from(entry, n == 0))
+            .collect()
+    }
+
+    // 8 or 16, from PPUCTRL
+    pub fn sprite_height(&self) -> usize {
+        self.cpu.bus.ppu.ctrl.sprite_size() as usize
+    }
+
+    // Every OAM entry drawn the way the PPU would, flips and palette
+    // included, in an 8x8 grid of 8x16 cells. Transparent pixels show
+    // the backdrop color.
+    pub fn sprite_viewer(&self) -> Frame {
+        let mut frame = Frame::new(64, 128);
+
+        let ppu = &self.cpu.bus.ppu;
+        let height = self.sprite_height();
+        let backdrop = ppu.palette().color(ppu.palette_table[0] as u16);
+
+        for (n, sprite) in self.sprites().iter().enumerate() {
+            let cell_x = (n % 8) * 8;
+            let cell_y = (n / 8) * 16;
+
+            for row in 0..height {
+                let fine_y = if sprite.flip_v() {
+                    height - 1 - row
+                } else {
+                    row
+                };
+
+                // 8x16 sprites pick their own pattern table, the bottom half is the next tile
+                let (pattern_table, tile, fine_y) = if height == 16 {
+                    (
+                        sprite.pattern_table_8x16(),
+                        sprite.tile_number_8x16() + (fine_y / 8) as u8,
+                        fine_y % 8,
+                    )
+                } else {
+                    (ppu.ctrl.sprt_pattern_addr(), sprite.tile, fine_y)
+                };
+
+                let address = pattern_table + tile as u16 * 16 + fine_y as u16;
+                let lower = ppu.peek_vram(address);
+                let upper = ppu.peek_vram(address + 8);
+
+                for column in 0..8 {
+                    let bit = if sprite.flip_h() { column } else { 7 - column };
+                    let value = ((lower >> bit) & 0x01) | (((upper >> bit) & 0x01) << 1);
+
+                    let rgb = match value {
+                        0 => backdrop,
+                        _ => {
+                            let color =
+                                ppu.palette_table[sprite.palette() as usize * 4 + value as usize];
+                            ppu.palette().color(color as u16)
+                        }
+                    };
+
+                    frame.set_pixel(cell_x + column, cell_y + row, rgb);
+                }
+            }
+        }
+
+        frame
+    }
+
     fn bg_pallette(
         &self,
         palette_table: &[u8],
@@ -177,6 +678,7 @@ impl NES {
 
         let rom = self.rom.as_ref().unwrap();
         let chr_rom = &rom.chr_rom;
+        let mirroring = rom.mapper.lock().unwrap().mirroring();
         let ppu_ctrl_bank = self.cpu.bus.ppu.ctrl.bknd_pattern_addr() as usize;
 
         for nametable in self.cpu.bus.ppu.vram.chunks(0x400) {
@@ -205,7 +707,7 @@ impl NES {
                         let value = ((lower >> x) & 0x01) | (((upper >> x) & 0x01) << 1);
 
                         let rgb = match value {
-                            0..=3 => palette::SYSTEM_PALETTE[palette[value as usize] as usize],
+                            0..=3 => self.palette().color(palette[value as usize] as u16),
                             _ => panic!("can't be"),
                         };
 
@@ -214,7 +716,7 @@ impl NES {
 
                         frame.set_pixel(x_offset + x, y_offset + y, rgb);
 
-                        if rom.mirroring == Mirroring::Vertical {
+                        if mirroring == Mirroring::Vertical {
                             frame.set_pixel(x_offset + x, y_offset + y + 240, rgb);
                         } else {
                             frame.set_pixel(x_offset + x + 256, y_offset + y, rgb);
@@ -223,7 +725,7 @@ impl NES {
                 }
             }
 
-            if rom.mirroring == Mirroring::Vertical {
+            if mirroring == Mirroring::Vertical {
                 x_offset = 256;
             } else {
                 y_offset = 240;
@@ -239,3 +741,447 @@ impl Default for NES {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::IrqSource;
+    use crate::ppu::events::PpuEventKind;
+    use crate::ppu::palette::{BuiltinPalette, SYSTEM_PALETTE};
+
+    // NROM with a program that keeps incrementing X: INX; JMP $8000
+    fn test_rom_bytes(prg_byte: u8) -> Vec<u8> {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut prg_rom = vec![prg_byte; 0x4000];
+        prg_rom[0..4].copy_from_slice(&[0xE8, 0x4C, 0x00, 0x80]);
+        prg_rom[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        raw.extend(prg_rom);
+        raw.extend(vec![0; 0x2000]);
+        raw
+    }
+
+    fn test_rom(prg_byte: u8) -> ROM {
+        ROM::from_bytes(&test_rom_bytes(prg_byte)).unwrap()
+    }
+
+    fn run_frames(nes: &mut NES, frames: usize) {
+        for _ in 0..frames {
+            while nes.emulate_frame().is_none() {}
+        }
+    }
+
+    #[test]
+    fn test_trace_format() {
+        let mut nes = NES::new();
+        nes.insert_cartridge(test_rom(0));
+        nes.cpu.register_x = 0x10;
+
+        assert_eq!(
+            nes.trace(),
+            "8000  E8        INX                             A:00 X:10 Y:00 P:24 SP:FD"
+        );
+
+        nes.emulate_frame();
+        assert_eq!(
+            nes.trace(),
+            "8001  4C 00 80  JMP $8000                       A:00 X:11 Y:00 P:24 SP:FD"
+        );
+    }
+
+    #[test]
+    fn test_peek_has_no_side_effects() {
+        let mut nes = NES::new();
+        nes.insert_cartridge(test_rom(0));
+
+        while nes.cpu.bus.ppu.scanline != 242 {
+            nes.emulate_frame();
+        }
+
+        // The vblank flag survives peeks, a read clears it
+        assert_eq!(nes.peek_memory(0x2002) & 0x80, 0x80);
+        assert_eq!(nes.peek_memory(0x2002) & 0x80, 0x80);
+        assert_eq!(nes.cpu.bus.mem_read(0x2002) & 0x80, 0x80);
+        assert_eq!(nes.peek_memory(0x2002) & 0x80, 0);
+
+        // Cartridge space
+        assert_eq!(nes.peek_memory(0x8000), 0xE8);
+        assert_eq!(nes.peek_memory(0xFFFC), 0x00);
+        assert_eq!(nes.peek_memory(0xFFFD), 0x80);
+    }
+
+    #[test]
+    fn test_memory_regions() {
+        let mut nes = NES::new();
+        nes.insert_cartridge(test_rom(0));
+
+        nes.poke_region(MemoryRegion::Cpu, 0x0802, 0x12);
+        assert_eq!(nes.peek_region(MemoryRegion::Cpu, 0x0002), 0x12);
+
+        // PRG-ROM can't be changed
+        nes.poke_region(MemoryRegion::Cpu, 0x8000, 0x12);
+        assert_eq!(nes.peek_region(MemoryRegion::Cpu, 0x8000), 0xE8);
+
+        // Unmapped addresses are ignored
+        nes.poke_region(MemoryRegion::Cpu, 0x5000, 0x12);
+        assert_eq!(nes.peek_region(MemoryRegion::Cpu, 0x5000), 0);
+
+        // Palette mirrors
+        nes.poke_region(MemoryRegion::Ppu, 0x3F10, 0x21);
+        assert_eq!(nes.peek_region(MemoryRegion::Ppu, 0x3F00), 0x21);
+
+        nes.poke_region(MemoryRegion::Oam, 4, 0x34);
+        assert_eq!(nes.peek_region(MemoryRegion::Oam, 4), 0x34);
+        assert_eq!(nes.memory_region_size(MemoryRegion::Oam), 256);
+
+        assert_eq!(nes.memory_region_size(MemoryRegion::PrgRam), 0x2000);
+        nes.poke_region(MemoryRegion::PrgRam, 0x10, 0x56);
+        assert_eq!(nes.peek_region(MemoryRegion::Cpu, 0x6010), 0x56);
+    }
+
+    #[test]
+    fn test_code_data_log() {
+        let mut nes = NES::new();
+        let rom = test_rom(0);
+        nes.start_code_data_log(CodeDataLogger::for_rom(&rom));
+        nes.insert_cartridge(rom);
+
+        let format = TraceFormat {
+            code_data: true,
+            ..Default::default()
+        };
+
+        // INX, then JMP $8000 runs for the first time
+        nes.emulate_frame();
+        assert!(trace::trace(&nes.cpu, format, &nes.symbols).ends_with("CDL:--"));
+        nes.emulate_frame();
+        assert!(trace::trace(&nes.cpu, format, &nes.symbols).ends_with("CDL:C-"));
+
+        // A one byte DMC sample at $C040
+        nes.cpu.bus.mem_write(0x4012, 0x01);
+        nes.cpu.bus.mem_write(0x4013, 0x00);
+        nes.cpu.bus.mem_write(0x4015, 0x10);
+        nes.emulate_frame();
+
+        // Reading CHR through PPUDATA
+        nes.cpu.bus.mem_write(0x2006, 0x00);
+        nes.cpu.bus.mem_write(0x2006, 0x10);
+        nes.cpu.bus.mem_read(0x2007);
+
+        let logger = nes.stop_code_data_log().unwrap();
+        assert_eq!(&logger.prg()[0..4], &[CodeDataLogger::CODE; 4]);
+        assert_eq!(logger.prg()[4], 0);
+        // Only PCM, in the $C000 slot
+        assert_eq!(logger.prg()[0x40], CodeDataLogger::PCM_DATA | 0x08);
+        assert_eq!(logger.chr()[0x10], CodeDataLogger::READ);
+
+        assert!(nes.code_data_logger().is_none());
+    }
+
+    #[test]
+    fn test_sprite_viewer() {
+        let mut raw = test_rom_bytes(0);
+        // Tile 1 has a single pixel in the top left corner
+        raw[16 + 0x4000 + 16] = 0x80;
+
+        let mut nes = NES::new();
+        nes.insert_cartridge(ROM::from_bytes(&raw).unwrap());
+
+        // Sprite 1 uses tile 1 and palette 5, flipped horizontally
+        nes.cpu.bus.ppu.oam_data[4..8].copy_from_slice(&[0x20, 0x01, 0x41, 0x30]);
+        nes.cpu.bus.ppu.palette_table[0x15] = 0x16;
+
+        let sprites = nes.sprites();
+        assert_eq!(sprites.len(), 64);
+        assert_eq!((sprites[1].x, sprites[1].y), (0x30, 0x21));
+        assert_eq!(nes.sprite_height(), 8);
+
+        let frame = nes.sprite_viewer();
+        let pixel = |x: usize, y: usize| {
+            let base = (y * frame.width() + x) * 3;
+            (frame.data[base], frame.data[base + 1], frame.data[base + 2])
+        };
+
+        // Second cell of the first row
+        assert_eq!(pixel(8 + 7, 0), SYSTEM_PALETTE[0x16]);
+        assert_eq!(pixel(8, 0), SYSTEM_PALETTE[0]);
+    }
+
+    #[test]
+    fn test_emphasis() {
+        let mut nes = NES::new();
+        nes.insert_cartridge(test_rom(0));
+        nes.set_palette(Palette::builtin(BuiltinPalette::Composite));
+
+        // A red backdrop with red emphasis
+        nes.cpu.bus.ppu.palette_table[0] = 0x16;
+        nes.cpu.bus.ppu.mask.update(0x20);
+        run_frames(&mut nes, 1);
+
+        let pixel = |nes: &NES| {
+            let data = &nes.cpu.bus.ppu.frame.data;
+            (data[0], data[1], data[2])
+        };
+        assert_eq!(pixel(&nes), nes.palette().color(0x16 | 0x40));
+        assert_eq!(nes.cpu.bus.ppu.frame.indices()[0], 0x16 | 0x40);
+
+        // Bit 5 emphasizes green on PAL
+        nes.set_region(Region::Pal);
+        run_frames(&mut nes, 1);
+        assert_eq!(pixel(&nes), nes.palette().color(0x16 | 0x80));
+
+        // Kept over a save state
+        let state = nes.save_state();
+        nes.load_state(&state).unwrap();
+        assert_eq!(nes.palette(), &Palette::builtin(BuiltinPalette::Composite));
+    }
+
+    #[test]
+    fn test_display_frame() {
+        let mut nes = NES::new();
+        nes.insert_cartridge(test_rom(0));
+        nes.cpu.bus.ppu.frame.set_pixel(0, 8, (1, 2, 3));
+
+        // The NTSC overscan hides the top 8 lines
+        let mut display = DisplayFrame::default();
+        nes.display_frame(None, &mut display);
+        assert_eq!((display.width, display.height), (256, 224));
+        assert_eq!(&display.rgba[0..4], &[1, 2, 3, 255]);
+
+        nes.set_overscan(Overscan::NONE);
+        nes.display_frame(None, &mut display);
+        assert_eq!((display.width, display.height), (256, 240));
+        assert_eq!(display.rgba.len(), 256 * 240 * 4);
+
+        // A filter's output is shown instead, at its own width
+        let filtered = Frame::new(512, 240);
+        nes.set_overscan(Overscan::NTSC);
+        nes.display_frame(Some(&filtered), &mut display);
+        assert_eq!((display.width, display.height), (512, 224));
+        assert_eq!(&display.rgba[0..4], &[0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_ppu_event_log() {
+        let mut raw = test_rom_bytes(0);
+        // LDA #$1E; STA $2001; JMP $8005
+        raw[16..24].copy_from_slice(&[0xA9, 0x1E, 0x8D, 0x01, 0x20, 0x4C, 0x05, 0x80]);
+
+        let mut nes = NES::new();
+        nes.start_ppu_event_log(PpuEventLogger::new());
+        nes.insert_cartridge(ROM::from_bytes(&raw).unwrap());
+        run_frames(&mut nes, 1);
+
+        let events = nes.ppu_event_logger().unwrap().events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(
+            events[0].kind,
+            PpuEventKind::RegisterWrite {
+                address: 0x2001,
+                value: 0x1E
+            }
+        );
+        assert_eq!(events[0].program_counter, 0x8002);
+        assert_eq!(events[0].scanline, 0);
+
+        // The APU's frame counter asserts its IRQ early in the second frame.
+        // Nothing acknowledges it, so there's no new edge on the third.
+        run_frames(&mut nes, 1);
+        let events = nes.ppu_event_logger().unwrap().events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, PpuEventKind::Irq(IrqSource::FRAME_COUNTER));
+
+        run_frames(&mut nes, 1);
+        assert!(nes.ppu_event_logger().unwrap().events().is_empty());
+
+        assert!(nes.stop_ppu_event_log().is_some());
+        assert!(nes.ppu_event_logger().is_none());
+    }
+
+    #[test]
+    fn test_ppu_event_log_nmi() {
+        let mut raw = test_rom_bytes(0);
+        // LDA #$80; STA $2000; JMP $8005
+        raw[16..24].copy_from_slice(&[0xA9, 0x80, 0x8D, 0x00, 0x20, 0x4C, 0x05, 0x80]);
+        // NMI vector, back into the loop
+        raw[16 + 0x3FFA..16 + 0x3FFC].copy_from_slice(&[0x05, 0x80]);
+
+        let mut nes = NES::new();
+        nes.start_ppu_event_log(PpuEventLogger::new());
+        nes.insert_cartridge(ROM::from_bytes(&raw).unwrap());
+        run_frames(&mut nes, 1);
+
+        // Raised by the PPU, not moved like the write before it
+        let events = nes.ppu_event_logger().unwrap().events();
+        let nmi = events
+            .iter()
+            .find(|event| event.kind == PpuEventKind::Nmi)
+            .unwrap();
+        assert_eq!((nmi.scanline, nmi.cycle), (241, 1));
+    }
+
+    #[test]
+    fn test_region() {
+        let mut nes = NES::new();
+        nes.insert_cartridge(test_rom(0));
+        assert_eq!(nes.region(), Region::Ntsc);
+
+        // iNES PAL flag
+        let mut raw = test_rom_bytes(0);
+        raw[9] = 0x01;
+        nes.insert_cartridge(ROM::from_bytes(&raw).unwrap());
+        assert_eq!(nes.region(), Region::Pal);
+
+        // 312 scanlines at 3.2 dots per CPU cycle
+        run_frames(&mut nes, 1);
+        let cycles = nes.cpu.cycles;
+        run_frames(&mut nes, 1);
+        assert!((nes.cpu.cycles - cycles).abs_diff(33248) < 8);
+
+        nes.set_region(Region::Dendy);
+        while nes.cpu.bus.ppu.scanline != 291 {
+            assert!(!nes.cpu.bus.ppu.status.is_in_vblank());
+            nes.emulate_frame();
+        }
+        nes.emulate_frame();
+        assert!(nes.cpu.bus.ppu.status.is_in_vblank());
+    }
+
+    #[test]
+    fn test_save_state_round_trip() {
+        let mut nes = NES::new();
+        nes.insert_cartridge(test_rom(0));
+        run_frames(&mut nes, 2);
+
+        let state = nes.save_state();
+        let register_x = nes.cpu.register_x;
+        let cycles = nes.cpu.cycles;
+
+        run_frames(&mut nes, 1);
+        assert_ne!(nes.cpu.cycles, cycles);
+
+        nes.load_state(&state).unwrap();
+        assert_eq!(nes.cpu.register_x, register_x);
+        assert_eq!(nes.cpu.cycles, cycles);
+        assert_eq!(nes.save_state(), state);
+    }
+
+    #[test]
+    fn test_save_state_chr() {
+        let mut nes = NES::new();
+        nes.insert_cartridge(test_rom(0));
+        let rom_state_len = nes.save_state().len();
+
+        let mut raw = test_rom_bytes(0);
+        raw[5] = 0;
+        raw.truncate(16 + 0x4000);
+        nes.insert_cartridge(ROM::from_bytes(&raw).unwrap());
+
+        let write_chr_ram = |nes: &mut NES, value: u8| {
+            nes.cpu.bus.mem_write(0x2006, 0x00);
+            nes.cpu.bus.mem_write(0x2006, 0x10);
+            nes.cpu.bus.mem_write(0x2007, value);
+        };
+
+        // Only CHR-RAM is saved, CHR-ROM comes from the cartridge
+        write_chr_ram(&mut nes, 0x42);
+        let state = nes.save_state();
+        assert_eq!(state.len(), rom_state_len + 0x2000);
+        write_chr_ram(&mut nes, 0x43);
+
+        nes.load_state(&state).unwrap();
+        let rom = nes.rom.as_ref().unwrap();
+        assert_eq!(rom.mapper.lock().unwrap().chr()[0x10], 0x42);
+    }
+
+    #[test]
+    fn test_mapper_mirroring_switch() {
+        // MMC1 starts on the first nametable
+        let mut raw = test_rom_bytes(0);
+        raw[6] = 0x10;
+        let mut nes = NES::new();
+        nes.insert_cartridge(ROM::from_bytes(&raw).unwrap());
+
+        let write_nametable = |nes: &mut NES, value: u8| {
+            nes.cpu.bus.mem_write(0x2006, 0x20);
+            nes.cpu.bus.mem_write(0x2006, 0x00);
+            nes.cpu.bus.mem_write(0x2007, value);
+        };
+
+        write_nametable(&mut nes, 0x42);
+        // Shifts 0x0D into the control register, for the second nametable
+        for bit in 0..5 {
+            nes.cpu.bus.mem_write(0x8000, 0x0D >> bit);
+        }
+        write_nametable(&mut nes, 0x43);
+
+        assert_eq!(nes.cpu.bus.ppu.vram[0], 0x42);
+        assert_eq!(nes.cpu.bus.ppu.vram[0x400], 0x43);
+    }
+
+    #[test]
+    fn test_dmc_stall_cycles() {
+        let mut quiet = NES::new();
+        quiet.insert_cartridge(test_rom(0));
+        run_frames(&mut quiet, 2);
+
+        // Loop the fastest DMC sample, so it fetches every 432 cycles
+        let mut nes = NES::new();
+        nes.insert_cartridge(test_rom(0));
+        nes.cpu.bus.mem_write(0x4010, 0x4F);
+        nes.cpu.bus.mem_write(0x4012, 0x00);
+        nes.cpu.bus.mem_write(0x4013, 0xFF);
+        nes.cpu.bus.mem_write(0x4015, 0x10);
+        run_frames(&mut nes, 2);
+
+        // Frames take as many CPU cycles whether or not the DMC stalls the CPU
+        assert!(nes.cpu.cycles.abs_diff(quiet.cpu.cycles) < 8);
+    }
+
+    #[test]
+    fn test_load_state_rejects_other_rom() {
+        let mut nes = NES::new();
+        nes.insert_cartridge(test_rom(0));
+        let state = nes.save_state();
+
+        nes.insert_cartridge(test_rom(1));
+        assert!(nes.load_state(&state).is_err());
+    }
+
+    #[test]
+    fn test_load_state_rejects_invalid_data() {
+        let mut nes = NES::new();
+        assert!(nes.load_state(&[]).is_err());
+
+        nes.insert_cartridge(test_rom(0));
+        assert!(nes.load_state(b"garbage").is_err());
+
+        let mut state = nes.save_state();
+        state.truncate(state.len() / 2);
+        assert!(nes.load_state(&state).is_err());
+    }
+
+    #[test]
+    fn test_battery_ram() {
+        let mut nes = NES::new();
+        nes.insert_cartridge(test_rom(0));
+        assert_eq!(nes.battery_ram(), None);
+        assert!(nes.load_battery_ram(&[0; 0x2000]).is_err());
+
+        let mut raw = test_rom_bytes(0);
+        raw[6] |= 0b10;
+        nes.insert_cartridge(ROM::from_bytes(&raw).unwrap());
+
+        nes.cpu.bus.mem_write(0x6000, 0x42);
+        let battery_ram = nes.battery_ram().unwrap();
+        assert_eq!(battery_ram.len(), 0x2000);
+        assert_eq!(battery_ram[0], 0x42);
+
+        let mut saved = vec![0; 0x2000];
+        saved[0x1FFF] = 0x24;
+        nes.load_battery_ram(&saved).unwrap();
+        assert_eq!(nes.cpu.bus.mem_read(0x7FFF), 0x24);
+        assert_eq!(nes.cpu.bus.mem_read(0x6000), 0);
+
+        assert!(nes.load_battery_ram(&[0; 16]).is_err());
+    }
+}
@@ -0,0 +1,322 @@
+use crate::ppu::frame::Frame;
+use crate::ppu::palette::{carrier_angle, signal_level, yiq_to_rgb, PALETTE_ENTRIES, SATURATION};
+
+// The PPU outputs 8 signal samples per pixel, 12 per color carrier cycle
+const SAMPLES_PER_PIXEL: usize = 8;
+// Output pixels per PPU pixel
+const OUTPUT_SCALE: usize = 2;
+// Blank signal around each scanline, so the filters can run past its ends
+const PADDING: usize = 48;
+
+// How the picture gets to the TV
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NtscSignal {
+    // Luma and chroma share one wire: color fringes, rainbows and dot crawl
+    Composite,
+    // Separate luma and chroma: sharp, with some color bleed
+    SVideo,
+    // Every pixel decoded on its own, no artifacts
+    Rgb,
+}
+
+impl NtscSignal {
+    pub const ALL: [NtscSignal; 3] = [NtscSignal::Composite, NtscSignal::SVideo, NtscSignal::Rgb];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            NtscSignal::Composite => "Composite",
+            NtscSignal::SVideo => "S-Video",
+            NtscSignal::Rgb => "RGB",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|signal| signal.name().eq_ignore_ascii_case(name))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NtscSettings {
+    pub signal: NtscSignal,
+    // -1 to 1. Sharper composite luma lets more of the carrier through.
+    pub sharpness: f32,
+    // -1 for grayscale to 1 for twice the color
+    pub saturation: f32,
+    // 0 to 1, how far colors spread into their neighbours
+    pub bleed: f32,
+}
+
+impl NtscSettings {
+    pub fn preset(signal: NtscSignal) -> Self {
+        match signal {
+            NtscSignal::Composite => NtscSettings {
+                signal,
+                sharpness: 0.0,
+                saturation: 0.0,
+                bleed: 0.5,
+            },
+            NtscSignal::SVideo => NtscSettings {
+                signal,
+                sharpness: 0.2,
+                saturation: 0.0,
+                bleed: 0.25,
+            },
+            NtscSignal::Rgb => NtscSettings {
+                signal,
+                sharpness: 0.0,
+                saturation: 0.0,
+                bleed: 0.0,
+            },
+        }
+    }
+}
+
+impl Default for NtscSettings {
+    fn default() -> Self {
+        NtscSettings::preset(NtscSignal::Composite)
+    }
+}
+
+// Turns the PPU's 9-bit colors into the signal the console sends to a TV
+// and decodes it back, the way blargg's nes_ntsc does, into an image twice
+// as wide. Only NTSC timing is modelled.
+pub struct NtscFilter {
+    settings: NtscSettings,
+    // Every color's signal at each of the 12 carrier phases
+    levels: Vec<[f32; 12]>,
+    // Every color's luma, what an S-Video cable carries
+    luma: Vec<f32>,
+    // Every color decoded from a whole carrier cycle
+    colors: Vec<(u8, u8, u8)>,
+    // Cosine and sine of the decoder's angle at each phase
+    carrier: [(f32, f32); 12],
+    // The carrier's phase at the start of a frame moves by a third of a
+    // cycle every frame, which makes the artifacts crawl
+    odd_frame: bool,
+    // Running sums over a scanline of luma, I and Q
+    y_sums: Vec<f32>,
+    i_sums: Vec<f32>,
+    q_sums: Vec<f32>,
+}
+
+impl NtscFilter {
+    pub fn new(settings: NtscSettings) -> Self {
+        let levels: Vec<[f32; 12]> = (0..PALETTE_ENTRIES)
+            .map(|index| std::array::from_fn(|phase| signal_level(index, phase)))
+            .collect();
+
+        let luma = levels
+            .iter()
+            .map(|levels| levels.iter().sum::<f32>() / 12.0)
+            .collect();
+
+        let mut filter = NtscFilter {
+            settings,
+            levels,
+            luma,
+            colors: Vec::new(),
+            carrier: std::array::from_fn(|phase| {
+                let angle = carrier_angle(phase);
+                (angle.cos(), angle.sin())
+            }),
+            odd_frame: false,
+            y_sums: Vec::new(),
+            i_sums: Vec::new(),
+            q_sums: Vec::new(),
+        };
+        filter.set_settings(settings);
+        filter
+    }
+
+    pub fn settings(&self) -> NtscSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: NtscSettings) {
+        self.settings = settings;
+
+        let saturation = self.saturation();
+        self.colors = self
+            .levels
+            .iter()
+            .map(|levels| {
+                let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+
+                for (level, (cos, sin)) in levels.iter().zip(self.carrier) {
+                    y += level;
+                    i += level * cos;
+                    q += level * sin;
+                }
+
+                yiq_to_rgb(y / 12.0, i * saturation / 12.0, q * saturation / 12.0)
+            })
+            .collect();
+    }
+
+    pub fn output_width(input_width: usize) -> usize {
+        input_width * OUTPUT_SCALE
+    }
+
+    // Filters the frame's palette indices, see `Frame::indices`. The output
+    // is replaced when it isn't `output_width` by the frame's height.
+    pub fn filter(&mut self, frame: &Frame, output: &mut Frame) {
+        let (width, height) = (frame.width(), frame.height());
+        let output_width = Self::output_width(width);
+
+        if output.width() != output_width || output.data.len() != output_width * height * 3 {
+            *output = Frame::new(output_width, height);
+        }
+
+        // The carrier moves 2728 samples, a third of a cycle, every
+        // scanline. Frames alternate as the NTSC PPU skips a dot every
+        // other frame.
+        let frame_phase = if self.odd_frame { 4 } else { 0 };
+        self.odd_frame = !self.odd_frame;
+
+        for (y, indices) in frame.indices().chunks_exact(width).enumerate() {
+            match self.settings.signal {
+                NtscSignal::Rgb => {
+                    for (x, &index) in indices.iter().enumerate() {
+                        let rgb = self.colors[index as usize % PALETTE_ENTRIES];
+
+                        for n in 0..OUTPUT_SCALE {
+                            output.set_pixel(x * OUTPUT_SCALE + n, y, rgb);
+                        }
+                    }
+                }
+                NtscSignal::Composite | NtscSignal::SVideo => {
+                    self.decode_scanline(indices, (frame_phase + y * 4) % 12, y, output);
+                }
+            }
+        }
+    }
+
+    fn saturation(&self) -> f32 {
+        SATURATION * (1.0 + self.settings.saturation.clamp(-1.0, 1.0))
+    }
+
+    fn decode_scanline(
+        &mut self,
+        indices: &[u16],
+        line_phase: usize,
+        y: usize,
+        output: &mut Frame,
+    ) {
+        let samples = indices.len() * SAMPLES_PER_PIXEL + 2 * PADDING;
+        let composite = self.settings.signal == NtscSignal::Composite;
+
+        for sums in [&mut self.y_sums, &mut self.i_sums, &mut self.q_sums] {
+            sums.clear();
+            sums.resize(samples + 1, 0.0);
+        }
+
+        for (x, &index) in indices.iter().enumerate() {
+            let index = index as usize % PALETTE_ENTRIES;
+
+            for n in 0..SAMPLES_PER_PIXEL {
+                let sample = PADDING + x * SAMPLES_PER_PIXEL + n;
+                let phase = (line_phase + sample) % 12;
+                let level = self.levels[index][phase];
+                let (cos, sin) = self.carrier[phase];
+
+                // Combined, the luma filter has to take the carrier out
+                let luma = if composite { level } else { self.luma[index] };
+
+                self.y_sums[sample + 1] = luma;
+                self.i_sums[sample + 1] = level * cos;
+                self.q_sums[sample + 1] = level * sin;
+            }
+        }
+
+        for sums in [&mut self.y_sums, &mut self.i_sums, &mut self.q_sums] {
+            let mut total = 0.0;
+
+            for sum in sums.iter_mut() {
+                total += *sum;
+                *sum = total;
+            }
+        }
+
+        // A whole carrier cycle removes it, sharper luma keeps some of it.
+        // Chroma filters stay whole cycles long so they don't pick up luma.
+        let sharpness = self.settings.sharpness.clamp(-1.0, 1.0);
+        let luma_base = if composite { 12.0 } else { 4.0 };
+        let luma_width = (luma_base * 2f32.powf(-sharpness)).round().max(1.0) as usize;
+        let chroma_width = 12 * (1 + (self.settings.bleed.clamp(0.0, 1.0) * 2.0).round() as usize);
+
+        let average = |sums: &[f32], center: usize, width: usize| {
+            let start = center - width / 2;
+            (sums[start + width] - sums[start]) / width as f32
+        };
+
+        let saturation = self.saturation();
+        let step = SAMPLES_PER_PIXEL / OUTPUT_SCALE;
+
+        for x in 0..indices.len() * OUTPUT_SCALE {
+            let center = PADDING + x * step + step / 2;
+
+            let luma = average(&self.y_sums, center, luma_width);
+            let i = average(&self.i_sums, center, chroma_width) * saturation;
+            let q = average(&self.q_sums, center, chroma_width) * saturation;
+
+            output.set_pixel(x, y, yiq_to_rgb(luma, i, q));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame_of(indices: &[u16], width: usize) -> Frame {
+        let mut frame = Frame::new(width, indices.len() / width);
+
+        for (n, &index) in indices.iter().enumerate() {
+            frame.set_indexed_pixel(n % width, n / width, index, (0, 0, 0));
+        }
+
+        frame
+    }
+
+    #[test]
+    fn test_flat_colors() {
+        let frame = frame_of(&[0x16; 64 * 2], 64);
+        let mut output = Frame::new(0, 0);
+
+        for signal in NtscSignal::ALL {
+            let mut filter = NtscFilter::new(NtscSettings::preset(signal));
+            filter.filter(&frame, &mut output);
+
+            assert_eq!((output.width(), output.height()), (128, 2));
+
+            // Away from the edges a flat color decodes to the palette's
+            let pixel = &output.data[64 * 3..64 * 3 + 3];
+            let expected = filter.colors[0x16];
+            for (channel, expected) in pixel.iter().zip([expected.0, expected.1, expected.2]) {
+                assert!(channel.abs_diff(expected) <= 2, "{:?}", signal);
+            }
+        }
+    }
+
+    #[test]
+    fn test_dot_crawl() {
+        // White and black columns make the composite colors fringe
+        let indices: Vec<u16> = (0..64)
+            .map(|x| if x % 2 == 0 { 0x30 } else { 0x0F })
+            .collect();
+        let frame = frame_of(&indices, 64);
+
+        let mut composite = NtscFilter::new(NtscSettings::preset(NtscSignal::Composite));
+        let (mut first, mut second) = (Frame::new(0, 0), Frame::new(0, 0));
+        composite.filter(&frame, &mut first);
+        composite.filter(&frame, &mut second);
+        assert_ne!(first.data, second.data);
+
+        let mut rgb = NtscFilter::new(NtscSettings::preset(NtscSignal::Rgb));
+        rgb.filter(&frame, &mut first);
+        rgb.filter(&frame, &mut second);
+        assert_eq!(first.data, second.data);
+    }
+}
@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+
+use crate::ppu::frame::Frame;
+
+const SCREEN_WIDTH: usize = 256;
+const SCREEN_HEIGHT: usize = 240;
+
+// Pixels hidden at each edge of the 256x240 picture. TVs showed less than
+// the PPU draws, and games leave garbage where they didn't.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Overscan {
+    pub top: usize,
+    pub bottom: usize,
+    pub left: usize,
+    pub right: usize,
+}
+
+impl Overscan {
+    pub const NONE: Overscan = Overscan {
+        top: 0,
+        bottom: 0,
+        left: 0,
+        right: 0,
+    };
+
+    // The 224 lines an NTSC TV typically shows
+    pub const NTSC: Overscan = Overscan {
+        top: 8,
+        bottom: 8,
+        left: 0,
+        right: 0,
+    };
+
+    // What's left of the picture, in PPU pixels
+    pub fn visible_size(&self) -> (usize, usize) {
+        (
+            SCREEN_WIDTH.saturating_sub(self.left + self.right),
+            SCREEN_HEIGHT.saturating_sub(self.top + self.bottom),
+        )
+    }
+
+    // How big the visible picture looks on screen, in square pixels
+    pub fn display_size(&self, aspect: PixelAspect) -> (f32, f32) {
+        let (width, height) = self.visible_size();
+        (width as f32 * aspect.ratio(), height as f32)
+    }
+
+    // Works on frames wider than the PPU's too, like a filter's output,
+    // by scaling the left and right edges
+    pub fn crop(&self, frame: &Frame, output: &mut Frame) {
+        let scale = (frame.width() / SCREEN_WIDTH).max(1);
+        let (width, height) = self.visible_size();
+
+        frame.crop(self.left * scale, self.top, width * scale, height, output);
+    }
+}
+
+impl Default for Overscan {
+    fn default() -> Self {
+        Overscan::NTSC
+    }
+}
+
+// The shape of a pixel on a TV
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PixelAspect {
+    #[default]
+    Square,
+    // NTSC pixels are 8:7, a little wider than tall
+    Ntsc,
+}
+
+impl PixelAspect {
+    pub fn ratio(&self) -> f32 {
+        match self {
+            PixelAspect::Square => 1.0,
+            PixelAspect::Ntsc => 8.0 / 7.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crop() {
+        let mut frame = Frame::new(512, 240);
+        frame.set_pixel(16, 8, (1, 2, 3));
+
+        let overscan = Overscan {
+            left: 8,
+            ..Overscan::NTSC
+        };
+        assert_eq!(overscan.visible_size(), (248, 224));
+
+        // Filtered frames are twice as wide
+        let mut output = Frame::new(0, 0);
+        overscan.crop(&frame, &mut output);
+        assert_eq!((output.width(), output.height()), (496, 224));
+        assert_eq!(&output.data[0..3], &[1, 2, 3]);
+
+        let (width, height) = Overscan::NTSC.display_size(PixelAspect::Ntsc);
+        assert_eq!((width.round(), height), (293.0, 224.0));
+    }
+}
@@ -1,16 +1,24 @@
 use std::sync::{Arc, Mutex};
 
+use serde::{Deserialize, Serialize};
+use serde_big_array::BigArray;
+
 mod addr;
 mod control;
+pub mod events;
 pub mod frame;
 mod mask;
 pub mod palette;
 mod scroll;
-mod sprite;
+pub mod sprite;
 mod status;
 
+use crate::cdl::CodeDataLogger;
 use crate::mapper::Mapper;
+use crate::ppu::events::{PpuEventKind, PpuEventLogger};
 use crate::ppu::frame::Frame;
+use crate::ppu::palette::Palette;
+use crate::region::Region;
 use crate::rom::{Mirroring, ROM};
 use addr::AddrRegister;
 use control::ControlRegister;
@@ -41,25 +49,35 @@ enum Scanline {
 }
 
 impl Scanline {
-    pub fn from(scanline: usize) -> Self {
+    pub fn from(scanline: usize, region: Region) -> Self {
         match scanline {
-            261 => Scanline::PreRender,
             0..=239 => Scanline::Visible,
-            240 => Scanline::PostRender,
-            241..=260 => Scanline::VBlank,
+            _ if scanline == region.pre_render_scanline() => Scanline::PreRender,
+            _ if scanline < region.vblank_scanline() => Scanline::PostRender,
+            _ if scanline < region.pre_render_scanline() => Scanline::VBlank,
 
             _ => panic!("Invalid scanline!"),
         }
     }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct PPU {
+    #[serde(skip)]
     mapper: Option<Arc<Mutex<Box<dyn Mapper + Send>>>>,
-    mirroring: Option<Mirroring>,
-
+    // The mapper's mirroring, so nametable fetches don't lock it, see `update_mirroring`
+    #[serde(skip)]
+    mirroring: Mirroring,
+    #[serde(skip)]
+    code_data_logger: Option<Arc<Mutex<CodeDataLogger>>>,
+    #[serde(skip)]
+    event_logger: Option<PpuEventLogger>,
+
+    #[serde(with = "BigArray")]
     pub vram: [u8; 2 * NAMETABLE_SIZE],
     pub palette_table: [u8; PALETTE_SIZE],
 
+    #[serde(with = "BigArray")]
     pub oam_data: [u8; OAM_SIZE],
     secondary_oam_data: Vec<Option<Sprite>>,
     pub oam_addr: u8,
@@ -67,6 +85,7 @@ pub struct PPU {
     pub scanline: usize,
     pub cycle: usize,
     frame_count: usize,
+    total_cycles: u64,
 
     pub mask: MaskRegister,
     pub addr: AddrRegister,
@@ -106,14 +125,21 @@ pub struct PPU {
     // Odd/even frame state
     odd_frame: bool,
 
+    region: Region,
+
+    #[serde(skip)]
     pub frame: Frame,
+    #[serde(skip)]
+    palette: Palette,
 }
 
 impl PPU {
     pub fn new() -> Self {
         PPU {
             mapper: None,
-            mirroring: None,
+            mirroring: Mirroring::None,
+            code_data_logger: None,
+            event_logger: None,
             vram: [0; 2 * NAMETABLE_SIZE],
             oam_data: [0xFF; OAM_SIZE],
             oam_addr: 0,
@@ -149,6 +175,7 @@ impl PPU {
             scanline: 0,
             cycle: 0,
             frame_count: 0,
+            total_cycles: 0,
 
             nmi_interrupt: None,
             suppress_vbl: false,
@@ -157,13 +184,90 @@ impl PPU {
 
             odd_frame: false,
 
+            region: Region::Ntsc,
+
             frame: Frame::new(256, 240),
+            palette: Palette::default(),
         }
     }
 
     pub fn load_rom(&mut self, rom: &ROM) {
-        self.mirroring = Some(rom.mirroring.clone());
         self.mapper = Some(Arc::clone(&rom.mapper));
+        self.update_mirroring();
+    }
+
+    // Takes over the cartridge connection, frame buffer and palette, which
+    // aren't part of a save state, from the PPU this one is replacing
+    pub fn reattach(&mut self, previous: &mut PPU) {
+        self.mapper = previous.mapper.take();
+        self.update_mirroring();
+        self.code_data_logger = previous.code_data_logger.take();
+        self.event_logger = previous.event_logger.take();
+        self.frame = std::mem::take(&mut previous.frame);
+        self.palette = std::mem::take(&mut previous.palette);
+    }
+
+    pub fn set_code_data_logger(&mut self, logger: Option<Arc<Mutex<CodeDataLogger>>>) {
+        self.code_data_logger = logger;
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    // The PPUMASK emphasis bits as red, green, blue from bit 0. The PAL
+    // and Dendy PPUs swap red and green.
+    fn emphasis(&self) -> u8 {
+        let bits = self.mask.bits() >> 5;
+
+        match self.region {
+            Region::Ntsc => bits,
+            Region::Pal | Region::Dendy => {
+                (bits & 0b100) | (bits & 0b001) << 1 | (bits & 0b010) >> 1
+            }
+        }
+    }
+
+    pub fn set_event_logger(&mut self, logger: Option<PpuEventLogger>) -> Option<PpuEventLogger> {
+        std::mem::replace(&mut self.event_logger, logger)
+    }
+
+    pub fn event_logger(&self) -> Option<&PpuEventLogger> {
+        self.event_logger.as_ref()
+    }
+
+    pub(crate) fn event_logger_mut(&mut self) -> Option<&mut PpuEventLogger> {
+        self.event_logger.as_mut()
+    }
+
+    // Logs an event caused by the running instruction, see `PpuEventLogger::record`
+    pub(crate) fn record_event(&mut self, kind: PpuEventKind) {
+        if let Some(logger) = &mut self.event_logger {
+            logger.record(self.scanline, self.cycle, kind);
+        }
+    }
+
+    // Logs an event at the current dot while ticking
+    fn record_placed_event(&mut self, kind: PpuEventKind) {
+        if let Some(logger) = &mut self.event_logger {
+            logger.record_placed(self.scanline, self.cycle, kind);
+        }
+    }
+
+    fn raise_nmi(&mut self) {
+        self.nmi_interrupt = Some(1);
     }
 
     fn increment_vram_addr(&mut self) {
@@ -373,7 +477,7 @@ impl PPU {
     }
 
     fn load_sprites(&mut self) {
-        if self.mask.show_sprites() {
+        if self.mask.rendering_enabled() {
             let next_scanline = (self.scanline + 1) as u16;
 
             for (i, sprite) in self.secondary_oam_data.iter().enumerate() {
@@ -418,6 +522,17 @@ impl PPU {
                     self.sprite_shifter_pattern_hi[i] = address_hi;
                 }
             }
+
+            // Unused sprite slots still fetch tile $FF, which keeps the A12
+            // line toggling once per scanline the way mappers expect
+            for _ in self.secondary_oam_data.len()..8 {
+                let pattern_table = if self.ctrl.sprite_size() == 16 {
+                    0x1000
+                } else {
+                    self.ctrl.sprt_pattern_addr()
+                };
+                self.read_pattern(pattern_table, 0xFF, 0);
+            }
         }
     }
 
@@ -465,6 +580,10 @@ impl PPU {
 
             // https://www.nesdev.org/wiki/PPU_OAM#Sprite_zero_hits
             if self.mask.rendering_enabled() && self.cycle != 255 {
+                if is_sprite_0 && !self.status.contains(StatusRegister::SPRITE_ZERO_HIT) {
+                    self.record_placed_event(PpuEventKind::SpriteZeroHit);
+                }
+
                 self.status.set_sprite_zero_hit(is_sprite_0)
             }
         }
@@ -475,9 +594,11 @@ impl PPU {
             color &= &0x30
         }
 
-        let rgb = palette::SYSTEM_PALETTE[color as usize];
+        let index = color as u16 | (self.emphasis() as u16) << 6;
+        let rgb = self.palette.color(index);
 
-        self.frame.set_pixel(self.cycle - 1, self.scanline, rgb);
+        self.frame
+            .set_indexed_pixel(self.cycle - 1, self.scanline, index, rgb);
     }
 
     fn render_background(&self) -> (u8, u8) {
@@ -557,17 +678,23 @@ impl PPU {
         self.mem_read(palette_addr)
     }
 
+    // Has to be called whenever the mapper may have switched its mirroring
+    pub fn update_mirroring(&mut self) {
+        self.mirroring = match self.mapper {
+            Some(ref mapper) => mapper.lock().unwrap().mirroring(),
+            None => Mirroring::None,
+        };
+    }
+
     fn mirror_nametable(&self, addr: u16) -> u16 {
         let mirrored_vram = addr & 0x0FFF;
         let nametable_index = mirrored_vram / 0x400;
         match (&self.mirroring, nametable_index) {
-            (Some(Mirroring::Vertical), 2) | (Some(Mirroring::Vertical), 3) => {
-                mirrored_vram - 0x800
-            }
-            (Some(Mirroring::Horizontal), 1) | (Some(Mirroring::Horizontal), 2) => {
-                mirrored_vram - 0x400
-            }
-            (Some(Mirroring::Horizontal), 3) => mirrored_vram - 0x800,
+            (Mirroring::Vertical, 2) | (Mirroring::Vertical, 3) => mirrored_vram - 0x800,
+            (Mirroring::Horizontal, 1) | (Mirroring::Horizontal, 2) => mirrored_vram - 0x400,
+            (Mirroring::Horizontal, 3) => mirrored_vram - 0x800,
+            (Mirroring::SingleScreenA, _) => mirrored_vram & 0x3FF,
+            (Mirroring::SingleScreenB, _) => 0x400 | (mirrored_vram & 0x3FF),
             _ => mirrored_vram,
         }
     }
@@ -581,16 +708,62 @@ impl PPU {
         }
     }
 
+    // Lets the mapper observe the PPU address bus
+    fn observe_address(&self, address: u16) {
+        if let Some(ref mapper) = self.mapper {
+            mapper
+                .lock()
+                .unwrap()
+                .ppu_address(address, self.total_cycles);
+        }
+    }
+
+    // Marks pattern table bytes for the code/data logger
+    fn log_chr(&self, address: u16, flags: u8) {
+        if address >= 0x2000 {
+            return;
+        }
+
+        if let (Some(logger), Some(mapper)) = (&self.code_data_logger, &self.mapper) {
+            if let Some(offset) = mapper.lock().unwrap().chr_rom_offset(address) {
+                logger.lock().unwrap().log_chr(offset, flags);
+            }
+        }
+    }
+
+    // Reads made while rendering
     fn mem_read(&self, address: u16) -> u8 {
+        self.log_chr(address, CodeDataLogger::RENDERED);
+        self.vram_read(address)
+    }
+
+    fn vram_read(&self, address: u16) -> u8 {
+        if address < 0x3f00 {
+            self.observe_address(address);
+        }
+
+        self.peek_vram(address)
+    }
+
+    // Reads the PPU address space without the mapper seeing the access
+    pub fn peek_vram(&self, address: u16) -> u8 {
+        let address = address & 0x3fff;
+
         match address {
-            0..=0x1fff => self.mapper.as_ref().unwrap().lock().unwrap().read(address),
+            0..=0x1fff => match self.mapper {
+                Some(ref mapper) => mapper.lock().unwrap().peek(address),
+                None => 0,
+            },
             0x2000..=0x3eff => self.vram[self.mirror_nametable(address) as usize],
-            0x3f00..=0x3fff => self.palette_table[self.mirror_palette(address)],
-            _ => panic!("unexpected access to mirrored space {}", address),
+            _ => self.palette_table[self.mirror_palette(address)],
         }
     }
 
     fn mem_write(&mut self, address: u16, data: u8) {
+        if address < 0x3f00 {
+            self.observe_address(address);
+        }
+
         match address {
             0..=0x1fff => self
                 .mapper
@@ -624,7 +797,7 @@ impl PPU {
                 // w:                  <- 0
                 self.w = false;
 
-                if self.scanline == 241 && self.cycle == 0 {
+                if self.scanline == self.region.vblank_scanline() && self.cycle == 0 {
                     self.suppress_vbl = true;
                 }
 
@@ -640,10 +813,12 @@ impl PPU {
                 // TODO: Verify behavior
                 if address >= 0x3F00 {
                     self.vram_buffer = self.vram[self.mirror_nametable(address) as usize];
-                    self.mem_read(address)
+                    self.vram_read(address)
                 } else {
+                    self.log_chr(address, CodeDataLogger::READ);
+
                     let result = self.vram_buffer;
-                    self.vram_buffer = self.mem_read(address);
+                    self.vram_buffer = self.vram_read(address);
                     result
                 }
             }
@@ -658,6 +833,42 @@ impl PPU {
         }
     }
 
+    // Writes to the PPU address space without the mapper seeing the access
+    pub fn poke_vram(&mut self, address: u16, data: u8) {
+        let address = address & 0x3fff;
+
+        match address {
+            0..=0x1fff => {
+                if let Some(ref mapper) = self.mapper {
+                    mapper.lock().unwrap().write(address, data);
+                }
+            }
+            0x2000..=0x3eff => self.vram[self.mirror_nametable(address) as usize] = data,
+            _ => self.palette_table[self.mirror_palette(address)] = data,
+        }
+    }
+
+    // What `cpu_read` would return, without clearing flags, latches or
+    // moving the VRAM address
+    pub fn peek(&self, address: u16) -> u8 {
+        match address {
+            PPUCTRL | PPUMASK | OAMADDR | PPUSCROLL | PPUADDR => self.data_bus,
+            PPUSTATUS => (self.status.bits() & 0xE0) | (self.data_bus & 0x1f),
+            OAMDATA => self.oam_data[self.oam_addr as usize],
+            PPUDATA => {
+                let address = self.v & 0x3fff;
+
+                if address >= 0x3F00 {
+                    self.peek_vram(address)
+                } else {
+                    self.vram_buffer
+                }
+            }
+            0x2008..=0x3FFF => self.peek(address & 0x2007),
+            _ => 0,
+        }
+    }
+
     pub fn cpu_write(&mut self, address: u16, data: u8) {
         self.data_bus = data;
 
@@ -672,7 +883,8 @@ impl PPU {
                 let updated_nmi_status = self.ctrl.generate_vblank_nmi();
 
                 if !before_nmi_status && updated_nmi_status && self.status.is_in_vblank() {
-                    self.nmi_interrupt = Some(1)
+                    self.raise_nmi();
+                    self.record_event(PpuEventKind::Nmi);
                 }
             }
             PPUMASK => {
@@ -730,7 +942,9 @@ impl PPU {
 
                 self.mem_write(address, data);
 
-                if self.mask.show_background() && (self.scanline < 240 || self.scanline == 261) {
+                if self.mask.show_background()
+                    && (self.scanline < 240 || self.scanline == self.region.pre_render_scanline())
+                {
                     self.increment_x();
                     self.increment_y();
                 } else {
@@ -748,10 +962,11 @@ impl PPU {
     }
 
     fn handle_vblank_scanline(&mut self) {
-        if self.scanline == 241 && self.cycle == 1 && !self.suppress_vbl {
+        if self.scanline == self.region.vblank_scanline() && self.cycle == 1 && !self.suppress_vbl {
             self.status.set_vblank_status(true);
             if self.ctrl.generate_vblank_nmi() {
-                self.nmi_interrupt = Some(1);
+                self.raise_nmi();
+                self.record_placed_event(PpuEventKind::Nmi);
             }
         }
     }
@@ -773,6 +988,8 @@ impl PPU {
                 self.update_shift_registers();
                 self.sprite_evaluation();
             }
+            // Sprite pattern fetches happen during dots 257-320
+            260 => self.load_sprites(),
             321..=336 => {
                 self.update_shift_registers();
                 self.fetch_internal_registers()
@@ -781,7 +998,6 @@ impl PPU {
                 // Unused NT fetches
                 self.fetch_nametable_byte();
             }
-            340 => self.load_sprites(),
             _ => (),
         }
     }
@@ -806,7 +1022,15 @@ impl PPU {
                     self.increment_y();
                 }
             }
-            257 => self.transfer_x(),
+            257 => {
+                self.transfer_x();
+
+                // No sprites are ever rendered on scanline 0
+                if self.mask.rendering_enabled() {
+                    self.secondary_oam_data.clear();
+                }
+            }
+            260 => self.load_sprites(),
             280..=304 => self.transfer_y(),
             321..=336 => {
                 self.update_shift_registers();
@@ -821,7 +1045,10 @@ impl PPU {
                 // The "Skipped on BG+odd" tick is implemented by jumping directly
                 // from (339, 261) to (0, 0), meaning the last tick of the last NT
                 // fetch takes place at (0, 0) on odd frames replacing the idle tick
-                if self.mask.rendering_enabled() && self.odd_frame {
+                if self.mask.rendering_enabled()
+                    && self.odd_frame
+                    && self.region.skips_odd_frame_dot()
+                {
                     self.cycle = 340;
                 }
             }
@@ -834,7 +1061,7 @@ impl PPU {
     }
 
     pub fn tick(&mut self) -> bool {
-        match Scanline::from(self.scanline) {
+        match Scanline::from(self.scanline, self.region) {
             Scanline::PreRender => self.handle_pre_render_scanline(),
             Scanline::Visible => self.handle_visible_scanline(),
             Scanline::PostRender => { /* Idle. Do nothing */ }
@@ -842,15 +1069,20 @@ impl PPU {
         }
 
         self.cycle += 1;
+        self.total_cycles += 1;
         if self.cycle > 340 {
             self.cycle = 0;
             self.scanline += 1;
 
-            if self.scanline > 261 {
+            if self.scanline > self.region.pre_render_scanline() {
                 self.scanline = 0;
                 self.frame_count += 1;
                 self.odd_frame = !self.odd_frame;
 
+                if let Some(logger) = &mut self.event_logger {
+                    logger.end_frame();
+                }
+
                 return true;
             }
         }
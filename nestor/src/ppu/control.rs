@@ -1,3 +1,5 @@
+use serde::{Deserialize, Serialize};
+
 bitflags! {
 
     // 7  bit  0
@@ -16,6 +18,7 @@ bitflags! {
     // |          (0: read backdrop from EXT pins; 1: output color on EXT pins)
     // +--------- Generate an NMI at the start of the
     //            vertical blanking interval (0: off; 1: on)
+    #[derive(Serialize, Deserialize)]
     pub struct ControlRegister: u8 {
         const NAMETABLE1              = 0b00000001;
         const NAMETABLE2              = 0b00000010;
@@ -0,0 +1,154 @@
+use crate::bus::IrqSource;
+use crate::region::Region;
+
+const DOTS_PER_SCANLINE: usize = 341;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PpuEventKind {
+    // A CPU write to $2000-$2007 (mirrors folded) or $4014
+    RegisterWrite { address: u16, value: u8 },
+    Nmi,
+    SpriteZeroHit,
+    // The IRQ line went from released to asserted
+    Irq(IrqSource),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PpuEvent {
+    pub scanline: usize,
+    pub cycle: usize,
+    pub kind: PpuEventKind,
+    // The instruction that was running
+    pub program_counter: u16,
+}
+
+// Records what happened to the PPU over a frame and where in the frame it
+// happened, for timing scroll splits and other raster effects
+#[derive(Default)]
+pub struct PpuEventLogger {
+    // The frame being rendered
+    current: Vec<PpuEvent>,
+    // The last complete frame
+    last_frame: Vec<PpuEvent>,
+    // Register writes are recorded while the PPU still sits where the
+    // instruction started, `place_writes` moves them to where they landed
+    unplaced: usize,
+    program_counter: u16,
+    irq: bool,
+}
+
+impl PpuEventLogger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Events of the last complete frame, in order
+    pub fn events(&self) -> &[PpuEvent] {
+        &self.last_frame
+    }
+
+    // Events of the frame being rendered so far
+    pub fn current_events(&self) -> &[PpuEvent] {
+        &self.current
+    }
+
+    pub(crate) fn set_instruction(&mut self, program_counter: u16) {
+        self.program_counter = program_counter;
+    }
+
+    // For events caused by the running instruction, `place_writes` moves
+    // them to where they landed
+    pub(crate) fn record(&mut self, scanline: usize, cycle: usize, kind: PpuEventKind) {
+        self.current.push(PpuEvent {
+            scanline,
+            cycle,
+            kind,
+            program_counter: self.program_counter,
+        });
+    }
+
+    // For events the PPU raises on its own while it's ticked, already at
+    // their dot. Writes still waiting are placed by then.
+    pub(crate) fn record_placed(&mut self, scanline: usize, cycle: usize, kind: PpuEventKind) {
+        self.record(scanline, cycle, kind);
+        self.unplaced = self.current.len();
+    }
+
+    // Writes happen on the last cycle of an instruction, `cycles` long
+    pub(crate) fn place_writes(&mut self, cycles: u8, region: Region) {
+        let (dots, per_cycles) = region.ppu_dots_per_cpu_cycle();
+        let delay = (cycles.max(1) as usize - 1) * dots as usize / per_cycles as usize;
+        let frame_end = region.scanlines_per_frame() * DOTS_PER_SCANLINE - 1;
+
+        for event in &mut self.current[self.unplaced..] {
+            let dot = event.scanline * DOTS_PER_SCANLINE + event.cycle + delay;
+            // Kept in this frame even when the write lands on the next one
+            let dot = dot.min(frame_end);
+
+            event.scanline = dot / DOTS_PER_SCANLINE;
+            event.cycle = dot % DOTS_PER_SCANLINE;
+        }
+
+        self.unplaced = self.current.len();
+    }
+
+    // Records the IRQ line's rising edges
+    pub(crate) fn update_irq(&mut self, scanline: usize, cycle: usize, sources: IrqSource) {
+        let asserted = !sources.is_empty();
+
+        if asserted && !self.irq {
+            self.record_placed(scanline, cycle, PpuEventKind::Irq(sources));
+        }
+
+        self.irq = asserted;
+    }
+
+    pub(crate) fn end_frame(&mut self) {
+        self.last_frame = std::mem::take(&mut self.current);
+        self.unplaced = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_writes_land_on_the_last_cycle() {
+        let mut logger = PpuEventLogger::new();
+        logger.set_instruction(0x8000);
+
+        // STA $2005 started at dot 338 and took 4 cycles
+        logger.record(
+            10,
+            338,
+            PpuEventKind::RegisterWrite {
+                address: 0x2005,
+                value: 0,
+            },
+        );
+        logger.place_writes(4, Region::Ntsc);
+
+        let event = logger.current_events()[0];
+        assert_eq!((event.scanline, event.cycle), (11, 6));
+        assert_eq!(event.program_counter, 0x8000);
+
+        // Only once
+        logger.place_writes(4, Region::Ntsc);
+        assert_eq!(logger.current_events()[0].cycle, 6);
+
+        // Raised by the PPU, stays where it was recorded
+        logger.record_placed(12, 1, PpuEventKind::Nmi);
+        logger.place_writes(4, Region::Ntsc);
+        let event = logger.current_events()[1];
+        assert_eq!((event.scanline, event.cycle), (12, 1));
+
+        logger.update_irq(20, 0, IrqSource::MAPPER);
+        logger.update_irq(20, 3, IrqSource::MAPPER);
+        assert_eq!(logger.current_events().len(), 3);
+
+        logger.end_frame();
+        assert_eq!(logger.events().len(), 3);
+        assert!(logger.current_events().is_empty());
+    }
+}
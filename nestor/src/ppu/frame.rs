@@ -1,7 +1,27 @@
-#[derive(Debug, Clone)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    Rgba,
+    Bgra,
+}
+
+// A frame as it's shown on screen, see `NES::display_frame`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DisplayFrame {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct Frame {
     width: usize,
     pub data: Vec<u8>,
+    // The 9-bit color each pixel came from: the palette entry in the low 6
+    // bits, the PPUMASK emphasis bits (red, green, blue) above them.
+    // Only the screen fills it in, the viewers leave it at 0.
+    indices: Vec<u16>,
 }
 
 impl Frame {
@@ -9,9 +29,18 @@ impl Frame {
         Frame {
             width,
             data: vec![0; width * height * 3],
+            indices: vec![0; width * height],
         }
     }
 
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.data.len().checked_div(self.width * 3).unwrap_or(0)
+    }
+
     pub fn set_pixel(&mut self, x: usize, y: usize, rgb: (u8, u8, u8)) {
         let base = y * 3 * self.width + x * 3;
         if base + 2 < self.data.len() {
@@ -21,15 +50,84 @@ impl Frame {
         }
     }
 
-    pub fn to_rgba(&self) -> Vec<u8> {
-        let mut buffer: Vec<u8> = vec![];
-        for color in self.data.chunks_exact(3) {
-            buffer.push(color[0]);
-            buffer.push(color[1]);
-            buffer.push(color[2]);
-            buffer.push(255);
+    pub fn set_indexed_pixel(&mut self, x: usize, y: usize, index: u16, rgb: (u8, u8, u8)) {
+        self.set_pixel(x, y, rgb);
+
+        if let Some(pixel) = self.indices.get_mut(y * self.width + x) {
+            *pixel = index;
         }
+    }
+
+    pub fn indices(&self) -> &[u16] {
+        &self.indices
+    }
+
+    // Fills a 4 bytes per pixel buffer without allocating, alpha is 255.
+    // Stops at whichever of the frame and the buffer ends first.
+    pub fn copy_to(&self, buffer: &mut [u8], format: PixelFormat) {
+        let pixels = self.data.chunks_exact(3).zip(buffer.chunks_exact_mut(4));
+
+        match format {
+            PixelFormat::Rgba => {
+                for (rgb, pixel) in pixels {
+                    pixel.copy_from_slice(&[rgb[0], rgb[1], rgb[2], 255]);
+                }
+            }
+            PixelFormat::Bgra => {
+                for (rgb, pixel) in pixels {
+                    pixel.copy_from_slice(&[rgb[2], rgb[1], rgb[0], 255]);
+                }
+            }
+        }
+    }
+
+    // Copies a rectangle, clipped to the frame, into `output`, which is
+    // replaced when its size doesn't match
+    pub fn crop(&self, x: usize, y: usize, width: usize, height: usize, output: &mut Frame) {
+        let x = x.min(self.width);
+        let y = y.min(self.height());
+        let width = width.min(self.width - x);
+        let height = height.min(self.height() - y);
+
+        if output.width != width || output.indices.len() != width * height {
+            *output = Frame::new(width, height);
+        }
+
+        for row in 0..height {
+            let source = (y + row) * self.width + x;
+            let target = row * width;
+
+            output.data[target * 3..(target + width) * 3]
+                .copy_from_slice(&self.data[source * 3..(source + width) * 3]);
+            output.indices[target..target + width]
+                .copy_from_slice(&self.indices[source..source + width]);
+        }
+    }
+
+    pub fn to_rgba(&self) -> Vec<u8> {
+        let mut buffer = vec![0; self.data.len() / 3 * 4];
+        self.copy_to(&mut buffer, PixelFormat::Rgba);
 
         buffer
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_copy_to() {
+        let mut frame = Frame::new(2, 1);
+        frame.set_indexed_pixel(1, 0, 0x16 | 0x40, (1, 2, 3));
+        assert_eq!(frame.indices(), &[0, 0x56]);
+
+        assert_eq!(frame.to_rgba(), vec![0, 0, 0, 255, 1, 2, 3, 255]);
+
+        // A short buffer only gets the first pixels
+        let mut buffer = [0; 4];
+        frame.set_pixel(0, 0, (4, 5, 6));
+        frame.copy_to(&mut buffer, PixelFormat::Bgra);
+        assert_eq!(buffer, [6, 5, 4, 255]);
+    }
+}
@@ -1,3 +1,5 @@
+use serde::{Deserialize, Serialize};
+
 bitflags! {
 
     // 7  bit  0
@@ -12,6 +14,7 @@ bitflags! {
     // ||+------- Emphasize red
     // |+-------- Emphasize green
     // +--------- Emphasize blue
+    #[derive(Serialize, Deserialize)]
     pub struct MaskRegister: u8 {
         const GREYSCALE               = 0b00000001;
         const LEFTMOST_8PXL_BACKGROUND  = 0b00000010;
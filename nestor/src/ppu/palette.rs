@@ -1,3 +1,6 @@
+use std::fs;
+use std::path::Path;
+
 pub static SYSTEM_PALETTE: [(u8, u8, u8); 64] = [
     (0x75, 0x75, 0x75),
     (0x27, 0x1B, 0x8F),
@@ -64,3 +67,263 @@ pub static SYSTEM_PALETTE: [(u8, u8, u8); 64] = [
     (0x00, 0x00, 0x00),
     (0x00, 0x00, 0x00),
 ];
+
+// 64 colors times the 8 combinations of the PPUMASK emphasis bits
+pub const PALETTE_ENTRIES: usize = 512;
+
+// How much an emphasis bit dims the parts of the signal it affects
+const ATTENUATION: f32 = 0.746;
+
+// The RGB PPU (2C03) palette, one octal digit per channel
+// https://www.nesdev.org/wiki/PPU_palettes#2C03_and_2C05
+const RGB_PALETTE: [u16; 64] = [
+    0o333, 0o014, 0o006, 0o326, 0o403, 0o503, 0o510, 0o420, 0o320, 0o120, 0o031, 0o040, 0o022,
+    0o000, 0o000, 0o000, 0o555, 0o036, 0o027, 0o407, 0o507, 0o704, 0o700, 0o630, 0o430, 0o140,
+    0o040, 0o053, 0o044, 0o000, 0o000, 0o000, 0o777, 0o357, 0o447, 0o637, 0o707, 0o737, 0o740,
+    0o750, 0o660, 0o360, 0o070, 0o276, 0o077, 0o000, 0o000, 0o000, 0o777, 0o567, 0o657, 0o757,
+    0o747, 0o755, 0o764, 0o772, 0o773, 0o572, 0o473, 0o276, 0o467, 0o000, 0o000, 0o000,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuiltinPalette {
+    // SYSTEM_PALETTE
+    Nestor,
+    // Decoded from a model of the composite signal the 2C02 generates
+    Composite,
+    // The Vs. System and PlayChoice-10 RGB PPU
+    Rgb,
+}
+
+impl BuiltinPalette {
+    pub const ALL: [BuiltinPalette; 3] = [
+        BuiltinPalette::Nestor,
+        BuiltinPalette::Composite,
+        BuiltinPalette::Rgb,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BuiltinPalette::Nestor => "Nestor",
+            BuiltinPalette::Composite => "Composite",
+            BuiltinPalette::Rgb => "RGB",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|palette| palette.name().eq_ignore_ascii_case(name))
+    }
+}
+
+// Maps a 9-bit color, the 6-bit palette entry plus the emphasis bits of
+// PPUMASK above it, to RGB
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    colors: Vec<(u8, u8, u8)>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::builtin(BuiltinPalette::Nestor)
+    }
+}
+
+impl Palette {
+    pub fn builtin(palette: BuiltinPalette) -> Self {
+        match palette {
+            BuiltinPalette::Nestor => Palette::from_colors(&SYSTEM_PALETTE),
+            BuiltinPalette::Composite => Palette {
+                colors: (0..PALETTE_ENTRIES).map(composite_color).collect(),
+            },
+            BuiltinPalette::Rgb => Palette {
+                colors: (0..PALETTE_ENTRIES).map(rgb_color).collect(),
+            },
+        }
+    }
+
+    // Emphasized colors are derived by dimming the other two channels
+    pub fn from_colors(colors: &[(u8, u8, u8); 64]) -> Self {
+        let mut palette = Vec::with_capacity(PALETTE_ENTRIES);
+
+        for emphasis in 0..8 {
+            for &(r, g, b) in colors {
+                let mut channels = [r as f32, g as f32, b as f32];
+
+                for bit in 0..3 {
+                    if emphasis & (1 << bit) != 0 {
+                        for (other, channel) in channels.iter_mut().enumerate() {
+                            if other != bit {
+                                *channel *= ATTENUATION;
+                            }
+                        }
+                    }
+                }
+
+                let [r, g, b] = channels.map(|channel| channel.round() as u8);
+                palette.push((r, g, b));
+            }
+        }
+
+        Palette { colors: palette }
+    }
+
+    // A .pal file holds RGB triplets, either the 64 base colors or all 512
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        let colors: Vec<(u8, u8, u8)> = data
+            .chunks_exact(3)
+            .map(|rgb| (rgb[0], rgb[1], rgb[2]))
+            .collect();
+
+        match data.len() {
+            192 => Ok(Palette::from_colors(colors.as_slice().try_into().unwrap())),
+            1536 => Ok(Palette { colors }),
+            length => Err(format!(
+                "Invalid palette size: {length} bytes, expected 192 or 1536"
+            )),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let data = fs::read(path).map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+
+        Palette::from_bytes(&data)
+    }
+
+    pub fn color(&self, index: u16) -> (u8, u8, u8) {
+        self.colors[index as usize % PALETTE_ENTRIES]
+    }
+
+    // All 512 colors in .pal layout
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.colors
+            .iter()
+            .flat_map(|&(r, g, b)| [r, g, b])
+            .collect()
+    }
+}
+
+// Tuned so that $16 comes out close to the usual 2C02 red
+const HUE: f32 = 4.0;
+pub(crate) const SATURATION: f32 = 1.5;
+
+// The 2C02 generates a color as a square wave between two voltages, at
+// one of the 12 phases of the color carrier. This is its level at a
+// given phase, from 0 for black to 1 for white.
+// https://www.nesdev.org/wiki/NTSC_video
+pub(crate) fn signal_level(index: usize, phase: usize) -> f32 {
+    const LOW: [f32; 4] = [0.350, 0.518, 0.962, 1.550];
+    const HIGH: [f32; 4] = [1.094, 1.506, 1.962, 1.962];
+    const BLACK: f32 = 0.518;
+    const WHITE: f32 = 1.962;
+
+    let hue = index & 0x0F;
+    let level = (index >> 4) & 0x03;
+    let emphasis = index >> 6;
+
+    // Hue 0 is a flat high level, $D a flat low one and $E-$F are black
+    let (low, high) = match hue {
+        0x00 => (HIGH[level], HIGH[level]),
+        0x01..=0x0C => (LOW[level], HIGH[level]),
+        0x0D => (LOW[level], LOW[level]),
+        _ => (BLACK, BLACK),
+    };
+
+    let in_phase = |hue: usize| (hue + phase) % 12 < 6;
+
+    let mut signal = if in_phase(hue) { high } else { low };
+
+    // Red, green and blue emphasis dim the phases of hues $C, $4 and $8
+    if hue < 0x0E
+        && ((emphasis & 1 != 0 && in_phase(0x0C))
+            || (emphasis & 2 != 0 && in_phase(0x04))
+            || (emphasis & 4 != 0 && in_phase(0x08)))
+    {
+        signal *= ATTENUATION;
+    }
+
+    (signal - BLACK) / (WHITE - BLACK)
+}
+
+// Where the TV's color decoder samples I and Q, for a phase of the carrier
+pub(crate) fn carrier_angle(phase: usize) -> f32 {
+    std::f32::consts::PI * (phase as f32 + HUE) / 6.0
+}
+
+pub(crate) fn yiq_to_rgb(y: f32, i: f32, q: f32) -> (u8, u8, u8) {
+    let channel = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+
+    (
+        channel(y + 0.956 * i + 0.621 * q),
+        channel(y - 0.272 * i - 0.647 * q),
+        channel(y - 1.106 * i + 1.703 * q),
+    )
+}
+
+// A whole carrier cycle of a color, decoded as an NTSC TV would
+fn composite_color(index: usize) -> (u8, u8, u8) {
+    let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+
+    for phase in 0..12 {
+        let value = signal_level(index, phase);
+        let angle = carrier_angle(phase);
+
+        y += value;
+        i += value * angle.cos();
+        q += value * angle.sin();
+    }
+
+    yiq_to_rgb(y / 12.0, i * SATURATION / 12.0, q * SATURATION / 12.0)
+}
+
+// The RGB PPUs don't dim anything, emphasis turns a channel fully on
+fn rgb_color(index: usize) -> (u8, u8, u8) {
+    let color = RGB_PALETTE[index & 0x3F];
+    let emphasis = index >> 6;
+
+    let channel = |shift: u16, bit: usize| {
+        if emphasis & bit != 0 {
+            255
+        } else {
+            (((color >> shift) & 0x07) * 255 / 7) as u8
+        }
+    };
+
+    (channel(6, 1), channel(3, 2), channel(0, 4))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pal_files() {
+        let base = Palette::from_bytes(&[0x80; 192]).unwrap();
+        assert_eq!(base.color(0x00), (0x80, 0x80, 0x80));
+        // Red emphasis dims green and blue
+        assert_eq!(base.color(0x40), (0x80, 0x5F, 0x5F));
+        // All three dim everything
+        assert_eq!(base.color(0x1C0), (0x47, 0x47, 0x47));
+
+        let full = Palette::from_bytes(&base.to_bytes()).unwrap();
+        assert_eq!(full, base);
+
+        assert!(Palette::from_bytes(&[0; 100]).is_err());
+        assert_eq!(BuiltinPalette::from_name("rgb"), Some(BuiltinPalette::Rgb));
+    }
+
+    #[test]
+    fn test_builtin_palettes() {
+        let composite = Palette::builtin(BuiltinPalette::Composite);
+        // $0F is black, $30 white and $16 red
+        assert_eq!(composite.color(0x0F), (0, 0, 0));
+        assert_eq!(composite.color(0x30), (255, 255, 255));
+        let (r, g, b) = composite.color(0x16);
+        assert!(r > g && r > b);
+
+        let rgb = Palette::builtin(BuiltinPalette::Rgb);
+        assert_eq!(rgb.color(0x16), (255, 0, 0));
+        assert_eq!(rgb.color(0x0F | 0x80), (0, 255, 0));
+    }
+}
@@ -1,4 +1,6 @@
-#[derive(Clone)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ScrollRegister {
     pub scroll_x: u8,
     pub scroll_y: u8,
@@ -1,4 +1,6 @@
-#[derive(Default, Copy, Clone)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Copy, Clone, Serialize, Deserialize)]
 pub struct Sprite {
     pub tile: u8,
 
@@ -1,3 +1,5 @@
+use serde::{Deserialize, Serialize};
+
 bitflags! {
 
     // 7  bit  0
@@ -20,6 +22,7 @@ bitflags! {
     //            Set at dot 1 of line 241 (the line *after* the post-render
     //            line); cleared after reading $2002 and at dot 1 of the
     //            pre-render line.
+    #[derive(Serialize, Deserialize)]
     pub struct StatusRegister: u8 {
         const NOTUSED          = 0b00000001;
         const NOTUSED2         = 0b00000010;
@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::rom::Timing;
+
+// The console's video standard, which sets the clocks and the frame layout
+// https://www.nesdev.org/wiki/Cycle_reference_chart
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    // Famiclone timing: PAL's frame with NTSC's clock ratio
+    Dendy,
+}
+
+impl Region {
+    // Multi-region games run as NTSC
+    pub fn from_timing(timing: Timing) -> Self {
+        match timing {
+            Timing::Ntsc | Timing::MultiRegion => Region::Ntsc,
+            Timing::Pal => Region::Pal,
+            Timing::Dendy => Region::Dendy,
+        }
+    }
+
+    pub fn scanlines_per_frame(&self) -> usize {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    // Where the vblank flag is set and the NMI fires. Dendy keeps 51
+    // post-render scanlines so that vblank lasts as long as on NTSC.
+    pub fn vblank_scanline(&self) -> usize {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    pub fn pre_render_scanline(&self) -> usize {
+        self.scanlines_per_frame() - 1
+    }
+
+    // PPU dots per CPU cycle, as a fraction: 3 or 3.2 for PAL
+    pub fn ppu_dots_per_cpu_cycle(&self) -> (u8, u8) {
+        match self {
+            Region::Ntsc | Region::Dendy => (3, 1),
+            Region::Pal => (16, 5),
+        }
+    }
+
+    // Only the NTSC PPU drops a dot on odd frames when rendering
+    pub fn skips_odd_frame_dot(&self) -> bool {
+        *self == Region::Ntsc
+    }
+
+    // CPU cycles per second
+    pub fn cpu_clock_rate(&self) -> f64 {
+        match self {
+            Region::Ntsc => 1_789_773.0,
+            Region::Pal => 1_662_607.0,
+            Region::Dendy => 1_773_448.0,
+        }
+    }
+
+    pub fn frames_per_second(&self) -> f64 {
+        match self {
+            Region::Ntsc => 60.0988,
+            Region::Pal | Region::Dendy => 50.007,
+        }
+    }
+
+    pub fn frame_duration(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.frames_per_second())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cycles_per_frame() {
+        // PPU dots per frame over CPU cycles per second, in frames per second
+        for region in [Region::Ntsc, Region::Pal, Region::Dendy] {
+            let (dots, cycles) = region.ppu_dots_per_cpu_cycle();
+            let dots_per_frame = (region.scanlines_per_frame() * 341) as f64;
+            let fps = region.cpu_clock_rate() * dots as f64 / cycles as f64 / dots_per_frame;
+
+            assert!(
+                (fps - region.frames_per_second()).abs() < 0.01,
+                "{:?}",
+                region
+            );
+        }
+
+        assert_eq!(Region::from_timing(Timing::MultiRegion), Region::Ntsc);
+        assert_eq!(Region::from_timing(Timing::Dendy), Region::Dendy);
+    }
+}
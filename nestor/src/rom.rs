@@ -2,40 +2,106 @@ use std::fs;
 use std::path::Path;
 use std::sync::{Arc, Mutex};
 
+use serde::{Deserialize, Serialize};
+
 use crate::mapper::Mapper;
-use crate::mappers::{CNROM, NROM};
+use crate::mappers::{AxROM, ColorDreams, GxROM, UxROM, CNROM, MMC1, MMC3, NROM};
 
 const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
 const PRG_ROM_PAGE_SIZE: usize = 16384;
 const CHR_ROM_PAGE_SIZE: usize = 8192;
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub enum Mirroring {
     Vertical,
     Horizontal,
     FourScreen,
+    SingleScreenA,
+    SingleScreenB,
+    #[default]
     None,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HeaderFormat {
+    INes,
+    Nes20,
+}
+
+// CPU/PPU timing the cartridge was made for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    MultiRegion,
+    Dendy,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem,
+    Playchoice10,
+    // https://www.nesdev.org/wiki/NES_2.0#Extended_Console_Type
+    Extended(u8),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RomHeader {
+    pub format: HeaderFormat,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub mirroring: Mirroring,
+    pub has_trainer: bool,
+    pub has_battery: bool,
+
+    // Sizes in bytes
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+
+    pub timing: Timing,
+    pub console_type: ConsoleType,
+    pub misc_roms: u8,
+    // https://www.nesdev.org/wiki/NES_2.0#Default_Expansion_Device
+    pub default_expansion_device: u8,
+}
+
 pub struct ROM {
+    pub header: RomHeader,
     pub prg_rom: Vec<u8>,
     pub chr_rom: Vec<u8>,
     pub mapper: Arc<Mutex<Box<dyn Mapper + Send>>>,
-    pub mirroring: Mirroring,
 }
 
-fn parse_ines_header(raw: &[u8]) -> Result<(usize, usize, Mirroring, u8), String> {
-    if raw[0..4] != NES_TAG {
-        return Err("File is not in iNES file format".to_string());
+// https://www.nesdev.org/wiki/NES_2.0#PRG-ROM_Area
+// When the MSB nibble is $F the LSB byte is an exponent-multiplier notation
+fn nes2_rom_size(lsb: u8, msb: u8, page_size: usize) -> usize {
+    if msb == 0x0F {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0b11) as usize * 2 + 1;
+        2usize.saturating_pow(exponent).saturating_mul(multiplier)
+    } else {
+        ((msb as usize) << 8 | lsb as usize) * page_size
     }
+}
 
-    let ines_ver = (raw[7] >> 2) & 0b11;
-    if ines_ver != 0 {
-        return Err("NES2.0 format is not supported".to_string());
+// https://www.nesdev.org/wiki/NES_2.0#PRG-(NV)RAM/EEPROM
+fn nes2_ram_size(shift_count: u8) -> usize {
+    if shift_count == 0 {
+        0
+    } else {
+        64 << shift_count
     }
+}
 
-    let prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
-    let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+fn parse_ines_header(raw: &[u8]) -> Result<RomHeader, String> {
+    if raw.len() < 16 || raw[0..4] != NES_TAG {
+        return Err("File is not in iNES file format".to_string());
+    }
 
     let four_screen = raw[6] & 0b1000 != 0;
     let vertical_mirroring = raw[6] & 0b1 != 0;
@@ -44,51 +110,356 @@ fn parse_ines_header(raw: &[u8]) -> Result<(usize, usize, Mirroring, u8), String
         (false, true) => Mirroring::Vertical,
         (false, false) => Mirroring::Horizontal,
     };
+    let has_battery = raw[6] & 0b10 != 0;
+    let has_trainer = raw[6] & 0b100 != 0;
 
-    let mapper_idx = (raw[7] & 0b1111_0000) | (raw[6] >> 4);
+    let console_type = match raw[7] & 0b11 {
+        0 => ConsoleType::Nes,
+        1 => ConsoleType::VsSystem,
+        2 => ConsoleType::Playchoice10,
+        _ => ConsoleType::Extended(raw[13] & 0x0F),
+    };
 
-    Ok((prg_rom_size, chr_rom_size, mirroring, mapper_idx))
+    let ines_ver = (raw[7] >> 2) & 0b11;
+
+    if ines_ver == 2 {
+        let mapper = (raw[8] as u16 & 0x0F) << 8 | (raw[7] & 0xF0) as u16 | (raw[6] >> 4) as u16;
+
+        let timing = match raw[12] & 0b11 {
+            0 => Timing::Ntsc,
+            1 => Timing::Pal,
+            2 => Timing::MultiRegion,
+            _ => Timing::Dendy,
+        };
+
+        return Ok(RomHeader {
+            format: HeaderFormat::Nes20,
+            mapper,
+            submapper: raw[8] >> 4,
+            mirroring,
+            has_trainer,
+            has_battery,
+            prg_rom_size: nes2_rom_size(raw[4], raw[9] & 0x0F, PRG_ROM_PAGE_SIZE),
+            chr_rom_size: nes2_rom_size(raw[5], raw[9] >> 4, CHR_ROM_PAGE_SIZE),
+            prg_ram_size: nes2_ram_size(raw[10] & 0x0F),
+            prg_nvram_size: nes2_ram_size(raw[10] >> 4),
+            chr_ram_size: nes2_ram_size(raw[11] & 0x0F),
+            chr_nvram_size: nes2_ram_size(raw[11] >> 4),
+            timing,
+            console_type,
+            misc_roms: raw[14] & 0b11,
+            default_expansion_device: raw[15] & 0x3F,
+        });
+    }
+
+    // Old dumping tools wrote signatures such as "DiskDude!" over bytes 7-15,
+    // in which case the upper mapper nibble can't be trusted
+    let dirty_header = ines_ver != 0 || raw[12..16].iter().any(|&byte| byte != 0);
+    let mapper_hi = if dirty_header { 0 } else { raw[7] & 0xF0 };
+    let mapper = (mapper_hi | (raw[6] >> 4)) as u16;
+
+    let prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
+    let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+
+    // A value of 0 infers 8 KB for compatibility. Like in NES 2.0 headers,
+    // battery-backed RAM is reported as non-volatile.
+    let ram_size = raw[8].max(1) as usize * 0x2000;
+    let (prg_ram_size, prg_nvram_size) = if has_battery {
+        (0, ram_size)
+    } else {
+        (ram_size, 0)
+    };
+    let chr_ram_size = if chr_rom_size == 0 { 0x2000 } else { 0 };
+
+    let timing = if !dirty_header && raw[9] & 0x01 != 0 {
+        Timing::Pal
+    } else {
+        Timing::Ntsc
+    };
+
+    Ok(RomHeader {
+        format: HeaderFormat::INes,
+        mapper,
+        submapper: 0,
+        mirroring,
+        has_trainer,
+        has_battery,
+        prg_rom_size,
+        chr_rom_size,
+        prg_ram_size,
+        prg_nvram_size,
+        chr_ram_size,
+        chr_nvram_size: 0,
+        timing,
+        console_type: if dirty_header {
+            ConsoleType::Nes
+        } else {
+            console_type
+        },
+        misc_roms: 0,
+        default_expansion_device: 0,
+    })
 }
 
-fn create_mapper(mapper_idx: u8, prg_rom: &[u8], chr_rom: &[u8]) -> Result<Arc<Mutex<Box<dyn Mapper + Send>>>, String> {
+fn create_mapper(
+    header: &RomHeader,
+    prg_rom: &[u8],
+    chr_rom: &[u8],
+) -> Result<Arc<Mutex<Box<dyn Mapper + Send>>>, String> {
+    let mapper_idx = header.mapper;
+    let mirroring = &header.mirroring;
+    let prg_ram_size = header.prg_ram_size + header.prg_nvram_size;
+
+    let bus_conflicts = has_bus_conflicts(header);
+
     let mapper: Mutex<Box<dyn Mapper + Send>> = match mapper_idx {
-        0 => Mutex::new(Box::new(NROM::new(prg_rom, chr_rom))),
-        3 => Mutex::new(Box::new(CNROM::new(prg_rom, chr_rom))),
+        0 => Mutex::new(Box::new(NROM::new(
+            prg_rom,
+            chr_rom,
+            prg_ram_size,
+            mirroring.clone(),
+        ))),
+        1 => Mutex::new(Box::new(MMC1::new(prg_rom, chr_rom, prg_ram_size))),
+        2 => Mutex::new(Box::new(UxROM::new(
+            prg_rom,
+            chr_rom,
+            mirroring.clone(),
+            bus_conflicts,
+        ))),
+        3 => Mutex::new(Box::new(CNROM::new(
+            prg_rom,
+            chr_rom,
+            mirroring.clone(),
+            bus_conflicts,
+        ))),
+        4 => Mutex::new(Box::new(MMC3::new(
+            prg_rom,
+            chr_rom,
+            prg_ram_size,
+            mirroring.clone(),
+        ))),
+        7 => Mutex::new(Box::new(AxROM::new(prg_rom, chr_rom, bus_conflicts))),
+        11 => Mutex::new(Box::new(ColorDreams::new(
+            prg_rom,
+            chr_rom,
+            mirroring.clone(),
+            bus_conflicts,
+        ))),
+        66 => Mutex::new(Box::new(GxROM::new(
+            prg_rom,
+            chr_rom,
+            mirroring.clone(),
+            bus_conflicts,
+        ))),
         _ => return Err(format!("Mapper not implement yet {mapper_idx}")),
     };
 
     Ok(Arc::new(mapper))
 }
 
+// Whether writes to the board's registers are ANDed with the ROM byte
+fn has_bus_conflicts(header: &RomHeader) -> bool {
+    match header.mapper {
+        // https://www.nesdev.org/wiki/NES_2.0_submappers#002,_003,_007:_UxROM,_CNROM,_AxROM
+        // Submapper 2 marks boards with AND-type bus conflicts. Plain iNES
+        // headers can't tell, and well-behaved games avoid them anyway.
+        2 | 3 | 7 => header.submapper == 2,
+        // Color Dreams and GxROM boards always have them
+        11 | 66 => true,
+        _ => false,
+    }
+}
+
 impl ROM {
     pub fn from_bytes(raw: &[u8]) -> Result<ROM, String> {
-        let (prg_rom_size, chr_rom_size, mirroring, mapper_idx) = parse_ines_header(raw)?;
+        let header = parse_ines_header(raw)?;
+
+        // Every mapper needs at least one PRG bank
+        if header.prg_rom_size == 0 {
+            return Err("Invalid ROM size".to_string());
+        }
 
-        let skip_trainer = raw[6] & 0b100 != 0;
+        let prg_rom_start: usize = 16 + if header.has_trainer { 512 } else { 0 };
+        // Exponent-multiplier sizes can get close to usize::MAX
+        let chr_rom_start = prg_rom_start
+            .checked_add(header.prg_rom_size)
+            .ok_or("Invalid ROM size")?;
+        let chr_rom_end = chr_rom_start
+            .checked_add(header.chr_rom_size)
+            .ok_or("Invalid ROM size")?;
 
-        let prg_rom_start = 16 + if skip_trainer { 512 } else { 0 };
-        let chr_rom_start = prg_rom_start + prg_rom_size;
+        if raw.len() < chr_rom_end {
+            return Err("ROM file is smaller than its header declares".to_string());
+        }
 
-        let prg_rom = raw[prg_rom_start..(prg_rom_start + prg_rom_size)].to_vec();
-        let mut chr_rom = raw[chr_rom_start..(chr_rom_start + chr_rom_size)].to_vec();
+        let prg_rom = raw[prg_rom_start..chr_rom_start].to_vec();
+        let mut chr_rom = raw[chr_rom_start..chr_rom_end].to_vec();
 
-        if chr_rom_size == 0 {
-            chr_rom = vec![0; 8192];
+        if header.chr_rom_size == 0 {
+            let chr_ram_size = header.chr_ram_size + header.chr_nvram_size;
+            chr_rom = vec![0; chr_ram_size.max(CHR_ROM_PAGE_SIZE)];
         }
 
-        let mapper = create_mapper(mapper_idx, &prg_rom, &chr_rom)?;
+        let mapper = create_mapper(&header, &prg_rom, &chr_rom)?;
 
         Ok(ROM {
+            header,
             prg_rom,
             chr_rom,
             mapper,
-            mirroring,
         })
     }
 
     pub fn from_path<P: AsRef<Path>>(path: P) -> Result<ROM, String> {
-        let game_code = fs::read(path).expect("Should have been able to read the game");
+        let game_code = fs::read(path).map_err(|e| format!("Failed to read the ROM: {e}"))?;
 
         ROM::from_bytes(&game_code)
     }
+
+    // CRC32 of the PRG and CHR data, the usual way of identifying a dump
+    pub fn crc32(&self) -> u32 {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&self.prg_rom);
+        hasher.update(&self.chr_rom);
+        hasher.finalize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_header(bytes: [u8; 12]) -> [u8; 16] {
+        let mut header = [0; 16];
+        header[0..4].copy_from_slice(&NES_TAG);
+        header[4..16].copy_from_slice(&bytes);
+        header
+    }
+
+    #[test]
+    fn test_ines_header() {
+        let raw = test_header([2, 1, 0x11, 0x10, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let header = parse_ines_header(&raw).unwrap();
+
+        assert_eq!(header.format, HeaderFormat::INes);
+        assert_eq!(header.mapper, 0x11);
+        assert_eq!(header.mirroring, Mirroring::Vertical);
+        assert_eq!(header.prg_rom_size, 2 * PRG_ROM_PAGE_SIZE);
+        assert_eq!(header.chr_rom_size, CHR_ROM_PAGE_SIZE);
+        assert_eq!(header.prg_ram_size, 0x2000);
+        assert_eq!(header.timing, Timing::Ntsc);
+    }
+
+    #[test]
+    fn test_ines_battery() {
+        let raw = test_header([1, 1, 0b10, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let header = parse_ines_header(&raw).unwrap();
+
+        assert!(header.has_battery);
+        assert_eq!(header.prg_ram_size, 0);
+        assert_eq!(header.prg_nvram_size, 0x2000);
+    }
+
+    #[test]
+    fn test_ines_dirty_header_ignores_upper_mapper_nibble() {
+        let mut raw = test_header([2, 1, 0x40, 0x44, 0, 0, 0, 0, 0, 0, 0, 0]);
+        raw[7..16].copy_from_slice(b"DiskDude!");
+        let header = parse_ines_header(&raw).unwrap();
+
+        assert_eq!(header.format, HeaderFormat::INes);
+        assert_eq!(header.mapper, 4);
+    }
+
+    #[test]
+    fn test_nes2_header() {
+        let raw = test_header([
+            0x02, // PRG-ROM LSB
+            0x01, // CHR-ROM LSB
+            0x42, // Mapper D3..D0, battery
+            0x18, // Mapper D7..D4, NES 2.0 identifier
+            0x21, // Submapper 2, mapper D11..D8
+            0x10, // CHR-ROM MSB 1, PRG-ROM MSB 0
+            0x07, // PRG-RAM 8 KB
+            0x70, // CHR-NVRAM 8 KB
+            0x01, // PAL
+            0x00, 0x01, // One misc ROM
+            0x01, // Standard controllers
+        ]);
+        let header = parse_ines_header(&raw).unwrap();
+
+        assert_eq!(header.format, HeaderFormat::Nes20);
+        assert_eq!(header.mapper, 0x114);
+        assert_eq!(header.submapper, 2);
+        assert_eq!(header.prg_rom_size, 2 * PRG_ROM_PAGE_SIZE);
+        assert_eq!(header.chr_rom_size, 0x101 * CHR_ROM_PAGE_SIZE);
+        assert_eq!(header.prg_ram_size, 0x2000);
+        assert_eq!(header.prg_nvram_size, 0);
+        assert_eq!(header.chr_ram_size, 0);
+        assert_eq!(header.chr_nvram_size, 0x2000);
+        assert_eq!(header.timing, Timing::Pal);
+        assert_eq!(header.console_type, ConsoleType::Nes);
+        assert_eq!(header.misc_roms, 1);
+        assert_eq!(header.default_expansion_device, 1);
+    }
+
+    #[test]
+    fn test_nes2_exponent_rom_size() {
+        // 2^5 * (1 * 2 + 1)
+        assert_eq!(nes2_rom_size(0b0001_0101, 0x0F, PRG_ROM_PAGE_SIZE), 96);
+    }
+
+    #[test]
+    fn test_nes2_extended_console_type() {
+        let raw = test_header([1, 0, 0, 0x0B, 0, 0, 0, 0, 0, 0x03, 0, 0]);
+        let header = parse_ines_header(&raw).unwrap();
+
+        assert_eq!(header.console_type, ConsoleType::Extended(3));
+    }
+
+    #[test]
+    fn test_rom_from_nes2_bytes() {
+        let mut raw = test_header([1, 0, 0x00, 0x08, 0, 0, 0, 0x09, 0, 0, 0, 0]).to_vec();
+        raw.extend(vec![0; PRG_ROM_PAGE_SIZE]);
+
+        let rom = ROM::from_bytes(&raw).unwrap();
+
+        assert_eq!(rom.prg_rom.len(), PRG_ROM_PAGE_SIZE);
+        // CHR-RAM sized from the header
+        assert_eq!(rom.chr_rom.len(), 0x8000);
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        let header = |mapper, submapper| RomHeader {
+            mapper,
+            submapper,
+            ..parse_ines_header(&test_header([1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])).unwrap()
+        };
+
+        assert!(!has_bus_conflicts(&header(2, 0)));
+        assert!(has_bus_conflicts(&header(2, 2)));
+        assert!(has_bus_conflicts(&header(7, 2)));
+        assert!(has_bus_conflicts(&header(11, 0)));
+        assert!(has_bus_conflicts(&header(66, 0)));
+        assert!(!has_bus_conflicts(&header(1, 2)));
+    }
+
+    #[test]
+    fn test_invalid_rom_size() {
+        // 2^63 * 7 bytes of PRG-ROM
+        let raw = test_header([0xFF, 0, 0, 0x08, 0, 0x0F, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(ROM::from_bytes(&raw).err().unwrap(), "Invalid ROM size");
+
+        let mut raw = test_header([0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]).to_vec();
+        raw.extend(vec![0; CHR_ROM_PAGE_SIZE]);
+        assert_eq!(ROM::from_bytes(&raw).err().unwrap(), "Invalid ROM size");
+    }
+
+    #[test]
+    fn test_truncated_rom() {
+        let mut raw = test_header([2, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]).to_vec();
+        raw.extend(vec![0; PRG_ROM_PAGE_SIZE]);
+
+        assert!(ROM::from_bytes(&raw).is_err());
+    }
 }
@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::bus::Bus;
+
+// ld65 output offsets count the iNES header
+const INES_HEADER_SIZE: usize = 16;
+// FCEUX name lists split the PRG-ROM in 16 KB banks
+const NL_BANK_SIZE: usize = 0x4000;
+
+// Labels for addresses, loaded from ca65/ld65 .dbg files or FCEUX .nl name lists
+#[derive(Default)]
+pub struct Symbols {
+    // By CPU address: RAM, registers, PRG-RAM and anything not tied to a bank
+    addresses: HashMap<u16, String>,
+    // By PRG-ROM offset, so banked code gets the right name
+    prg: HashMap<usize, String>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty() && self.prg.is_empty()
+    }
+
+    pub fn add_label(&mut self, address: u16, name: &str) {
+        self.addresses.insert(address, name.to_string());
+    }
+
+    pub fn add_prg_label(&mut self, offset: usize, name: &str) {
+        self.prg.insert(offset, name.to_string());
+    }
+
+    // Label for the PRG-ROM byte at `prg_offset`, if any, otherwise for `address`
+    pub fn label(&self, address: u16, prg_offset: Option<usize>) -> Option<&str> {
+        prg_offset
+            .and_then(|offset| self.prg.get(&offset))
+            .or_else(|| self.addresses.get(&address))
+            .map(String::as_str)
+    }
+
+    // Resolves `address` with the cartridge's current banking
+    pub(crate) fn label_at(&self, bus: &Bus, address: u16) -> Option<&str> {
+        if self.is_empty() {
+            return None;
+        }
+
+        self.label(address, bus.prg_rom_offset(address))
+    }
+
+    // One entry per line, `$ADDR#name#comment` or `$ADDR/SIZE#name#comment`
+    // for arrays. `bank` is the 16 KB PRG bank the file describes, from its
+    // name (game.nes.2.nl), or None for game.nes.ram.nl.
+    pub fn parse_nl(&mut self, text: &str, bank: Option<usize>) -> Result<(), String> {
+        for (number, line) in text.lines().enumerate() {
+            // Multi-line comments continue on lines starting with a backslash
+            let Some(entry) = line.strip_prefix('$') else {
+                continue;
+            };
+
+            let mut fields = entry.splitn(3, '#');
+            let location = fields.next().unwrap_or_default();
+            let name = fields.next().unwrap_or_default().trim();
+
+            if name.is_empty() {
+                continue;
+            }
+
+            let (address, size) = match location.split_once('/') {
+                Some((address, size)) => (address, size),
+                None => (location, "1"),
+            };
+
+            let invalid = || format!("Invalid name list entry on line {}", number + 1);
+            let address = u16::from_str_radix(address.trim(), 16).map_err(|_| invalid())?;
+            let size = u16::from_str_radix(size.trim(), 16).map_err(|_| invalid())?;
+
+            for i in 0..size.max(1) {
+                let name = if i == 0 {
+                    name.to_string()
+                } else {
+                    format!("{}+{}", name, i)
+                };
+                let address = address.wrapping_add(i);
+
+                match bank {
+                    Some(bank) if address >= 0x8000 => {
+                        let offset = bank * NL_BANK_SIZE + (address as usize & (NL_BANK_SIZE - 1));
+                        self.add_prg_label(offset, &name);
+                    }
+                    _ => self.add_label(address, &name),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Labels from the `seg` and `sym` lines of an ld65 --dbgfile. Segments
+    // written to the ROM carry an output offset, which gives the PRG offset.
+    pub fn parse_dbg(&mut self, text: &str) -> Result<(), String> {
+        // Segment id to its start address and offset in the output file
+        let mut segments: HashMap<&str, (usize, Option<usize>)> = HashMap::new();
+        let mut symbols = vec![];
+
+        for (number, line) in text.lines().enumerate() {
+            let Some((kind, rest)) = line.split_once('\t') else {
+                continue;
+            };
+
+            let fields = dbg_fields(rest);
+            let invalid = || format!("Invalid debug info on line {}", number + 1);
+
+            match kind {
+                "seg" => {
+                    let id = fields.get("id").ok_or_else(invalid)?;
+                    let start = fields
+                        .get("start")
+                        .and_then(|value| parse_dbg_number(value))
+                        .ok_or_else(invalid)?;
+                    let output_offset = fields.get("ooffs").and_then(|v| parse_dbg_number(v));
+
+                    segments.insert(id, (start, output_offset));
+                }
+                "sym" => symbols.push((number, fields)),
+                _ => {}
+            }
+        }
+
+        for (number, fields) in symbols {
+            // Equates and imports aren't addresses
+            if fields.get("type") != Some(&"lab") {
+                continue;
+            }
+
+            let invalid = || format!("Invalid debug info on line {}", number + 1);
+            let name = fields.get("name").ok_or_else(invalid)?;
+            let value = fields
+                .get("val")
+                .and_then(|value| parse_dbg_number(value))
+                .ok_or_else(invalid)?;
+
+            let segment = fields.get("seg").and_then(|id| segments.get(id));
+
+            match segment {
+                Some((start, Some(output_offset)))
+                    if value >= 0x8000 && *output_offset >= INES_HEADER_SIZE =>
+                {
+                    let offset = output_offset - INES_HEADER_SIZE + value.wrapping_sub(*start);
+                    self.add_prg_label(offset, name);
+                }
+                _ if value <= 0xFFFF => self.add_label(value as u16, name),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    // Loads a .dbg file, or a .nl file whose bank comes from its name
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("dbg") => self.parse_dbg(&text),
+            Some("nl") => {
+                // game.nes.ram.nl or game.nes.<bank in hex>.nl
+                let bank = path
+                    .file_stem()
+                    .and_then(|stem| Path::new(stem).extension())
+                    .and_then(|bank| usize::from_str_radix(&bank.to_string_lossy(), 16).ok());
+
+                self.parse_nl(&text, bank)
+            }
+            _ => Err(format!("Unknown symbol file {}", path.display())),
+        }
+    }
+
+    // Everything found next to the ROM: game.dbg and game.nes.*.nl
+    pub fn load_for_rom<P: AsRef<Path>>(rom_path: P) -> Result<Self, String> {
+        let rom_path = rom_path.as_ref();
+        let mut symbols = Symbols::new();
+
+        let dbg = rom_path.with_extension("dbg");
+        if dbg.exists() {
+            symbols.load(dbg)?;
+        }
+
+        let (Some(directory), Some(rom_name)) = (rom_path.parent(), rom_path.file_name()) else {
+            return Ok(symbols);
+        };
+        let prefix = format!("{}.", rom_name.to_string_lossy());
+
+        let entries = fs::read_dir(if directory.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            directory
+        })
+        .map_err(|e| format!("Failed to read {}: {e}", directory.display()))?;
+
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+
+            if name.starts_with(&prefix) && name.ends_with(".nl") {
+                symbols.load(entry.path())?;
+            }
+        }
+
+        Ok(symbols)
+    }
+}
+
+// key=value pairs separated by commas, values may be quoted
+fn dbg_fields(text: &str) -> HashMap<&str, &str> {
+    let mut fields = HashMap::new();
+    let mut in_quotes = false;
+    let mut start = 0;
+
+    for (i, c) in text
+        .char_indices()
+        .chain(std::iter::once((text.len(), ',')))
+    {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => {
+                if let Some((key, value)) = text[start..i].split_once('=') {
+                    fields.insert(key.trim(), value.trim().trim_matches('"'));
+                }
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    fields
+}
+
+fn parse_dbg_number(value: &str) -> Option<usize> {
+    match value.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_nl() {
+        let mut symbols = Symbols::new();
+        symbols
+            .parse_nl(
+                "$0300#player_x#\n$0200/3#oam#Sprite\n\\buffer\n$C000##",
+                None,
+            )
+            .unwrap();
+        symbols
+            .parse_nl("$8000#reset#Entry point\n$A010#irq#", Some(2))
+            .unwrap();
+
+        assert_eq!(symbols.label(0x0300, None), Some("player_x"));
+        assert_eq!(symbols.label(0x0202, None), Some("oam+2"));
+        assert_eq!(symbols.label(0xC000, None), None);
+
+        // Bank 2 starts at PRG offset $8000
+        assert_eq!(symbols.label(0x8000, Some(0x8000)), Some("reset"));
+        assert_eq!(symbols.label(0xA010, Some(0xA010)), Some("irq"));
+        assert_eq!(symbols.label(0x8000, Some(0x0000)), None);
+
+        assert!(symbols.parse_nl("$XYZ#broken#", None).is_err());
+    }
+
+    #[test]
+    fn test_parse_dbg() {
+        let dbg = "version\tmajor=2,minor=0\n\
+            seg\tid=0,name=\"ZEROPAGE\",start=0x000000,size=0x0010,addrsize=zeropage,type=rw\n\
+            seg\tid=1,name=\"CODE\",start=0x00C000,size=0x0100,addrsize=absolute,type=ro,oname=\"game.nes\",ooffs=16400\n\
+            sym\tid=0,name=\"frame_count\",addrsize=zeropage,scope=0,def=1,val=0x02,seg=0,type=lab\n\
+            sym\tid=1,name=\"reset\",addrsize=absolute,scope=0,def=2,val=0xC010,seg=1,type=lab\n\
+            sym\tid=2,name=\"PPUCTRL\",addrsize=absolute,scope=0,def=3,val=0x2000,type=equ\n";
+
+        let mut symbols = Symbols::new();
+        symbols.parse_dbg(dbg).unwrap();
+
+        assert_eq!(symbols.label(0x0002, None), Some("frame_count"));
+        // CODE is the second 16 KB bank of the PRG-ROM
+        assert_eq!(symbols.label(0xC010, Some(0x4010)), Some("reset"));
+        assert_eq!(symbols.label(0xC010, Some(0x0010)), None);
+        assert_eq!(symbols.label(0x2000, None), None);
+    }
+}
@@ -1,23 +1,129 @@
-#![allow(dead_code)]
-use crate::bus::CpuBus;
+use crate::bus::Bus;
 use crate::bus::Memory;
+use crate::cdl::CodeDataLogger;
 use crate::cpu::AddressingMode;
 use crate::cpu::CPU;
+use crate::disassembler;
 use crate::opcodes;
 use crate::opcodes::Mnemonic;
-use std::collections::HashMap;
+use crate::symbols::Symbols;
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
 
-lazy_static! {
-    pub static ref NON_READABLE_ADDR: Vec<u16> =
-        vec!(0x2001, 0x2002, 0x2003, 0x2004, 0x2005, 0x2006, 0x2007, 0x4016, 0x4017);
+// Optional columns after the registers. With both enabled the output
+// matches Nintendulator and the full nestest.log:
+// C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TraceFormat {
+    // Scanline and dot the PPU is at
+    pub ppu_position: bool,
+    // CPU cycles since power on
+    pub cycles: bool,
+    // How the code/data logger had seen the opcode byte before this ran:
+    // CDL:C- code, CDL:-D data, CDL:-- never, e.g. running for the first time
+    pub code_data: bool,
 }
 
-pub fn trace<B: Memory + CpuBus>(cpu: &mut CPU<B>) -> String {
-    let opscodes: &HashMap<u8, &'static opcodes::OpCode> = &opcodes::OPCODES_MAP;
+impl TraceFormat {
+    pub const NINTENDULATOR: TraceFormat = TraceFormat {
+        ppu_position: true,
+        cycles: true,
+        code_data: false,
+    };
+}
+
+enum TraceOutput {
+    File(BufWriter<File>),
+    // Keeps only the most recent lines
+    RingBuffer(VecDeque<String>, usize),
+}
+
+// Logs every instruction the NES executes, see `NES::start_trace`
+pub struct TraceLogger {
+    format: TraceFormat,
+    output: TraceOutput,
+}
+
+impl TraceLogger {
+    pub fn to_file<P: AsRef<Path>>(path: P, format: TraceFormat) -> Result<Self, String> {
+        let path = path.as_ref();
+        let file =
+            File::create(path).map_err(|e| format!("Failed to create {}: {e}", path.display()))?;
+
+        Ok(TraceLogger {
+            format,
+            output: TraceOutput::File(BufWriter::new(file)),
+        })
+    }
+
+    // Keeps at least the last line
+    pub fn ring_buffer(capacity: usize, format: TraceFormat) -> Self {
+        let capacity = capacity.max(1);
+
+        TraceLogger {
+            format,
+            output: TraceOutput::RingBuffer(VecDeque::with_capacity(capacity), capacity),
+        }
+    }
+
+    // The buffered lines, oldest first. Empty when logging to a file.
+    pub fn lines(&self) -> impl Iterator<Item = &String> {
+        let lines = match &self.output {
+            TraceOutput::RingBuffer(lines, _) => Some(lines.iter()),
+            TraceOutput::File(_) => None,
+        };
+
+        lines.into_iter().flatten()
+    }
+
+    pub fn flush(&mut self) -> Result<(), String> {
+        match &mut self.output {
+            TraceOutput::File(writer) => writer.flush().map_err(|e| e.to_string()),
+            TraceOutput::RingBuffer(..) => Ok(()),
+        }
+    }
 
-    let non_readable_addr = &NON_READABLE_ADDR;
+    pub(crate) fn log(&mut self, cpu: &CPU<Bus>, symbols: &Symbols) {
+        let line = trace(cpu, self.format, symbols);
 
-    let code = cpu.bus.mem_read(cpu.program_counter);
+        match &mut self.output {
+            TraceOutput::File(writer) => {
+                // A trace isn't worth stopping the emulation for
+                let _ = writeln!(writer, "{}", line);
+            }
+            TraceOutput::RingBuffer(lines, capacity) => {
+                if lines.len() == *capacity {
+                    lines.pop_front();
+                }
+                lines.push_back(line);
+            }
+        }
+    }
+}
+
+// Nintendulator shows the PPU and APU/IO registers as FF, so they're left
+// out to stay comparable with its logs
+fn is_readable(address: u16) -> bool {
+    !(0x2000..=0x401F).contains(&address)
+}
+
+fn peek_u16(cpu: &CPU<Bus>, address: u16) -> u16 {
+    let lo = cpu.bus.peek(address);
+    let hi = cpu.bus.peek(address.wrapping_add(1));
+    (hi as u16) << 8 | (lo as u16)
+}
+
+// Formats the instruction at the current PC in the same way as nestest.log:
+// C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD
+// With symbols loaded, the labels of the PC and of the address the operand
+// refers to follow as a comment:
+// C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD ; reset: main
+pub fn trace(cpu: &CPU<Bus>, format: TraceFormat, symbols: &Symbols) -> String {
+    let opscodes: &HashMap<u8, &'static opcodes::OpCode> = &opcodes::OPCODES_MAP;
+
+    let code = cpu.bus.peek(cpu.program_counter);
     let ops = opscodes.get(&code).unwrap();
 
     let begin = cpu.program_counter;
@@ -29,12 +135,12 @@ pub fn trace<B: Memory + CpuBus>(cpu: &mut CPU<B>) -> String {
         | AddressingMode::NoneAddressing
         | AddressingMode::Accumulator => (0, 0),
         _ => {
-            let (addr, _) = cpu.get_address_by_addressing_mode(&ops.mode, begin + 1);
+            let (addr, _) = cpu.peek_address_by_addressing_mode(&ops.mode, begin + 1);
 
-            if !non_readable_addr.contains(&addr) {
-                (addr, cpu.bus.mem_read(addr))
+            if is_readable(addr) {
+                (addr, cpu.bus.peek(addr))
             } else {
-                (addr, 0)
+                (addr, 0xFF)
             }
         }
     };
@@ -45,7 +151,7 @@ pub fn trace<B: Memory + CpuBus>(cpu: &mut CPU<B>) -> String {
             _ => String::from(""),
         },
         2 => {
-            let address: u8 = cpu.bus.mem_read(begin + 1);
+            let address: u8 = cpu.bus.peek(begin + 1);
             // let value = cpu.mem_read(address));
             hex_dump.push(address);
 
@@ -88,23 +194,23 @@ pub fn trace<B: Memory + CpuBus>(cpu: &mut CPU<B>) -> String {
             }
         }
         3 => {
-            let address_lo = cpu.bus.mem_read(begin + 1);
-            let address_hi = cpu.bus.mem_read(begin + 2);
+            let address_lo = cpu.bus.peek(begin + 1);
+            let address_hi = cpu.bus.peek(begin + 2);
             hex_dump.push(address_lo);
             hex_dump.push(address_hi);
 
-            let address = cpu.bus.mem_read_u16(begin + 1);
+            let address = peek_u16(cpu, begin + 1);
 
             match ops.mode {
                 AddressingMode::NoneAddressing => {
                     if ops.code == 0x6c {
                         //jmp indirect
                         let jmp_addr = if address & 0x00FF == 0x00FF {
-                            let lo = cpu.bus.mem_read(address);
-                            let hi = cpu.bus.mem_read(address & 0xFF00);
+                            let lo = cpu.bus.peek(address);
+                            let hi = cpu.bus.peek(address & 0xFF00);
                             (hi as u16) << 8 | (lo as u16)
                         } else {
-                            cpu.bus.mem_read_u16(address)
+                            peek_u16(cpu, address)
                         };
 
                         // let jmp_addr = cpu.mem_read_u16(address);
@@ -117,7 +223,7 @@ pub fn trace<B: Memory + CpuBus>(cpu: &mut CPU<B>) -> String {
                     Mnemonic::JMP | Mnemonic::JSR => {
                         format!("${:04X}", address)
                     }
-                    _ => format!("${:04x} = #${:02x}", mem_addr, stored_value),
+                    _ => format!("${:04x} = {:02x}", mem_addr, stored_value),
                 },
                 AddressingMode::AbsoluteX => format!(
                     "${:04x},X @ {:04x} = {:02x}",
@@ -148,21 +254,157 @@ pub fn trace<B: Memory + CpuBus>(cpu: &mut CPU<B>) -> String {
         .collect::<Vec<String>>()
         .join(" ");
     let asm_str = format!(
-        "${:04x}: {:8}{: >4} {}",
+        "{:04x}  {:8} {: >4} {}",
         begin, hex_str, ops._mnemonic_name, tmp
     )
     .trim()
-    .to_ascii_uppercase()
     .to_string();
 
-    format!(
-        "c{:<12}A:{:02X} X:{:02X} Y:{:02X} S:{:02X}  {}",
-        cpu.cycles,
+    let mut line = format!(
+        "{:47} A:{:02x} X:{:02x} Y:{:02x} P:{:02x} SP:{:02x}",
+        asm_str,
         cpu.register_a,
         cpu.register_x,
         cpu.register_y,
+        cpu.processor_status,
         cpu.stack_pointer,
-        asm_str,
-        // cpu.bus.borrow().ppu.scanline
-    )
+    );
+
+    if format.ppu_position {
+        line.push_str(&format!(
+            " PPU:{:3},{:3}",
+            cpu.bus.ppu.scanline, cpu.bus.ppu.cycle
+        ));
+    }
+
+    if format.cycles {
+        line.push_str(&format!(" CYC:{}", cpu.cycles));
+    }
+
+    if format.code_data {
+        let flags = cpu.bus.code_data_flags(cpu.program_counter).unwrap_or(0);
+        let code = if flags & CodeDataLogger::CODE != 0 {
+            'C'
+        } else {
+            '-'
+        };
+        let data = if flags & CodeDataLogger::DATA != 0 {
+            'D'
+        } else {
+            '-'
+        };
+
+        line.push_str(&format!(" CDL:{}{}", code, data));
+    }
+
+    let mut line = line.to_ascii_uppercase();
+
+    if !symbols.is_empty() {
+        let mut labels = vec![];
+
+        if let Some(label) = symbols.label_at(&cpu.bus, begin) {
+            labels.push(format!("{}:", label));
+        }
+
+        let has_memory_operand = !matches!(
+            ops.mode,
+            AddressingMode::Immediate
+                | AddressingMode::NoneAddressing
+                | AddressingMode::Accumulator
+        );
+        let operand_address = disassembler::decode(&mut |a| cpu.bus.peek(a), begin)
+            .target
+            .or(has_memory_operand.then_some(mem_addr));
+
+        if let Some(label) = operand_address.and_then(|a| symbols.label_at(&cpu.bus, a)) {
+            labels.push(label.to_string());
+        }
+
+        if !labels.is_empty() {
+            line.push_str(" ; ");
+            line.push_str(&labels.join(" "));
+        }
+    }
+
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::ROM;
+
+    // NROM running LDA #$10; JMP $8000
+    fn test_cpu() -> CPU<Bus> {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut prg_rom = vec![0; 0x4000];
+        prg_rom[0..5].copy_from_slice(&[0xA9, 0x10, 0x4C, 0x00, 0x80]);
+        prg_rom[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        raw.extend(prg_rom);
+        raw.extend(vec![0; 0x2000]);
+
+        let rom = ROM::from_bytes(&raw).unwrap();
+        let mut bus = Bus::new();
+        bus.load_rom(&rom);
+
+        let mut cpu = CPU::new(bus);
+        cpu.reset();
+        cpu
+    }
+
+    #[test]
+    fn test_nintendulator_format() {
+        let mut cpu = test_cpu();
+        cpu.cycles = 7;
+        cpu.bus.ppu.cycle = 21;
+
+        assert_eq!(
+            trace(&cpu, TraceFormat::NINTENDULATOR, &Symbols::new()),
+            "8000  A9 10     LDA #$10                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7"
+        );
+    }
+
+    #[test]
+    fn test_labels() {
+        let mut cpu = test_cpu();
+        let mut symbols = Symbols::new();
+        symbols.parse_nl("$8000#main#", Some(0)).unwrap();
+
+        assert!(trace(&cpu, TraceFormat::default(), &symbols).ends_with("SP:FD ; main:"));
+
+        cpu.run();
+        assert!(
+            trace(&cpu, TraceFormat::default(), &symbols).starts_with("8002  4C 00 80  JMP $8000")
+        );
+        assert!(trace(&cpu, TraceFormat::default(), &symbols).ends_with("SP:FD ; main"));
+    }
+
+    #[test]
+    fn test_ring_buffer_keeps_latest_lines() {
+        let mut cpu = test_cpu();
+        let mut logger = TraceLogger::ring_buffer(2, TraceFormat::default());
+
+        for _ in 0..3 {
+            logger.log(&cpu, &Symbols::new());
+            cpu.run();
+        }
+
+        let lines: Vec<&String> = logger.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("8002  4C 00 80  JMP $8000"));
+        assert!(lines[1].starts_with("8000  A9 10     LDA #$10"));
+    }
+
+    #[test]
+    fn test_empty_ring_buffer() {
+        let mut cpu = test_cpu();
+        let mut logger = TraceLogger::ring_buffer(0, TraceFormat::default());
+
+        for _ in 0..3 {
+            logger.log(&cpu, &Symbols::new());
+            cpu.run();
+        }
+
+        assert_eq!(logger.lines().count(), 1);
+    }
 }
@@ -0,0 +1,84 @@
+mod common;
+
+// Every test needs the ROMs from tests/fixtures/fetch.sh, so they're all
+// ignored by default. The ones without a reason cover behaviour the emulator
+// doesn't get right yet, run them by name to check on progress.
+
+fn run(name: &str) {
+    let rom = common::load_fixture(name);
+
+    match common::run_blargg_test(rom) {
+        Ok(result) => assert_eq!(
+            result.code, 0,
+            "{name} failed with code {}:\n{}",
+            result.code, result.text
+        ),
+        Err(error) => panic!("{name}: {error}"),
+    }
+}
+
+#[test]
+#[ignore = "needs the test ROMs from tests/fixtures/fetch.sh"]
+fn instr_test_official_only() {
+    run("official_only.nes");
+}
+
+#[test]
+#[ignore]
+fn instr_test_all_instrs() {
+    run("all_instrs.nes");
+}
+
+#[test]
+#[ignore]
+fn instr_misc() {
+    run("instr_misc.nes");
+}
+
+#[test]
+#[ignore]
+fn instr_timing() {
+    run("instr_timing.nes");
+}
+
+#[test]
+#[ignore]
+fn cpu_interrupts() {
+    run("cpu_interrupts.nes");
+}
+
+#[test]
+#[ignore]
+fn ppu_vbl_nmi() {
+    run("ppu_vbl_nmi.nes");
+}
+
+#[test]
+#[ignore]
+fn apu_test() {
+    run("apu_test.nes");
+}
+
+#[test]
+#[ignore]
+fn mmc3_clocking() {
+    run("mmc3_clocking.nes");
+}
+
+#[test]
+#[ignore]
+fn mmc3_details() {
+    run("mmc3_details.nes");
+}
+
+#[test]
+#[ignore]
+fn mmc3_a12_clocking() {
+    run("mmc3_a12_clocking.nes");
+}
+
+#[test]
+#[ignore]
+fn mmc3_scanline_timing() {
+    run("mmc3_scanline_timing.nes");
+}
@@ -0,0 +1,103 @@
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+
+use nestor::{NES, ROM};
+
+// Test ROMs aren't part of the repository, `tests/fixtures/fetch.sh` downloads them.
+// The tests using them are ignored by default and run with `--ignored`.
+pub fn load_fixture(name: &str) -> ROM {
+    let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "fixtures", name]
+        .iter()
+        .collect();
+
+    assert!(
+        path.exists(),
+        "{name} not found. Run tests/fixtures/fetch.sh to download it."
+    );
+
+    ROM::from_path(path).unwrap()
+}
+
+pub fn run_frame(nes: &mut NES) {
+    while nes.emulate_frame().is_none() {}
+}
+
+// https://github.com/christopherpow/nes-test-roms/blob/master/instr_test-v5/readme.txt
+// blargg's test ROMs write their status to $6000 once $6001-$6003 hold the
+// DE B0 61 signature: $80 while running, $81 when the console has to be reset,
+// or the final result code, with a zero-terminated message from $6004.
+const STATUS_ADDRESS: u16 = 0x6000;
+const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const TEXT_ADDRESS: u16 = 0x6004;
+
+const STATUS_RUNNING: u8 = 0x80;
+const STATUS_RESET_REQUESTED: u8 = 0x81;
+
+// The reset has to come at least 100ms after the request
+const RESET_DELAY_FRAMES: usize = 10;
+const TIMEOUT_FRAMES: usize = 60 * 60;
+
+pub struct BlarggResult {
+    pub code: u8,
+    pub text: String,
+}
+
+pub fn run_blargg_test(rom: ROM) -> Result<BlarggResult, String> {
+    let mut nes = NES::new();
+    nes.insert_cartridge(rom);
+
+    let mut reset_at = None;
+
+    for frame in 0..TIMEOUT_FRAMES {
+        run_frame(&mut nes);
+
+        let signature = [
+            nes.peek_memory(0x6001),
+            nes.peek_memory(0x6002),
+            nes.peek_memory(0x6003),
+        ];
+        if signature != SIGNATURE {
+            continue;
+        }
+
+        match nes.peek_memory(STATUS_ADDRESS) {
+            STATUS_RUNNING => {}
+            STATUS_RESET_REQUESTED => match reset_at {
+                None => reset_at = Some(frame + RESET_DELAY_FRAMES),
+                Some(reset_frame) if frame >= reset_frame => {
+                    nes.start_emulation();
+                    reset_at = None;
+                }
+                _ => {}
+            },
+            code => {
+                return Ok(BlarggResult {
+                    code,
+                    text: read_text(&nes),
+                })
+            }
+        }
+    }
+
+    Err(format!(
+        "Timed out after {TIMEOUT_FRAMES} frames: {}",
+        read_text(&nes)
+    ))
+}
+
+fn read_text(nes: &NES) -> String {
+    let mut text = vec![];
+    let mut address = TEXT_ADDRESS;
+
+    while address < 0x8000 {
+        let byte = nes.peek_memory(address);
+        if byte == 0 {
+            break;
+        }
+        text.push(byte);
+        address += 1;
+    }
+
+    String::from_utf8_lossy(&text).trim().to_string()
+}
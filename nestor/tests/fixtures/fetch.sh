@@ -0,0 +1,32 @@
+#!/bin/sh
+# Downloads the test ROMs used by the integration tests into this directory.
+# The tests using them are ignored by default, run them with `cargo test -- --ignored <name>`.
+set -e
+
+cd "$(dirname "$0")"
+
+BASE_URL="https://raw.githubusercontent.com/christopherpow/nes-test-roms/master"
+
+fetch() {
+    name="$1"
+    path="$2"
+
+    if [ ! -f "$name" ]; then
+        echo "Fetching $name"
+        curl -sSfL -o "$name" "$BASE_URL/$path"
+    fi
+}
+
+fetch nestest.nes other/nestest.nes
+
+fetch official_only.nes instr_test-v5/official_only.nes
+fetch all_instrs.nes instr_test-v5/all_instrs.nes
+fetch instr_misc.nes instr_misc/instr_misc.nes
+fetch instr_timing.nes instr_timing/instr_timing.nes
+fetch cpu_interrupts.nes cpu_interrupts_v2/cpu_interrupts.nes
+fetch ppu_vbl_nmi.nes ppu_vbl_nmi/ppu_vbl_nmi.nes
+fetch apu_test.nes apu_test/apu_test.nes
+fetch mmc3_clocking.nes mmc3_test_2/rom_singles/1-clocking.nes
+fetch mmc3_details.nes mmc3_test_2/rom_singles/2-details.nes
+fetch mmc3_a12_clocking.nes mmc3_test_2/rom_singles/3-A12_clocking.nes
+fetch mmc3_scanline_timing.nes mmc3_test_2/rom_singles/4-scanline_timing.nes
@@ -0,0 +1,30 @@
+mod common;
+
+use nestor::NES;
+
+const NESTEST_LOG: &str = include_str!("../../nestest_no_cycle.log");
+
+// https://www.qmtpro.com/~nes/misc/nestest.txt
+// In automation mode nestest starts at $C000 and runs through every official
+// and unofficial instruction without needing a PPU, so the trace can be
+// compared to the reference log line by line.
+#[test]
+#[ignore = "needs the test ROMs from tests/fixtures/fetch.sh"]
+fn nestest() {
+    let rom = common::load_fixture("nestest.nes");
+
+    let mut nes = NES::new();
+    nes.insert_cartridge(rom);
+    nes.cpu.program_counter = 0xC000;
+
+    for (line, expected) in NESTEST_LOG.lines().enumerate() {
+        let actual = nes.trace();
+        assert_eq!(actual, expected, "Trace differs at line {}", line + 1);
+
+        nes.emulate_frame();
+    }
+
+    // Result codes for the official and unofficial opcode tests
+    assert_eq!(nes.peek_memory(0x0002), 0x00);
+    assert_eq!(nes.peek_memory(0x0003), 0x00);
+}